@@ -0,0 +1,212 @@
+// A small language server for trying out rilo's LSP client without a real
+// one. It treats every identifier as a symbol: hover names the word, the
+// definition is its first occurrence, references and rename cover every
+// occurrence and completion offers the words of the document. Lines with
+// `TODO` get a warning and the word `error` an error.
+//
+//     cargo build --example mock_lsp
+//     echo "lsp.rust = target/debug/examples/mock_lsp" >> .rilo/config
+
+#[path = "../src/editor_config/json.rs"]
+#[allow(dead_code)]
+mod json;
+
+use json::Json;
+use std::io::{self, BufRead, Write};
+
+fn main() {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut documents: Vec<(String, String)> = Vec::new();
+    while let Some(message) = read_message(&mut input) {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("").to_string();
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+        let uri = params.path(&["textDocument", "uri"]).and_then(Json::as_str).unwrap_or("").to_string();
+        match method.as_str() {
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let text = params.path(&["textDocument", "text"])
+                    .or_else(|| params.get("contentChanges")
+                        .and_then(Json::as_array)
+                        .and_then(|changes| changes.last())
+                        .and_then(|change| change.get("text")))
+                    .and_then(Json::as_str)
+                    .unwrap_or("")
+                    .to_string();
+                documents.retain(|(u, _)| *u != uri);
+                publish_diagnostics(&uri, &text);
+                documents.push((uri.clone(), text));
+            },
+            "textDocument/didClose" => documents.retain(|(u, _)| *u != uri),
+            "exit" => return,
+            _ => (),
+        }
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => continue,
+        };
+        let text = documents.iter().find(|(u, _)| *u == uri).map(|(_, t)| t.as_str()).unwrap_or("");
+        let line = params.path(&["position", "line"]).and_then(Json::as_u32).unwrap_or(0) as usize;
+        let character = params.path(&["position", "character"]).and_then(Json::as_u32).unwrap_or(0) as usize;
+        let word = word_at(text, line, character);
+        let result = match method.as_str() {
+            "initialize" => Json::object(vec![
+                ("capabilities", Json::object(vec![
+                    ("textDocumentSync", Json::from(1u32)),
+                    ("hoverProvider", Json::from(true)),
+                    ("definitionProvider", Json::from(true)),
+                    ("referencesProvider", Json::from(true)),
+                    ("renameProvider", Json::from(true)),
+                    ("completionProvider", Json::object(vec![])),
+                ])),
+            ]),
+            "textDocument/hover" if !word.is_empty() => Json::object(vec![
+                ("contents", Json::object(vec![
+                    ("kind", Json::from("plaintext")),
+                    ("value", Json::from(format!("mock hover: {}", word))),
+                ])),
+            ]),
+            "textDocument/definition" => {
+                let first = occurrences(text, &word).into_iter().next();
+                first.map(|range| location(&uri, range)).unwrap_or(Json::Null)
+            },
+            "textDocument/references" => {
+                Json::Array(occurrences(text, &word).into_iter().map(|range| location(&uri, range)).collect())
+            },
+            "textDocument/rename" => {
+                let new_name = params.get("newName").and_then(Json::as_str).unwrap_or("");
+                let edits = occurrences(text, &word).into_iter().map(|range| Json::object(vec![
+                    ("range", range_json(range)),
+                    ("newText", Json::from(new_name)),
+                ])).collect();
+                Json::Object(vec![
+                    (String::from("changes"), Json::Object(vec![(uri.clone(), Json::Array(edits))])),
+                ])
+            },
+            "textDocument/completion" => {
+                let prefix = word_before(text, line, character);
+                let mut words: Vec<String> = words(text).into_iter()
+                    .map(|(_, _, w)| w)
+                    .filter(|w| w.starts_with(&prefix) && *w != prefix)
+                    .collect();
+                words.sort();
+                words.dedup();
+                Json::Array(words.into_iter().map(|w| Json::object(vec![("label", Json::from(w))])).collect())
+            },
+            _ => Json::Null,
+        };
+        send(&Json::object(vec![("jsonrpc", Json::from("2.0")), ("id", id), ("result", result)]));
+    }
+}
+
+fn read_message(input: &mut impl BufRead) -> Option<Json> {
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok()?;
+        }
+    }
+    let mut body = vec![0u8; length];
+    input.read_exact(&mut body).ok()?;
+    Json::parse(&String::from_utf8_lossy(&body)).ok()
+}
+
+fn send(message: &Json) {
+    let body = message.to_string();
+    let mut stdout = io::stdout();
+    let _ = write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let _ = stdout.flush();
+}
+
+fn publish_diagnostics(uri: &str, text: &str) {
+    let mut diagnostics = Vec::new();
+    for (line, row) in text.lines().enumerate() {
+        if let Some(col) = row.find("TODO") {
+            diagnostics.push(diagnostic((line, col, col + 4), 2, "unfinished work"));
+        }
+    }
+    for range in occurrences(text, "error") {
+        diagnostics.push(diagnostic(range, 1, "mock error"));
+    }
+    send(&Json::object(vec![
+        ("jsonrpc", Json::from("2.0")),
+        ("method", Json::from("textDocument/publishDiagnostics")),
+        ("params", Json::object(vec![
+            ("uri", Json::from(uri)),
+            ("diagnostics", Json::Array(diagnostics)),
+        ])),
+    ]));
+}
+
+fn diagnostic(range: (usize, usize, usize), severity: u32, message: &str) -> Json {
+    Json::object(vec![
+        ("range", range_json(range)),
+        ("severity", Json::from(severity)),
+        ("message", Json::from(message)),
+    ])
+}
+
+// Ranges are (line, start, end) in characters; the mock only handles ASCII.
+fn range_json((line, start, end): (usize, usize, usize)) -> Json {
+    let position = |character: usize| Json::object(vec![
+        ("line", Json::from(line as u32)),
+        ("character", Json::from(character as u32)),
+    ]);
+    Json::object(vec![("start", position(start)), ("end", position(end))])
+}
+
+fn location(uri: &str, range: (usize, usize, usize)) -> Json {
+    Json::object(vec![("uri", Json::from(uri)), ("range", range_json(range))])
+}
+
+fn words(text: &str) -> Vec<(usize, usize, String)> {
+    let mut words = Vec::new();
+    for (line, row) in text.lines().enumerate() {
+        let bytes = row.as_bytes();
+        let mut idx = 0;
+        while idx < bytes.len() {
+            if is_word(bytes[idx]) {
+                let start = idx;
+                while idx < bytes.len() && is_word(bytes[idx]) {
+                    idx += 1;
+                }
+                words.push((line, start, String::from(&row[start..idx])));
+            }else{
+                idx += 1;
+            }
+        }
+    }
+    words
+}
+
+fn occurrences(text: &str, word: &str) -> Vec<(usize, usize, usize)> {
+    words(text).into_iter()
+        .filter(|(_, _, w)| w == word)
+        .map(|(line, start, w)| (line, start, start + w.len()))
+        .collect()
+}
+
+fn word_at(text: &str, line: usize, character: usize) -> String {
+    words(text).into_iter()
+        .find(|(l, start, w)| *l == line && *start <= character && character <= start + w.len())
+        .map(|(_, _, w)| w)
+        .unwrap_or_default()
+}
+
+fn word_before(text: &str, line: usize, character: usize) -> String {
+    let row = text.lines().nth(line).unwrap_or("");
+    let before = &row.as_bytes()[..character.min(row.len())];
+    let start = before.iter().rposition(|c| !is_word(*c)).map(|idx| idx + 1).unwrap_or(0);
+    String::from_utf8_lossy(&before[start..]).into_owned()
+}
+
+fn is_word(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
@@ -1,21 +1,82 @@
+mod brackets;
+mod completion;
+mod editorconfig;
 mod erow;
-pub use crate::editor_config::erow::{Erow};
+mod file_format;
+mod fold;
+mod glob;
+mod highlighter;
+mod indent;
+mod json;
+mod locations;
+mod lsp;
+mod panel;
+mod parser;
+mod project;
+mod regex;
+mod search;
+mod settings;
+mod shell;
+mod snippet;
+mod symbol;
+mod syntax;
+mod tags;
+mod theme;
+mod tree;
+mod undo;
+pub use crate::editor_config::brackets::{BracketMatch, match_bracket, unmatched_brackets, is_text, is_comment};
+pub use crate::editor_config::completion::{collect_words, rank_words, RECENT_WORDS};
+pub use crate::editor_config::editorconfig::{FileProperties};
+pub use crate::editor_config::erow::{Erow, HlOpen, RowState, is_ident_char};
+pub use crate::editor_config::file_format::{FileFormat, Charset, read_rows, write_rows};
+pub use crate::editor_config::fold::{Fold, Folds, fold_region, is_closer_row};
+pub use crate::editor_config::highlighter::{Highlighter, new_highlighter};
+pub use crate::editor_config::indent::{IndentStyle, detect_indent};
+pub use crate::editor_config::json::{Json};
+pub use crate::editor_config::locations::{parse_locations};
+pub use crate::editor_config::lsp::{LspClient, LspEvent, LspRequest, Diagnostic, position, path_to_uri,
+    uri_to_path, utf16_col, byte_col, hover_text, parse_lsp_locations, apply_text_edits};
+pub use crate::editor_config::panel::{ListPanel, ListItem};
+pub use crate::editor_config::parser::{Language, Scope};
+pub use crate::editor_config::project::{project_root, project_files, rank_files};
+pub use crate::editor_config::search::{ProjectSearch, SEARCH_RESULTS};
+pub use crate::editor_config::settings::{EditorSettings};
+pub use crate::editor_config::shell::{run_filter, run_capture};
+pub use crate::editor_config::snippet::{Snippet, SnippetSession, load_snippets, expand_snippet};
+pub use crate::editor_config::symbol::{Symbol, row_symbol, enclosing_symbols, filter_symbols};
+pub use crate::editor_config::syntax::{EditorSyntax, EditorSyntaxInf, HLFlags, NumFlags,
+    load_syntax_definitions, modeline_file_type, shebang_interpreter};
+pub use crate::editor_config::tags::{find_tags_file, read_tags, tag_locations};
+pub use crate::editor_config::tree::{TreeEvent, tree_path};
+pub use crate::editor_config::theme::{Theme, Style, Color, ColorDepth, load_theme, detect_color_depth};
+pub use crate::editor_config::undo::{UndoJournal};
 use super::{EditorKey, ab_append, editor_read_key, AppendBuffer, Function, Arrow, 
             enable_raw_mode, get_window_size};
 
-use std::io::{self, Write, stdout, BufRead};
-use std::path::Path;
-use std::fs::{File};
-use std::{str};
+use std::io::{Write, stdout};
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::{str, mem};
 use std::time::{Instant, Duration};
+use std::collections::HashMap;
 use termios::*;
 use terminal_size::{Width, Height};
-use bitflags::bitflags;
 
 
 pub const RILO_VERSION: u16 = 1;
 pub const RILO_TAB_STOP: u16 = 8;
 pub const RILO_QUIT_TIMES: u16 = 3;
+pub const RILO_MESSAGE_TIMEOUT: u64 = 5;
+// Highlighting runs for at most this many milliseconds per idle tick.
+const RILO_HL_IDLE_BUDGET: u64 = 15;
+// Rows on screen further than this below the last highlighted row are
+// highlighted on their own instead of catching up to them first.
+const RILO_HL_SYNC_ROWS: usize = 2000;
+// How far away the partner of a bracket is looked for.
+pub const RILO_BRACKET_SCAN_ROWS: usize = 1000;
+// Characters typed with their closer when auto_pairs is on.
+const PAIRS: [(u8, u8); 5] = [(b'(', b')'), (b'[', b']'), (b'{', b'}'), (b'"', b'"'), (b'\'', b'\'')];
+pub const RILO_HELP: &str = "HELP: Ctrl-s = save | Ctrl-q = quit | Ctrl-f = find | Ctrl-e = command";
 
 macro_rules! ctrl_key {
     ($ch:expr) => {
@@ -23,89 +84,132 @@ macro_rules! ctrl_key {
     };
 }
 
-bitflags! {
-    pub struct HLFlags: u32 {
-        const HLF_NUMBERS = 0b00000001;
-        const HLF_STRINGS = 0b00000010;
-    }
-}
-
-#[derive(Clone)]
-pub struct EditorSyntaxInf {
-    syntax: Option<EditorSyntax>,
-    in_string: u8,
-}
-
-#[derive(Clone)]
-pub struct EditorSyntax {
-    file_type: String,
-    file_extensions: Vec<String>,
-    pub keywords: Vec<String>,
-    singleline_comment_start: String,
-    pub flags: HLFlags,
-}
-
-impl EditorSyntax {
-    fn new(f_type: &str, f_ext: Vec<String>, kw: Vec<String>, sc: String, hf: HLFlags) -> EditorSyntax {
-        EditorSyntax{
-            file_type: String::from(f_type),
-            file_extensions: f_ext.clone(),
-            keywords: kw.clone(),
-            singleline_comment_start: sc,
-            flags: hf,
-        }
-    }
-
-    fn much_type(&mut self, filename: &Vec<u8>) -> bool {
-        let str_fname = String::from_utf8(filename.clone()).unwrap();
-        let work_vec: Vec<&str> = str_fname.split('.').collect();
-        if work_vec.len() == 1 { return false };
-        let ext: &str = work_vec[work_vec.len() - 1];
-        for syntax_ext in self.file_extensions.iter(){
-            if syntax_ext == ext {
-                return true
-            }
-        }
-        return false
-    }
-}
-
-#[derive(Clone)]
+// The groups keep kilo's upper case names.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, PartialEq)]
 pub enum Highlight {
     NONE,
     NORMAL,
     COMMENT,
+    MLCOMMENT,
     KEYWORD1,
     KEYWORD2,
     NUMBER,
     MATCH,
     STRING,
+    TYPE,
+    FUNCTION,
+    MACRO,
+    LIFETIME,
+    ATTRIBUTE,
+    CHAR,
+    ESCAPE,
+    DOCCOMMENT,
+    OPERATOR,
+    BRACKET,
+    UNMATCHED,
 }
 
-struct CurrentPosition {x: u16, y: u16}
+struct CurrentPosition {x: u16, y: u32}
 struct Screen { rows: u16, cols: u16}
-struct Offset {row: u16, col: u16}
+struct Offset {row: u32, col: u16}
 struct Status { message: Vec<u8>, time: Instant}
 
+// A buffer that is not on screen. Switching buffers swaps these fields with
+// the ones of EditorConfig.
+struct EditorBuffer {
+    cp: CurrentPosition,
+    off: Offset,
+    erow: Vec<Erow>,
+    numrows: u32,
+    filename: Vec<u8>,
+    scratch: Option<String>,
+    dirty: bool,
+    editor_syntax: EditorSyntaxInf,
+    syntax_override: Option<String>,
+    highlighter: Box<dyn Highlighter>,
+    hl_stale_from: usize,
+    indent: IndentStyle,
+    format: FileFormat,
+    mark: Option<u32>,
+    diagnostics: Vec<Diagnostic>,
+    lsp_version: i64,
+    lsp_changed: bool,
+    undo: UndoJournal,
+    folds: Folds,
+    symbols: Option<Vec<Symbol>>,
+    symbols_stale: bool,
+}
+
+// A box drawn at the cursor: hover text, or completions to pick from with
+// the text each one inserts. With a picker it is at the top of the screen,
+// listing what matches the query typed into it with their `rows`.
+struct Popup {
+    lines: Vec<String>,
+    completions: Vec<String>,
+    selected: usize,
+    picker: Option<Picker>,
+    rows: Vec<usize>,
+}
+
+// The symbols of the buffer, by the row they start on, or the files of the
+// project under `root`, by their index.
+enum Picker {
+    Symbols { query: String },
+    Files { query: String, root: PathBuf, files: Vec<String> },
+}
+
 pub struct EditorConfig {
     cp: CurrentPosition,
     rx: u16,
     screen: Screen,
     pub termios: Termios,
     erow: Vec<Erow>,
-    numrows: u16,
+    numrows: u32,
     off: Offset,
     filename: Vec<u8>,
+    scratch: Option<String>,
+    buffers: Vec<EditorBuffer>,
+    panel: Option<ListPanel>,
+    // The places jumped from to tags, the last one on top.
+    tag_stack: Vec<ListItem>,
+    popup: Option<Popup>,
+    recent_words: Vec<String>,
+    snippets: Vec<Snippet>,
+    snippet: Option<SnippetSession>,
+    auto_closers: Vec<(u32, u16)>,
+    undo: UndoJournal,
+    folds: Folds,
+    symbols: Option<Vec<Symbol>>,
+    symbols_stale: bool,
+    undo_typing: bool,
+    edits: u64,
+    lsp: Vec<LspClient>,
+    diagnostics: Vec<Diagnostic>,
+    lsp_version: i64,
+    lsp_changed: bool,
     status: Status,
     dirty: bool,
     quit_times: u16,
     last_match: i32,
     direction: i32,
     current_color: Highlight,
-    saved_hl_line: i16,
+    saved_hl_line: i32,
     saved_hl: Vec<Highlight>,
     pub editor_syntax: EditorSyntaxInf,
     syntax_pattern: Vec<EditorSyntax>,
+    syntax_override: Option<String>,
+    highlighter: Box<dyn Highlighter>,
+    hl_stale_from: usize,
+    hl_marks: Vec<(usize, usize, Highlight)>,
+    indent: IndentStyle,
+    format: FileFormat,
+    mark: Option<u32>,
+    settings: EditorSettings,
+    project_loaded: bool,
+    config_errors: Vec<String>,
+    theme: Theme,
+    color_depth: ColorDepth,
 }
 
 impl EditorConfig {
@@ -143,6 +247,17 @@ impl EditorConfig {
                 }
             },
         }
+        // Moving into a closed fold steps over it.
+        if let Some(fold) = self.folds.hiding(self.cp.y) {
+            match key {
+                Arrow::Down | Arrow::Right => self.cp.y = fold.end + 1,
+                Arrow::Left => {
+                    self.cp.y = fold.start;
+                    self.cp.x = self.erow[fold.start as usize].size;
+                },
+                Arrow::Up => self.cp.y = fold.start,
+            }
+        }
         if self.cp.y >= self.numrows {
             self.cp.x = 0;
         }else if self.cp.x > self.erow[self.cp.y as usize].size {
@@ -150,24 +265,78 @@ impl EditorConfig {
         }
     }
     
+    // Each key that edits the buffer is one undo step, except that typing
+    // continues the step of the keys typed before it.
     pub fn editor_process_keypress(&mut self) -> Result<usize, & 'static str> {
         let inkey: EditorKey = editor_read_key();
+        let typing = match &inkey {
+            EditorKey::Else(c) => *c >= b' ' && *c != 127,
+            EditorKey::Function(Function::Backspace) | EditorKey::Function(Function::Delete) => true,
+            _ => false,
+        };
+        let moving = matches!(inkey, EditorKey::Arrow(_));
+        if !(moving || typing && self.undo_typing) {
+            self.editor_undo_record();
+            self.undo.begin(self.cp.y, self.cp.x);
+        }
+        let edits = self.edits;
+        let result = self.editor_handle_key(inkey);
+        if self.edits != edits {
+            if !typing {
+                self.editor_undo_record();
+            }
+            self.undo_typing = typing;
+        }else if !typing {
+            self.undo_typing = false;
+        }
+        result
+    }
+
+    fn editor_handle_key(&mut self, inkey: EditorKey) -> Result<usize, & 'static str> {
+        if self.popup.is_some() && self.editor_popup_key(&inkey) {
+            return Ok(0);
+        }
+        if self.snippet.is_some() && self.editor_snippet_key(&inkey) {
+            return Ok(0);
+        }
+        let before = self.editor_snippet_before();
         match inkey {
             EditorKey::Arrow(arrow) => {
                 self.editor_move_cursor(&arrow);
             },
+            EditorKey::Alt(val) => {
+                if val == b'n' {
+                    self.editor_panel_step(true);
+                }else if val == b'p' {
+                    self.editor_panel_step(false);
+                }else if val == b'k' {
+                    self.editor_lsp_request(LspRequest::Hover, "textDocument/hover", vec![]);
+                }else if val == b'd' {
+                    self.editor_lsp_request(LspRequest::Definition, "textDocument/definition", vec![]);
+                }else if val == b'r' {
+                    self.editor_lsp_references();
+                }else if val == b'c' {
+                    self.editor_lsp_request(LspRequest::Completion, "textDocument/completion", vec![]);
+                }else if val == b'f' {
+                    self.editor_toggle_fold();
+                }else if val == b'o' {
+                    self.editor_outline();
+                }else if val == b'g' {
+                    self.editor_grep_prompt();
+                }
+            },
             EditorKey::Function(func) => {
                 match func {
                     Function::Up | Function::Down => {
                         if let Function::Up = func {
                             self.cp.y = self.off.row;
                         }else{
-                            self.cp.y = self.off.row + self.screen.rows -1;
+                            self.cp.y = self.off.row + self.editor_text_rows() as u32 - 1;
                             if self.cp.y > self.numrows {
                                 self.cp.y = self.numrows;
                             }
                         }
-                        let mut times = self.screen.rows;
+                        let mut times = self.editor_text_rows();
                         let y: Arrow = if let Function::Up = func {
                             Arrow::Up
                         } else {
@@ -186,16 +355,20 @@ impl EditorConfig {
                     },
                     Function::Delete => {
                         self.editor_move_cursor(&Arrow::Right);
-                        self.editor_delete_char();
+                        self.editor_delete_char(&Function::Delete);
                     },
                     Function::Backspace => {
-                        self.editor_delete_char();
+                        self.editor_delete_char(&Function::Backspace);
+                    },
+                    Function::BackTab => {
+                        let (first, last) = self.editor_selected_rows();
+                        self.editor_dedent_rows(first, last);
                     },
                 }
             }
             EditorKey::Else(val) => {
                 if val == ctrl_key!('q') {
-                    if self.dirty && self.quit_times > 0 {
+                    if self.editor_unsaved_buffers() > 0 && self.quit_times > 0 {
                         self.editor_set_status_message(
                             format!(
                             "WARNING!!! File has unsaves changes. Press Ctrl-Q {} more times to quit.",
@@ -204,107 +377,587 @@ impl EditorConfig {
                         self.quit_times -= 1;
                         return Ok(0)
                     }
+                    for client in self.lsp.iter_mut() {
+                        client.shutdown();
+                    }
+                    stdout().write_all(b"\x1b[0m").unwrap();
                     stdout().write("\x1b[2J".as_bytes()).unwrap();
                     stdout().write("\x1b[H".as_bytes()).unwrap();
                     return Ok(1)
                 }else if val == ctrl_key!('h') {
-                    self.editor_delete_char();
+                    self.editor_delete_char(&Function::Backspace);
                 }else if val == ctrl_key!('f') {
                     self.editor_find();
                 }else if val == ctrl_key!('s') {
                     self.editor_save();
+                }else if val == ctrl_key!(b'e') {
+                    self.editor_command();
+                }else if val == ctrl_key!(b'b') {
+                    self.editor_jump_to_bracket();
+                }else if val == ctrl_key!(b'z') {
+                    self.editor_undo(false);
+                }else if val == ctrl_key!(b'y') {
+                    self.editor_undo(true);
+                }else if val == ctrl_key!(b'_') {
+                    self.editor_toggle_comment();
+                }else if val == ctrl_key!(b']') {
+                    let name = self.editor_word_at_cursor();
+                    self.editor_jump_to_tag(&name);
+                }else if val == ctrl_key!(b't') {
+                    self.editor_pop_tag();
+                }else if val == ctrl_key!(b'o') {
+                    self.editor_file_picker();
+                }else if val == ctrl_key!(b'n') || val == ctrl_key!(b'p') {
+                    self.editor_word_completion(val == ctrl_key!(b'n'));
                 }else if val == '\r' as u8 {
                     self.editor_insert_new_line();
+                }else if val == b'\t' && self.mark.is_some() {
+                    let (first, last) = self.editor_selected_rows();
+                    self.editor_indent_rows(first, last);
+                }else if val == b'\t' && self.editor_expand_snippet() {
+                    // The snippet trigger before the cursor was expanded.
+                }else if val == b'\t' && self.indent.expand_tabs {
+                    self.editor_insert_soft_tab();
+                }else if val == b'\0' {
+                    self.editor_toggle_mark();
                 }else if val == '\x1b' as u8 {
+                    if self.mark.is_some() {
+                        self.mark = None;
+                    }else{
+                        self.panel = None;
+                    }
                 }else{
                     self.editor_insert_char(&val);
                 }
             }, 
         };
+        self.editor_snippet_after(before);
         Ok(0)
     }
     
     pub fn editor_select_syntax_highlight(&mut self){
-        self.editor_syntax.syntax = None;
-        if self.filename.len() == 0 {
+        self.editor_syntax.syntax = self.editor_detect_syntax();
+        self.highlighter = new_highlighter(&self.editor_syntax, self.settings.structural_highlighting);
+        for row in self.erow.iter_mut() {
+            row.hl_stale = true;
+        }
+        self.hl_stale_from = 0;
+        self.symbols = None;
+    }
+
+    // A file type set with the `filetype` command wins, then a modeline,
+    // then the file name and finally the shebang line.
+    fn editor_detect_syntax(&self) -> Option<EditorSyntax> {
+        if let Some(name) = &self.syntax_override {
+            return self.editor_find_syntax(name);
+        }
+        let rows: Vec<&[u8]> = self.erow.iter().map(|row| &row.chars[..row.size as usize]).collect();
+        if let Some(name) = modeline_file_type(&rows) {
+            if let Some(syntax) = self.editor_find_syntax(&name) {
+                return Some(syntax);
+            }
+        }
+        if !self.filename.is_empty() {
+            if let Some(syntax) = self.syntax_pattern.iter().rev().find(|s| s.much_type(&self.filename)) {
+                return Some(syntax.clone());
+            }
+        }
+        let interpreter = shebang_interpreter(rows.first()?)?;
+        self.syntax_pattern.iter().rev().find(|s| s.much_shebang(&interpreter)).cloned()
+    }
+
+    fn editor_find_syntax(&self, name: &str) -> Option<EditorSyntax> {
+        self.syntax_pattern.iter().rev().find(|s| s.much_name(name)).cloned()
+    }
+
+    pub fn editor_set_file_type(&mut self, name: &str) {
+        match name {
+            "" => {
+                let ft = match &self.editor_syntax.syntax {
+                    Some(syntax) => syntax.file_type.clone(),
+                    None => String::from("none"),
+                };
+                self.editor_set_status_message(format!("filetype={}", ft));
+                return;
+            },
+            "auto" => self.syntax_override = None,
+            "none" | "off" => self.syntax_override = Some(String::from(name)),
+            _ => {
+                match self.editor_find_syntax(name) {
+                    Some(syntax) => self.syntax_override = Some(syntax.file_type),
+                    None => {
+                        self.editor_set_status_message(format!("Unknown file type: {}", name));
+                        return;
+                    },
+                }
+            },
+        }
+        self.editor_select_syntax_highlight();
+    }
+
+    // Shows the nodes of the parse tree at the cursor with where they open
+    // and close, like `block 3:12-40:1 > params 5:11-5:30`.
+    fn editor_show_scopes(&mut self) {
+        let parsed = self.settings.structural_highlighting &&
+            self.editor_syntax.syntax.as_ref().map(|s| s.parser.is_some()).unwrap_or(false);
+        let filerow = self.cp.y as usize;
+        if !parsed || filerow >= self.numrows as usize {
+            self.editor_set_status_message(String::from("No parse tree here"));
             return;
         }
-        let pattern = self.syntax_pattern.clone();
-        for syntax in pattern.iter(){
-            let mut ts = syntax.clone();
-            if ts.much_type(&self.filename) {
-                self.editor_syntax.syntax = Some(ts.clone());
-                let mut filerow: usize = 0;
-                while filerow < self.numrows as usize {
-                    self.erow[filerow].editor_update_syntax(&mut self.editor_syntax);
-                    filerow += 1;
+        if self.hl_stale_from < self.numrows as usize {
+            self.editor_highlight_upto(usize::MAX, None);
+        }
+        let rx = editor_row_cxtorx(&self.erow[filerow].chars, self.cp.x as usize, self.indent.tab_stop) as usize;
+        let nodes: Vec<String> = tree_path(&self.erow, filerow, rx).iter().map(|node| {
+            let mut label = format!("{} {}:{}", node.scope.name(), node.start.0 + 1, node.start.1 + 1);
+            if let Some((row, col)) = node.end {
+                label.push_str(&format!("-{}:{}", row + 1, col + 1));
+            }
+            label
+        }).collect();
+        let message = if nodes.is_empty() { String::from("Top level") } else { nodes.join(" > ") };
+        self.editor_set_status_message(message);
+    }
+
+    fn editor_open_state_before(&self, filerow: usize) -> RowState {
+        if filerow == 0 || filerow > self.numrows as usize {
+            return RowState::new();
+        }
+        self.erow[filerow - 1].hl_state.clone()
+    }
+
+    // Each row keeps the comment and string state open at its end, so highlighting
+    // can restart at any row. Rows before `hl_stale_from` are up to date.
+    fn editor_invalidate_syntax(&mut self, at: usize) {
+        self.undo.changed(at);
+        self.lsp_changed = true;
+        self.symbols_stale = true;
+        self.edits += 1;
+        if at < self.numrows as usize {
+            self.erow[at].hl_stale = true;
+            self.hl_stale_from = self.hl_stale_from.min(at);
+        }
+    }
+
+    // A row whose end state changes makes the next row stale too. The item
+    // starting on the row is looked for again with it.
+    fn editor_highlight_row(&mut self, filerow: usize) {
+        let before = self.editor_open_state_before(filerow);
+        if self.highlighter.highlight_row(&mut self.erow[filerow], &before) &&
+                filerow + 1 < self.numrows as usize {
+            self.erow[filerow + 1].hl_stale = true;
+        }
+        let symbol = match &self.editor_syntax.syntax {
+            Some(syntax) => row_symbol(syntax, &self.erow[filerow]),
+            None => None,
+        };
+        if symbol != self.erow[filerow].symbol {
+            self.erow[filerow].symbol = symbol;
+            self.symbols_stale = true;
+        }
+    }
+
+    fn editor_highlight_upto(&mut self, last: usize, deadline: Option<Instant>) {
+        while self.hl_stale_from < self.numrows as usize && self.hl_stale_from <= last {
+            let filerow = self.hl_stale_from;
+            self.hl_stale_from += 1;
+            if !self.erow[filerow].hl_stale {
+                continue;
+            }
+            self.editor_highlight_row(filerow);
+            self.erow[filerow].hl_stale = false;
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    return;
                 }
             }
         }
     }
 
-    pub fn editor_open(&mut self, filename: &String) {
+    // Rows far below the highlighted part of the file start from whatever state
+    // the row above holds and stay stale until the idle pass reaches them.
+    fn editor_highlight_visible(&mut self) {
+        let first = self.off.row as usize;
+        let last = first + self.editor_text_rows() as usize;
+        if first <= self.hl_stale_from + RILO_HL_SYNC_ROWS {
+            self.editor_highlight_upto(last, None);
+            return;
+        }
+        for filerow in first..(last + 1).min(self.numrows as usize) {
+            if self.erow[filerow].hl_stale {
+                self.editor_highlight_row(filerow);
+            }
+        }
+    }
+
+    // The bracket at the cursor, or else just before it, and its partner.
+    fn editor_find_bracket(&mut self) -> Option<(usize, usize, BracketMatch)> {
+        let filerow = self.cp.y as usize;
+        if filerow >= self.numrows as usize {
+            return None;
+        }
+        if filerow <= self.hl_stale_from + RILO_HL_SYNC_ROWS {
+            self.editor_highlight_upto(filerow + RILO_BRACKET_SCAN_ROWS, None);
+        }
+        let rx = editor_row_cxtorx(&self.erow[filerow].chars, self.cp.x as usize,
+            self.indent.tab_stop) as usize;
+        for col in [Some(rx), rx.checked_sub(1)].iter().flatten() {
+            if let Some(m) = match_bracket(&self.erow, filerow, *col, RILO_BRACKET_SCAN_ROWS) {
+                return Some((filerow, *col, m));
+            }
+        }
+        None
+    }
+
+    fn editor_mark_brackets(&mut self) {
+        self.hl_marks.retain(|(_, _, hlk)| *hlk != Highlight::BRACKET && *hlk != Highlight::UNMATCHED);
+        match self.editor_find_bracket() {
+            Some((row, col, BracketMatch::Matched(mrow, mcol))) => {
+                self.hl_marks.push((row, col, Highlight::BRACKET));
+                self.hl_marks.push((mrow, mcol, Highlight::BRACKET));
+            },
+            Some((row, col, BracketMatch::Unmatched)) => {
+                self.hl_marks.push((row, col, Highlight::UNMATCHED));
+            },
+            None => (),
+        }
+        // Without a syntax every bracket is text, like the `1)` of a list.
+        if self.editor_syntax.syntax.is_none() || self.numrows == 0 {
+            return;
+        }
+        let first = (self.off.row as usize).min(self.numrows as usize - 1);
+        let last = (first + self.editor_text_rows() as usize).min(self.numrows as usize) - 1;
+        if first <= self.hl_stale_from + RILO_HL_SYNC_ROWS {
+            self.editor_highlight_upto(last + RILO_BRACKET_SCAN_ROWS, None);
+        }
+        for (row, col) in unmatched_brackets(&self.erow, first, last, RILO_BRACKET_SCAN_ROWS) {
+            if !self.hl_marks.iter().any(|(r, c, _)| *r == row && *c == col) {
+                self.hl_marks.push((row, col, Highlight::UNMATCHED));
+            }
+        }
+    }
+
+    fn editor_jump_to_bracket(&mut self) {
+        match self.editor_find_bracket() {
+            Some((_, _, BracketMatch::Matched(mrow, mcol))) => {
+                self.cp.y = mrow as u32;
+                self.cp.x = editor_row_rxtocx(&self.erow[mrow].chars, mcol, self.indent.tab_stop);
+            },
+            Some((_, _, BracketMatch::Unmatched)) => {
+                self.editor_set_status_message(String::from("No matching bracket"));
+            },
+            None => (),
+        }
+    }
+
+    pub fn editor_idle_pending(&self) -> bool {
+        self.hl_stale_from < self.numrows as usize || self.symbols_stale ||
+            self.panel.as_ref().map(|p| p.search.is_some()).unwrap_or(false)
+    }
+
+    // Background work done while no key is pressed. Returns whether the screen
+    // needs to be redrawn.
+    pub fn editor_idle(&mut self) -> bool {
+        let from = self.hl_stale_from;
+        let deadline = Instant::now() + Duration::from_millis(RILO_HL_IDLE_BUDGET);
+        self.editor_highlight_upto(usize::MAX, Some(deadline));
+        // The symbols are gathered again once the rows are all highlighted.
+        let indexed = self.symbols_stale && self.hl_stale_from >= self.numrows as usize;
+        if indexed {
+            self.editor_update_symbols();
+        }
+        let first = self.off.row as usize;
+        let highlighted = from != self.hl_stale_from && from <= first + self.editor_text_rows() as usize &&
+            self.hl_stale_from > first;
+        let searched = self.editor_search_step();
+        self.editor_lsp_poll() || highlighted || searched || indexed
+    }
+
+    // Lists the lines of the files of the project holding `pattern`. The project
+    // is walked and searched while the editor is idle and the panel fills as it
+    // is.
+    fn editor_grep(&mut self, pattern: &str) {
+        let root = project_root(&self.editor_file_dir());
+        let mut panel = ListPanel::new(format!("grep {}", pattern), Vec::new());
+        panel.search = Some(ProjectSearch::new(String::from(pattern), root));
+        self.panel = Some(panel);
+        self.editor_search_step();
+    }
+
+    fn editor_grep_prompt(&mut self) {
+        let pattern = String::from_utf8(self.editor_prompt(String::from("Grep: {}"), None)).unwrap();
+        if !pattern.is_empty() {
+            self.editor_grep(&pattern);
+        }
+    }
+
+    // Searches more files for the panel's project search. Jumps to the first
+    // match and reports the count when done. Returns whether the panel changed.
+    fn editor_search_step(&mut self) -> bool {
+        let panel = match &mut self.panel {
+            Some(panel) => panel,
+            None => return false,
+        };
+        let search = match &mut panel.search {
+            Some(search) => search,
+            None => return false,
+        };
+        let deadline = Instant::now() + Duration::from_millis(RILO_HL_IDLE_BUDGET);
+        let items = search.step(deadline);
+        let first = panel.items.is_empty() && !items.is_empty();
+        panel.items.extend(items);
+        if first {
+            self.editor_panel_jump();
+        }
+        let panel = match &mut self.panel {
+            Some(panel) => panel,
+            None => return true,
+        };
+        let done = match &panel.search {
+            Some(search) if search.is_done() => search,
+            _ => return true,
+        };
+        let message = if panel.items.is_empty() {
+            format!("No matches for {}", done.pattern)
+        }else{
+            let more = if done.matches >= SEARCH_RESULTS { " (stopped there)" } else { "" };
+            let files = if done.matched_files == 1 { "file" } else { "files" };
+            format!("{} matches in {} {}{}", done.matches, done.matched_files, files, more)
+        };
+        if panel.items.is_empty() {
+            self.panel = None;
+        }else{
+            panel.search = None;
+        }
+        self.editor_set_status_message(message);
+        true
+    }
+
+    pub fn editor_load_settings(&mut self, dir: Option<&Path>) {
+        let (settings, mut errors) = EditorSettings::load(dir);
+        self.settings = settings;
+        self.syntax_pattern = load_syntax_definitions(dir, &mut errors);
+        self.snippets = load_snippets(dir, &mut errors);
+        self.theme = load_theme(&self.settings.theme, dir, &mut errors);
+        self.theme.merge(&self.settings.colors);
+        self.color_depth = match self.settings.color_depth {
+            Some(depth) => depth,
+            None => detect_color_depth(),
+        };
+        self.config_errors = errors;
+        self.quit_times = self.settings.quit_times;
+        self.indent = IndentStyle::new(self.settings.tab_stop, self.settings.expand_tabs);
+    }
+
+    pub fn editor_show_startup_message(&mut self) {
+        if self.config_errors.is_empty() {
+            let help = self.settings.help.clone();
+            self.editor_set_status_message(help);
+        }else{
+            let mut message = format!("Config error: {}", self.config_errors[0]);
+            if self.config_errors.len() > 1 {
+                message.push_str(&format!(" (and {} more)", self.config_errors.len() - 1));
+            }
+            self.editor_set_status_message(message);
+        }
+    }
+
+    pub fn editor_open(&mut self, filename: &String) -> Result<(), String> {
         let path = Path::new(filename); 
-        let file = File::open(path).unwrap();
+        let bytes = fs::read(path).map_err(|e| format!("Cannot open {}: {}", filename, e))?;
+        let full: PathBuf = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let dir: PathBuf = full.parent().map(|d| d.to_path_buf()).unwrap_or_else(|| PathBuf::from("."));
+        // The first file opened brings in its project's configuration; later
+        // ones only take the settings that belong to a buffer from theirs, so
+        // the other buffers keep the editor as it is.
+        let settings = if self.project_loaded {
+            EditorSettings::load(Some(&dir)).0
+        }else{
+            self.editor_load_settings(Some(&dir));
+            self.project_loaded = true;
+            self.settings.clone()
+        };
         self.filename = filename.as_bytes().to_vec();
+        let props = FileProperties::load(&full);
+        self.format = FileFormat::new();
+        for mut row in read_rows(&bytes, props.charset, &mut self.format) {
+            let at = self.numrows;
+            let size = row.len() as u16;
+            self.editor_insert_row(&at, &mut row, size);
+        }
+        self.format.trim_trailing_whitespace = settings.trim_trailing_whitespace;
+        self.format.final_newline |= settings.ensure_final_newline;
+        props.apply_format(&mut self.format);
+        let mut indent = IndentStyle::new(settings.tab_stop, settings.expand_tabs);
+        if settings.detect_indent {
+            let rows: Vec<&[u8]> = self.erow.iter().map(|row| &row.chars[..row.size as usize]).collect();
+            indent = detect_indent(&rows, indent);
+        }
+        if props.sets_indent() {
+            indent = props.indent_style(indent);
+        }
+        self.editor_set_indent_style(indent);
+        self.undo.reset(&self.erow);
         self.editor_select_syntax_highlight();
-        let mut reader = io::BufReader::new(file);
-        let mut line = String::new();
-        loop{
-            match reader.read_line(&mut line) {
-                Ok(0) => break,
-                Ok(len) => {
-                    let mut vec_line: Vec<u8> = line.clone().as_bytes().to_vec();
-                    let mut ll = len;
-                    while ll > 0 && (vec_line[ll - 1] == '\r' as u8 || vec_line[ll - 1] == '\n' as u8) {
-                        ll -= 1;
-                    }
-                    if ll != 0 {
-                        vec_line.truncate(ll);
-                        let at = self.numrows;
-                        self.editor_insert_row(&at, &mut vec_line, ll as u16);
-                    }
-                },
-                Err(e) => panic!("File read line fail:{:?}",e),
-            };
-            line.clear();
+        self.editor_lsp_open();
+        Ok(())
+    }
+
+    // The tab width and whether Tab inserts spaces belong to the buffer, so
+    // they can follow the file rather than the configuration.
+    fn editor_set_indent_style(&mut self, indent: IndentStyle) {
+        let rerender = indent.tab_stop != self.indent.tab_stop;
+        self.indent = indent;
+        if rerender {
+            for row in self.erow.iter_mut() {
+                row.editor_update_row(indent.tab_stop);
+            }
+            self.editor_invalidate_syntax(0);
         }
     }
 
-    fn editor_insert_row(&mut self, at: &u16, char_vec: &mut Vec<u8>, size: u16){
+    fn editor_insert_row(&mut self, at: &u32, char_vec: &mut Vec<u8>, size: u16){
         if *at > self.numrows {
             return;
         }
-        char_vec.append(&mut "\0".as_bytes().to_vec());
+        let erow = self.editor_new_row(*at as usize, mem::take(char_vec), size);
+        self.erow.insert(*at as usize, erow);
+        self.numrows += 1;
+        self.folds.insert_row(*at);
+        self.editor_invalidate_syntax(*at as usize);
+    }
+    
+    fn editor_new_row(&self, at: usize, mut chars: Vec<u8>, size: u16) -> Erow {
+        chars.push(b'\0');
         let mut erow: Erow = Erow {
             size: size,
-            chars: char_vec.clone(),
+            chars,
             _rsize: 0,
             render: Vec::new(),
             hl: Vec::new(),
+            hl_state: self.editor_open_state_before(at),
+            hl_stale: true,
+            tree: Vec::new(),
+            symbol: None,
         };
-        erow.editor_update_row(&mut self.editor_syntax);
-        self.erow.insert(*at as usize, erow);
-        self.numrows += 1;
+        erow.editor_update_row(self.indent.tab_stop);
+        erow
     }
-    
+
     pub fn editor_scroll(&mut self){
         self.rx = 0;
         if self.cp.y < self.numrows {
-            self.rx = editor_row_cxtorx(self.erow[self.cp.y as usize].chars.clone(), self.cp.x as usize);
+            self.rx = editor_row_cxtorx(&self.erow[self.cp.y as usize].chars, self.cp.x as usize,
+                self.indent.tab_stop);
         }
         
+        if self.folds.is_hidden(self.cp.y) {
+            self.folds.reveal(self.cp.y);
+        }
         if self.cp.y < self.off.row {
             self.off.row = self.cp.y;
         }
-        if self.cp.y >= self.off.row + self.screen.rows {
-            self.off.row = self.cp.y - self.screen.rows + 1;
+        if let Some(fold) = self.folds.hiding(self.off.row) {
+            self.off.row = fold.start;
+        }
+        if self.settings.wrap || !self.folds.is_empty() {
+            // The first row from which the rows down to the cursor fit.
+            let text_rows = self.editor_text_rows();
+            let cols = self.editor_text_cols();
+            let mut used = if self.settings.wrap { self.rx / cols + 1 } else { 1 };
+            let mut first = self.cp.y;
+            while first > self.off.row {
+                let height = self.editor_row_height(first - 1);
+                if used + height > text_rows {
+                    break;
+                }
+                used += height;
+                first -= 1;
+            }
+            self.off.row = first;
+        }else if self.cp.y >= self.off.row + self.editor_text_rows() as u32 {
+            self.off.row = self.cp.y - self.editor_text_rows() as u32 + 1;
+        }
+        let cols = self.editor_text_cols();
+        if self.settings.wrap {
+            self.off.col = 0;
+            return;
         }
         if self.rx < self.off.col {
             self.off.col = self.rx;
         }
-        if self.rx >= self.off.col + self.screen.cols {
-            self.off.col = self.rx - self.screen.cols + 1;
+        if self.rx >= self.off.col + cols {
+            self.off.col = self.rx - cols + 1;
+        }
+    }
+
+    fn editor_gutter_width(&self) -> u16 {
+        let signs = self.editor_sign_width();
+        if !self.settings.line_numbers {
+            return signs;
+        }
+        let mut digits = 1;
+        let mut n = self.numrows;
+        while n >= 10 {
+            n /= 10;
+            digits += 1;
+        }
+        if digits < 3 { digits = 3; }
+        digits + 1 + signs
+    }
+
+    // A column for diagnostic markers while the buffer has diagnostics.
+    fn editor_sign_width(&self) -> u16 {
+        if self.diagnostics.is_empty() { 0 } else { 2 }
+    }
+
+    // Screen rows left for the text when the list panel is open.
+    fn editor_text_rows(&self) -> u16 {
+        match &self.panel {
+            Some(panel) => self.screen.rows.saturating_sub(panel.height()).max(1),
+            None => self.screen.rows,
+        }
+    }
+
+    fn editor_text_cols(&self) -> u16 {
+        let gutter = self.editor_gutter_width();
+        if self.screen.cols > gutter + 1 {
+            self.screen.cols - gutter
+        }else{
+            1
+        }
+    }
+
+    // Number of screen lines a file row occupies.
+    fn editor_row_height(&self, filerow: u32) -> u16 {
+        if self.folds.is_hidden(filerow) {
+            return 0;
+        }
+        if !self.settings.wrap || filerow >= self.numrows || self.folds.at(filerow).is_some() {
+            return 1;
+        }
+        self.erow[filerow as usize]._rsize / self.editor_text_cols() + 1
+    }
+
+    fn editor_cursor_screen_row(&self) -> u16 {
+        if !self.settings.wrap && self.folds.is_empty() {
+            return (self.cp.y - self.off.row) as u16;
+        }
+        let mut line: u16 = 0;
+        let mut filerow = self.off.row;
+        while filerow < self.cp.y {
+            line += self.editor_row_height(filerow);
+            filerow += 1;
+        }
+        if self.settings.wrap { line + self.rx / self.editor_text_cols() } else { line }
+    }
+
+    fn editor_cursor_screen_col(&self) -> u16 {
+        if self.settings.wrap {
+            self.rx % self.editor_text_cols() + self.editor_gutter_width()
+        }else{
+            self.rx - self.off.col + self.editor_gutter_width()
         }
     }
 
@@ -368,16 +1021,21 @@ impl EditorConfig {
 
     pub fn editor_refresh_screen(&mut self) {
         self.editor_scroll();
+        self.editor_highlight_visible();
+        self.editor_mark_brackets();
     
         let mut abuf: AppendBuffer = AppendBuffer { b:Vec::<u8>::new(), len: 0, };
         ab_append(&mut abuf, &mut "\x1b[?25l".as_bytes().to_vec());
         ab_append(&mut abuf, &mut "\x1b[H".as_bytes().to_vec());
     
         self.editor_draw_rows(&mut abuf);
+        self.editor_draw_panel(&mut abuf);
         self.editor_draw_status_bar(&mut abuf);
         self.editor_draw_message_bar(&mut abuf);
+        self.editor_draw_popup(&mut abuf);
     
-        let csr = format!("\x1b[{};{}H", self.cp.y - self.off.row + 1, self.rx - self.off.col + 1);
+        let csr = format!("\x1b[{};{}H", self.editor_cursor_screen_row() + 1,
+            self.editor_cursor_screen_col() + 1);
         ab_append(&mut abuf, &mut csr.as_bytes().to_vec());
     
         ab_append(&mut abuf, &mut "\x1b[?25h".as_bytes().to_vec());
@@ -391,14 +1049,23 @@ impl EditorConfig {
     }
     
     fn editor_draw_rows(&mut self, abuf: &mut AppendBuffer) {
+        let cols = self.editor_text_cols() as usize;
+        let gutter = self.editor_gutter_width() as usize;
+        let mut filerow = self.off.row;
+        let mut segment: usize = 0;
+        let normal = self.theme.style("normal").escape(self.color_depth);
         let mut y: u16 = 0;
-        while y < self.screen.rows {
-            let filerow = y + self.off.row;
+        while y < self.editor_text_rows() {
+            ab_append(abuf, &mut normal.as_bytes().to_vec());
+            self.current_color = Highlight::NORMAL;
+            while segment == 0 && filerow < self.numrows && self.folds.is_hidden(filerow) {
+                filerow += 1;
+            }
             if filerow >= self.numrows {
-                if self.numrows == 0 && y == self.screen.rows / 3 {
+                if self.numrows == 0 && y == self.editor_text_rows() / 3 {
                     let msg = format!("Rilo editor -- version {}", RILO_VERSION);
                     let mut vmsg: Vec<u8> = msg.as_bytes().to_vec();
-                    let mut padding = (self.screen.cols - vmsg.len() as u16) / 2;
+                    let mut padding = self.screen.cols.saturating_sub(vmsg.len() as u16) / 2;
                     if padding != 0 {
                         ab_append(abuf, &mut "~".as_bytes().to_vec());
                         padding  -= 1;
@@ -412,37 +1079,42 @@ impl EditorConfig {
                     ab_append(abuf, &mut "~".as_bytes().to_vec());
                 }
             }else{
-                let mut disp_vec: Vec<u8> = self.erow[filerow as usize].render.clone();
-                disp_vec.truncate(disp_vec.len());
-                
-                if self.off.col > 0 {
-                    disp_vec.drain(1..self.off.col as usize);
+                let signs = self.editor_sign_width() as usize;
+                if signs > 0 {
+                    let sign = if segment == 0 { self.editor_sign(filerow as usize) } else { String::from("  ") };
+                    ab_append(abuf, &mut sign.into_bytes());
                 }
-    
-                if disp_vec.len() > self.screen.cols as usize {
-                    disp_vec.truncate(self.screen.cols as usize - 1);
-                }
-                let hl_vec: Vec<Highlight> = self.erow[filerow as usize].hl.clone();
-                let mut idx = 0;
-                while idx < hl_vec.len() {
-                    if let Highlight::NORMAL = hl_vec[idx]  {
-                        if !matches!(self.current_color, Highlight::NORMAL) {
-                            ab_append(abuf, &mut "\x1b[39m".as_bytes().to_vec());
-                            self.current_color = Highlight::NORMAL;
-                        }
-                        ab_append(abuf, &mut std::slice::from_ref(&disp_vec[idx]).to_vec());
+                if gutter > signs {
+                    let number = if segment == 0 {
+                        format!("{}{:>w$} {}", self.theme.style("line_number").escape(self.color_depth),
+                            filerow + 1, normal, w = gutter - signs - 1)
                     }else{
-                        let color: u8 = editor_syntax_to_color(&hl_vec[idx]);
-                        if color != editor_syntax_to_color(&self.current_color) {
-                            self.current_color = editor_color_to_syntax(color);
-                            let clen = format!("\x1b[{}m", color); 
-                            ab_append(abuf, &mut clen.as_bytes().to_vec());
-                        }
-                        ab_append(abuf, &mut std::slice::from_ref(&disp_vec[idx]).to_vec());
+                        " ".repeat(gutter - signs)
+                    };
+                    ab_append(abuf, &mut number.as_bytes().to_vec());
+                }
+                let rsize = self.erow[filerow as usize]._rsize as usize;
+                let start = if self.settings.wrap { segment * cols } else { self.off.col as usize };
+                if let Some(fold) = self.folds.at(filerow) {
+                    // A closed fold shows its first row and how much it hides.
+                    let summary = self.editor_fold_summary(fold);
+                    let width = cols.saturating_sub(summary.len());
+                    self.editor_draw_row_segment(abuf, filerow as usize, start, width);
+                    let shown = rsize.saturating_sub(start).min(width);
+                    let summary: String = summary.chars().take(cols - shown).collect();
+                    let line = format!("{}{}{}", self.theme.style("fold").escape(self.color_depth), summary, normal);
+                    ab_append(abuf, &mut line.into_bytes());
+                    filerow = fold.end + 1;
+                    segment = 0;
+                }else{
+                    self.editor_draw_row_segment(abuf, filerow as usize, start, cols);
+                    if self.settings.wrap && (segment + 1) * cols <= rsize {
+                        segment += 1;
+                    }else{
+                        filerow += 1;
+                        segment = 0;
                     }
-                    idx += 1;
                 }
-                ab_append(abuf, &mut "\x1b[39m".as_bytes().to_vec());
             }
             ab_append(abuf, &mut "\x1b[K".as_bytes().to_vec());
             ab_append(abuf, &mut "\r\n".as_bytes().to_vec());
@@ -451,38 +1123,160 @@ impl EditorConfig {
 
     }
 
+    fn editor_draw_panel(&self, abuf: &mut AppendBuffer) {
+        let panel = match &self.panel {
+            Some(panel) => panel,
+            None => return,
+        };
+        let cols = self.screen.cols as usize;
+        let mut title = self.theme.style("status_bar");
+        if title == Style::new() {
+            title.reverse = true;
+        }
+        let mut selected = self.theme.style("selection");
+        if selected.bg == self.theme.style("normal").bg {
+            selected.reverse = true;
+        }
+        let normal = self.theme.style("normal").escape(self.color_depth);
+        let mut heading = format!("{} ({}/{})", panel.title, panel.selected + 1, panel.items.len());
+        if panel.search.is_some() {
+            heading.push_str(" searching...");
+        }
+        let mut lines = vec![(title, heading, None)];
+        for (idx, item) in panel.visible() {
+            let style = if idx == panel.selected { selected } else { self.theme.style("normal") };
+            let label = item.label();
+            // The text ends the label.
+            let span = item.span.map(|(start, end)| {
+                let offset = label.len() - item.text.len();
+                (offset + start, offset + end)
+            });
+            lines.push((style, label, span));
+        }
+        for (style, text, span) in lines {
+            // The match keeps the row's background unless its style has one.
+            let mut marked = self.theme.style("match");
+            if marked.bg == Color::Default {
+                marked.bg = style.bg;
+                marked.reverse |= style.reverse;
+            }
+            marked.bold = true;
+            let mut line = style.escape(self.color_depth);
+            let mut width = 0;
+            for (idx, c) in text.char_indices().take(cols) {
+                match span {
+                    Some((start, _)) if start == idx => line.push_str(&marked.escape(self.color_depth)),
+                    Some((_, end)) if end == idx => line.push_str(&style.escape(self.color_depth)),
+                    _ => (),
+                }
+                line.push(c);
+                width += 1;
+            }
+            if span.map(|(_, end)| end >= text.len()).unwrap_or(false) {
+                line.push_str(&style.escape(self.color_depth));
+            }
+            line.push_str(&format!("{}{}\r\n", " ".repeat(cols.saturating_sub(width)), normal));
+            ab_append(abuf, &mut line.into_bytes());
+        }
+    }
+
+    fn editor_draw_row_segment(&mut self, abuf: &mut AppendBuffer, filerow: usize, start: usize,
+            width: usize) {
+        // The selection and text past max_line_length keep their colors on another background.
+        let selection = self.editor_selection_style(filerow);
+        let long_line = self.format.max_line_length.map(|limit| (limit, self.theme.style("long_line")));
+        let underlines = self.editor_diagnostic_spans(filerow);
+        let placeholders = self.editor_placeholder_spans(filerow);
+        let placeholder = self.theme.style("selection");
+        let erow = &self.erow[filerow];
+        let end = std::cmp::min(erow._rsize as usize, start + width);
+        let mut current: Style = self.theme.highlight_style(&self.current_color);
+        let mut idx = start;
+        while idx < end {
+            let hlk = match self.hl_marks.iter().find(|(r, c, _)| *r == filerow && *c == idx) {
+                Some((_, _, hlk)) => hlk.clone(),
+                None => erow.hl[idx].clone(),
+            };
+            let mut style: Style = self.theme.highlight_style(&hlk);
+            let overlay = match long_line {
+                Some((limit, long)) if idx >= limit => Some(long),
+                _ if placeholders.iter().any(|(start, end)| *start <= idx && idx < *end) => Some(placeholder),
+                _ => selection,
+            };
+            if let Some(overlay) = overlay {
+                style.bg = overlay.bg;
+                style.reverse |= overlay.reverse;
+            }
+            if underlines.iter().any(|(start, end)| *start <= idx && idx < *end) {
+                style.underline = true;
+            }
+            if style != current {
+                ab_append(abuf, &mut style.escape(self.color_depth).as_bytes().to_vec());
+                current = style;
+            }
+            self.current_color = hlk;
+            ab_append(abuf, &mut std::slice::from_ref(&erow.render[idx]).to_vec());
+            idx += 1;
+        }
+        if current != self.theme.style("normal") {
+            let normal = self.theme.style("normal").escape(self.color_depth);
+            ab_append(abuf, &mut normal.as_bytes().to_vec());
+        }
+        self.current_color = Highlight::NORMAL;
+    }
+
     fn editor_draw_message_bar(&self, abuf: &mut AppendBuffer){
+        let normal = self.theme.style("normal").escape(self.color_depth);
+        ab_append(abuf, &mut normal.as_bytes().to_vec());
         ab_append(abuf, &mut "\x1b[K".as_bytes().to_vec());
         let mut msg = self.status.message.clone();
         let mut msglen = self.status.message.len() as u16;
         if msglen > self.screen.cols {
             msglen = self.screen.cols;
         }
-        if Instant::now() - self.status.time < Duration::from_secs(5) {
+        let timeout = self.settings.message_timeout;
+        if timeout == 0 || Instant::now() - self.status.time < Duration::from_secs(timeout) {
             msg.truncate(msglen as usize);
             ab_append(abuf, &mut msg);
         }
     }
 
     fn editor_draw_status_bar(&mut self, abuf: &mut AppendBuffer){
-        ab_append(abuf, &mut "\x1b[7m".as_bytes().to_vec());
-        let mut status = self.filename.clone();
+        let mut style = self.theme.style("status_bar");
+        if style == Style::new() {
+            style.reverse = true;
+        }
+        ab_append(abuf, &mut style.escape(self.color_depth).as_bytes().to_vec());
+        let mut status = match &self.scratch {
+            Some(name) if self.filename.is_empty() => name.as_bytes().to_vec(),
+            _ => self.filename.clone(),
+        };
+        if !self.buffers.is_empty() {
+            status.append(&mut format!(" [{} buffers]", self.buffers.len() + 1).into_bytes());
+        }
         let mut line = format!(" - {} lines", self.numrows); 
         status.append(&mut line.as_bytes().to_vec());
         if self.dirty {
             status.append(&mut "(modified)".as_bytes().to_vec());
         }
-        let mut len = status.len() as u16;
-        if len > self.screen.cols {
-            status.truncate(self.screen.cols as usize);
-        }
-        ab_append(abuf, &mut status);
         let st = self.editor_syntax.syntax.clone();
         let ft: String = match st {
             Some(val) => val.file_type,
             None => String::from("no ft")
         };
-        line = format!("{} | {}/{}", ft, self.cp.y + 1, self.numrows);
+        line = format!("{} | {} | {}/{}", ft, self.indent.describe(), self.cp.y + 1, self.numrows);
+        // The breadcrumb gets the room left, keeping its innermost end.
+        let crumb = self.editor_breadcrumb();
+        let room = (self.screen.cols as usize).saturating_sub(status.len() + line.len() + 4);
+        if !crumb.is_empty() && room > 0 {
+            let skip = crumb.chars().count().saturating_sub(room);
+            status.append(&mut format!(" | {}", crumb.chars().skip(skip).collect::<String>()).into_bytes());
+        }
+        let mut len = status.len() as u16;
+        if len > self.screen.cols {
+            status.truncate(self.screen.cols as usize);
+        }
+        ab_append(abuf, &mut status);
         status.append(&mut line.as_bytes().to_vec());
         let rlen = status.len() as u16;
         while len < self.screen.cols {
@@ -499,99 +1293,1978 @@ impl EditorConfig {
     }
     
     fn editor_insert_new_line(&mut self){
+        self.mark = None;
+        self.auto_closers.clear();
         let mut at = self.cp.y;
-        if self.cp.x == 0 {
-            self.editor_insert_row(&at, &mut "".as_bytes().to_vec(), 0)
-        }else{
-            at += 1;
-            let mut row = self.erow[self.cp.y as usize].chars.split_off(self.cp.x as usize);
-            self.erow[self.cp.y as usize].size =
-                (self.erow[self.cp.y as usize].chars.len() - 1) as u16;
-            self.erow[self.cp.y as usize].editor_update_row(&mut self.editor_syntax);
-            let size = row.len() as u16;
-            self.editor_insert_row(&at, &mut row,  size)
+        if self.cp.x == 0 || !self.settings.auto_indent {
+            if self.cp.x == 0 {
+                self.editor_insert_row(&at, &mut "".as_bytes().to_vec(), 0)
+            }else{
+                at += 1;
+                let mut row = self.editor_split_row();
+                let size = row.len() as u16;
+                self.editor_insert_row(&at, &mut row,  size)
+            }
+            self.cp.y += 1;
+            self.cp.x = 0;
+            self.dirty = true;
+            return;
+        }
+        let filerow = self.cp.y as usize;
+        let chars = &self.erow[filerow].chars;
+        let mut indent = chars[..leading_whitespace(chars).min(self.cp.x as usize)].to_vec();
+        let opener = self.editor_last_code_char(filerow, self.cp.x as usize);
+        let mut tail = self.editor_split_row();
+        tail.drain(..leading_whitespace(&tail));
+        let indents = match (&opener, &self.editor_syntax.syntax) {
+            (Some(c), Some(syntax)) => syntax.indent_after.contains(c),
+            _ => false,
+        };
+        if indents {
+            let closer = matches!((opener, tail.first()),
+                (Some(b'{'), Some(b'}')) | (Some(b'('), Some(b')')) | (Some(b'['), Some(b']')));
+            if closer {
+                let mut row = indent.clone();
+                row.extend_from_slice(&tail);
+                let size = row.len() as u16;
+                self.editor_insert_row(&(at + 1), &mut row, size);
+                tail.clear();
+            }
+            indent.extend(self.editor_indent_unit());
         }
+        self.cp.x = indent.len() as u16;
+        indent.extend_from_slice(&tail);
+        let size = indent.len() as u16;
+        at += 1;
+        self.editor_insert_row(&at, &mut indent, size);
         self.cp.y += 1;
-        self.cp.x = 0;
         self.dirty = true;
     }
-    
-    fn editor_insert_char(&mut self, c: &u8){
-        if self.cp.y == self.numrows {
-            let at = self.cp.y;
-            self.editor_insert_row(&at, &mut "".as_bytes().to_vec(), 0);
+
+    // Cuts the current row at the cursor and returns the part after it.
+    fn editor_split_row(&mut self) -> Vec<u8> {
+        let filerow = self.cp.y as usize;
+        let mut row = self.erow[filerow].chars.split_off(self.cp.x as usize);
+        row.pop();
+        self.erow[filerow].chars.push(b'\0');
+        self.erow[filerow].size = (self.erow[filerow].chars.len() - 1) as u16;
+        self.erow[filerow].editor_update_row(self.indent.tab_stop);
+        self.editor_invalidate_syntax(filerow);
+        row
+    }
+
+    // The last character before `cx` that is not blank and not in a comment or string.
+    fn editor_last_code_char(&self, filerow: usize, cx: usize) -> Option<u8> {
+        let erow = &self.erow[filerow];
+        let mut idx = cx;
+        while idx > 0 {
+            idx -= 1;
+            let c = erow.chars[idx];
+            if c == b' ' || c == b'\t' {
+                continue;
+            }
+            let rx = editor_row_cxtorx(&erow.chars, idx, self.indent.tab_stop) as usize;
+            let hl = erow.hl.get(rx);
+            if is_comment(hl) {
+                continue;
+            }
+            return if is_text(hl) { None } else { Some(c) };
         }
-        let mut at: i16 = self.cp.x as i16; 
-        self.erow[self.cp.y as usize].editor_row_insert_character(&mut at, *c, &mut self.editor_syntax);
-        self.cp.x += 1;
-        self.dirty = true;
+        None
     }
-    
+
+    fn editor_indent_unit(&self) -> Vec<u8> {
+        if self.indent.expand_tabs {
+            vec![b' '; self.indent.indent_size as usize]
+        }else{
+            vec![b'\t']
+        }
+    }
+
+    // Replaces the leading whitespace of a row, keeping the cursor on the same character.
+    fn editor_set_indent(&mut self, filerow: usize, indent: &[u8]) {
+        let old = leading_whitespace(&self.erow[filerow].chars);
+        self.erow[filerow].chars.splice(..old, indent.iter().cloned());
+        self.erow[filerow].size = (self.erow[filerow].chars.len() - 1) as u16;
+        self.erow[filerow].editor_update_row(self.indent.tab_stop);
+        self.editor_invalidate_syntax(filerow);
+        if self.cp.y as usize == filerow {
+            self.cp.x = (self.cp.x as usize + indent.len()).saturating_sub(old) as u16;
+        }
+        self.dirty = true;
+    }
+
+    // A closing bracket typed on a blank row lines up with the row of its partner.
+    fn editor_dedent_closer(&mut self, c: u8) {
+        let filerow = self.cp.y as usize;
+        let cx = self.cp.x as usize - 1;
+        let dedents = match &self.editor_syntax.syntax {
+            Some(syntax) => syntax.dedent_on.contains(&c),
+            None => false,
+        };
+        if !dedents || leading_whitespace(&self.erow[filerow].chars) != cx || cx == 0 {
+            return;
+        }
+        self.editor_highlight_upto(filerow, None);
+        let rx = editor_row_cxtorx(&self.erow[filerow].chars, cx, self.indent.tab_stop) as usize;
+        let indent = match match_bracket(&self.erow, filerow, rx, RILO_BRACKET_SCAN_ROWS) {
+            Some(BracketMatch::Matched(mrow, _)) => {
+                let chars = &self.erow[mrow].chars;
+                chars[..leading_whitespace(chars)].to_vec()
+            },
+            _ => {
+                let mut indent = self.erow[filerow].chars[..cx].to_vec();
+                let unit = self.editor_indent_unit();
+                if indent.ends_with(&unit) {
+                    indent.truncate(indent.len() - unit.len());
+                }else{
+                    indent.pop();
+                }
+                indent
+            },
+        };
+        self.editor_set_indent(filerow, &indent);
+    }
+    
+    fn editor_toggle_mark(&mut self) {
+        if self.mark.is_some() {
+            self.mark = None;
+            self.editor_set_status_message(String::from("Mark cleared"));
+        }else{
+            self.mark = Some(self.cp.y.min(self.numrows.saturating_sub(1)));
+            self.editor_set_status_message(String::from("Mark set"));
+        }
+    }
+
+    // The rows between the mark and the cursor, or the cursor row alone.
+    fn editor_selected_rows(&self) -> (usize, usize) {
+        let cy = self.cp.y as usize;
+        match self.mark {
+            Some(mark) => {
+                let mark = mark as usize;
+                let last = cy.max(mark).min((self.numrows as usize).saturating_sub(1));
+                (cy.min(mark), last)
+            },
+            None => (cy, cy),
+        }
+    }
+
+    fn editor_selection_style(&self, filerow: usize) -> Option<Style> {
+        self.mark?;
+        let (first, last) = self.editor_selected_rows();
+        if filerow < first || filerow > last {
+            return None;
+        }
+        let mut style = self.theme.style("selection");
+        if style.bg == self.theme.style("normal").bg {
+            style.reverse = true;
+        }
+        Some(style)
+    }
+
+    fn editor_indent_rows(&mut self, first: usize, last: usize) {
+        let unit = self.editor_indent_unit();
+        for filerow in first..(last + 1).min(self.numrows as usize) {
+            let chars = &self.erow[filerow].chars;
+            if leading_whitespace(chars) == chars.len() - 1 {
+                continue;
+            }
+            let mut indent = unit.clone();
+            indent.extend_from_slice(&chars[..leading_whitespace(chars)]);
+            self.editor_set_indent(filerow, &indent);
+        }
+    }
+
+    // Removes a tab or up to an indent of spaces from the start of each row.
+    fn editor_dedent_rows(&mut self, first: usize, last: usize) {
+        for filerow in first..(last + 1).min(self.numrows as usize) {
+            let chars = &self.erow[filerow].chars;
+            let old = leading_whitespace(chars);
+            let cut = match chars.first() {
+                Some(b'\t') => 1,
+                _ => chars[..old].iter().take(self.indent.indent_size as usize).take_while(|c| **c == b' ').count(),
+            };
+            if cut > 0 {
+                let indent = chars[cut..old].to_vec();
+                self.editor_set_indent(filerow, &indent);
+            }
+        }
+    }
+
+    fn editor_insert_char(&mut self, c: &u8){
+        self.mark = None;
+        if self.cp.y == self.numrows {
+            let at = self.cp.y;
+            self.editor_insert_row(&at, &mut "".as_bytes().to_vec(), 0);
+        }
+        if self.settings.auto_pairs && self.editor_skip_closer(*c) {
+            return;
+        }
+        let closer = if self.settings.auto_pairs { self.editor_pair_closer(*c) } else { None };
+        let mut at: i16 = self.cp.x as i16; 
+        self.erow[self.cp.y as usize].editor_row_insert_character(&mut at, *c, self.indent.tab_stop);
+        self.editor_shift_closers(self.cp.y, self.cp.x, 1);
+        self.editor_invalidate_syntax(self.cp.y as usize);
+        self.cp.x += 1;
+        self.dirty = true;
+        if let Some(closer) = closer {
+            let mut at: i16 = self.cp.x as i16;
+            self.erow[self.cp.y as usize].editor_row_insert_character(&mut at, closer, self.indent.tab_stop);
+            self.editor_shift_closers(self.cp.y, self.cp.x, 1);
+            self.auto_closers.push((self.cp.y, self.cp.x));
+        }
+        if self.settings.auto_indent {
+            self.editor_dedent_closer(*c);
+        }
+    }
+
+    // Typing the closer an opener inserted steps over it.
+    fn editor_skip_closer(&mut self, c: u8) -> bool {
+        let pos = (self.cp.y, self.cp.x);
+        if self.erow[pos.0 as usize].chars[pos.1 as usize] != c || !self.auto_closers.contains(&pos) {
+            return false;
+        }
+        self.auto_closers.retain(|p| *p != pos);
+        self.cp.x += 1;
+        true
+    }
+
+    // The closer to insert after `c`. Pairs are only added before blanks,
+    // closers and the end of the row, and not in strings and comments. A quote
+    // after a word is an apostrophe, and in Rust some quotes start lifetimes.
+    fn editor_pair_closer(&mut self, c: u8) -> Option<u8> {
+        let closer = PAIRS.iter().find(|(open, _)| *open == c)?.1;
+        let filerow = self.cp.y as usize;
+        let cx = self.cp.x as usize;
+        let chars = &self.erow[filerow].chars;
+        let next = chars[cx];
+        if next != b'\0' && !next.is_ascii_whitespace() && !b")]},;:".contains(&next) {
+            return None;
+        }
+        let prev = if cx > 0 { chars[cx - 1] } else { b' ' };
+        if c == closer && is_ident_char(prev) {
+            return None;
+        }
+        let lifetimes = match &self.editor_syntax.syntax {
+            Some(syntax) => syntax.flags.contains(HLFlags::HLF_LIFETIMES),
+            None => false,
+        };
+        if c == b'\'' && lifetimes && starts_lifetime(&chars[..cx], &chars[cx..]) {
+            return None;
+        }
+        if self.editor_in_string_or_comment(filerow, cx) {
+            return None;
+        }
+        Some(closer)
+    }
+
+    // Whether `cx` is inside a string or a comment, going by the highlighting of
+    // the characters around it.
+    fn editor_in_string_or_comment(&mut self, filerow: usize, cx: usize) -> bool {
+        self.editor_highlight_upto(filerow, None);
+        let erow = &self.erow[filerow];
+        let tab_stop = self.indent.tab_stop;
+        let rx = editor_row_cxtorx(&erow.chars, cx, tab_stop) as usize;
+        let next = erow.hl.get(rx).filter(|_| cx < erow.size as usize);
+        if cx == 0 {
+            return is_text(next);
+        }
+        let prev = &erow.hl[rx - 1];
+        match prev {
+            Highlight::COMMENT | Highlight::DOCCOMMENT | Highlight::ESCAPE => true,
+            Highlight::MLCOMMENT => {
+                let end = match &self.editor_syntax.syntax {
+                    Some(syntax) => syntax.multiline_comment_end.clone(),
+                    None => String::new(),
+                };
+                next == Some(prev) || end.is_empty() || !erow.chars[..cx].ends_with(end.as_bytes())
+            },
+            Highlight::STRING | Highlight::CHAR => {
+                if next == Some(prev) {
+                    return true;
+                }
+                // A quote closes the string when the string started before it.
+                let quote = erow.chars[cx - 1];
+                let mut start = cx - 1;
+                while start > 0 && is_text(erow.hl.get(editor_row_cxtorx(&erow.chars, start - 1, tab_stop) as usize)) {
+                    start -= 1;
+                }
+                !(PAIRS.iter().any(|(open, close)| open == close && *open == quote) &&
+                    start < cx - 1 && erow.chars[start] == quote)
+            },
+            _ => false,
+        }
+    }
+
+    fn editor_shift_closers(&mut self, row: u32, at: u16, delta: i16) {
+        for pos in self.auto_closers.iter_mut() {
+            if pos.0 == row && pos.1 >= at {
+                pos.1 = (pos.1 as i16 + delta) as u16;
+            }
+        }
+    }
+
+    fn editor_insert_soft_tab(&mut self){
+        let size = self.indent.indent_size;
+        let mut rx = 0;
+        if self.cp.y < self.numrows {
+            rx = editor_row_cxtorx(&self.erow[self.cp.y as usize].chars, self.cp.x as usize, self.indent.tab_stop);
+        }
+        let mut spaces = size - rx % size;
+        while spaces > 0 {
+            self.editor_insert_char(&b' ');
+            spaces -= 1;
+        }
+    }
+    
     fn editor_row_append(&mut self, at: usize, ap_vec: &mut Vec<u8>){
         let del_pt: usize = self.erow[at].size as usize;
         self.erow[at].chars.remove(del_pt);
         self.erow[at].chars.append(ap_vec);
-        self.erow[at].size = self.erow[at].chars.len() as u16;
+        self.erow[at].size = (self.erow[at].chars.len() - 1) as u16;
+    }
+
+    fn editor_delete_row(&mut self, at: usize){
+        if at >= self.numrows as usize{
+            return;
+        }
+        self.erow.remove(at);
+        self.numrows -= 1;
+        self.folds.delete_row(at as u32);
+        self.editor_invalidate_syntax(at);
+        self.dirty = true;
+    }
+
+    fn editor_delete_char(&mut self, key: &Function){
+        self.mark = None;
+        if self.cp.y == self.numrows || (self.cp.x == 0 && self.cp.y == 0) {
+            return;
+        }
+        let filerow = self.cp.y as usize;
+        let cx = self.cp.x as usize;
+        if self.cp.x > 0 {
+            let inserted = self.auto_closers.contains(&(self.cp.y, cx as u16));
+            let (from, to) = deleted_columns(&self.erow[filerow].chars, cx, key, &self.indent, inserted);
+            for _ in from..to {
+                let mut at: i16 = from as i16;
+                self.erow[filerow].editor_row_delete_char(&mut at, self.indent.tab_stop);
+            }
+            let row = self.cp.y;
+            self.auto_closers.retain(|pos| pos.0 != row || !(from..to).contains(&(pos.1 as usize)));
+            self.editor_shift_closers(row, from as u16, -((to - from) as i16));
+            self.editor_invalidate_syntax(filerow);
+            self.cp.x = from as u16;
+        }else{
+            self.cp.x = self.erow[(self.cp.y - 1) as usize].size;
+            let mut temp_row = self.erow[self.cp.y as usize].chars.clone();
+            self.editor_row_append((self.cp.y - 1 )as usize, &mut temp_row);
+            self.editor_delete_row(self.cp.y as usize);
+            self.cp.y -= 1;
+            self.erow[self.cp.y as usize].editor_update_row(self.indent.tab_stop);
+            self.editor_invalidate_syntax(self.cp.y as usize);
+        }
+        self.dirty = true;
+    }
+    
+    fn editor_rows_to_string(&mut self) -> Vec<u8> {
+        let rows: Vec<&[u8]> = self.erow.iter().map(|row| &row.chars[..row.size as usize]).collect();
+        write_rows(&rows, &self.format)
+    }
+
+    fn editor_trim_trailing_whitespace(&mut self) {
+        for filerow in 0..self.numrows as usize {
+            let chars = &self.erow[filerow].chars;
+            let size = chars.len() - 1;
+            let trailing = chars[..size].iter().rev().take_while(|c| **c == b' ' || **c == b'\t').count();
+            if trailing == 0 {
+                continue;
+            }
+            self.erow[filerow].chars.drain(size - trailing..size);
+            self.erow[filerow].size = (size - trailing) as u16;
+            self.erow[filerow].editor_update_row(self.indent.tab_stop);
+            self.editor_invalidate_syntax(filerow);
+            if self.cp.y as usize == filerow {
+                self.cp.x = self.cp.x.min(self.erow[filerow].size);
+            }
+        }
+    }
+    
+    fn editor_save(&mut self) {
+        if self.filename.is_empty() {
+            let cb: Option<fn(&mut EditorConfig, &String, &EditorKey)> = None;
+            self.filename = self.editor_prompt(String::from("Save as: {} (ESC to cancel)"), cb);
+            if self.filename.len() == 0 { 
+                self.editor_set_status_message("Save aborted".to_string());
+                return;
+            }
+            self.editor_select_syntax_highlight();
+        }
+        let path = String::from_utf8(self.filename.clone()).unwrap();
+        // Save hooks: trimming, then the formatter. The final newline is added
+        // when the rows are joined.
+        if self.format.trim_trailing_whitespace {
+            self.editor_trim_trailing_whitespace();
+        }
+        let mut format_error = None;
+        if self.settings.format_on_save {
+            format_error = self.editor_run_formatter().err();
+        }
+        let w_vec: Vec<u8> = self.editor_rows_to_string();
+        let len = w_vec.len();
+        if let Err(e) = std::fs::write(path, w_vec) {
+            self.editor_set_status_message(format!("Can't save! I/O error: {}", e));
+            return;
+        }
+        self.editor_lsp_sync();
+        if let (Some(idx), Some(uri)) = (self.editor_lsp_index(), self.editor_lsp_uri()) {
+            self.lsp[idx].notify("textDocument/didSave", Json::object(vec![
+                ("textDocument", Json::object(vec![("uri", Json::from(uri))])),
+            ]));
+        }
+        match format_error {
+            Some(e) => self.editor_set_status_message(format!("Formatter failed: {} ({} bytes written)", e, len)),
+            None => self.editor_set_status_message(format!("{} bytes written to disk", len)),
+        }
+        self.dirty = false;
+        self.quit_times = self.settings.quit_times;
+    }
+    
+    fn editor_formatter(&self) -> Option<String> {
+        let file_type = &self.editor_syntax.syntax.as_ref()?.file_type;
+        self.settings.formatters.iter().find(|(ft, _)| ft == file_type).map(|(_, cmd)| cmd.clone())
+    }
+
+    // Pipes the buffer through the formatter of its file type. On failure the
+    // buffer is left as it was.
+    fn editor_run_formatter(&mut self) -> Result<(), String> {
+        let command = match self.editor_formatter() {
+            Some(command) => command,
+            None => return Ok(()),
+        };
+        let rows: Vec<&[u8]> = self.erow.iter().map(|row| &row.chars[..row.size as usize]).collect();
+        let mut input = FileFormat::new();
+        input.final_newline = true;
+        let output = run_filter(&command, &write_rows(&rows, &input))?;
+        let rows = read_rows(&output, Some(Charset::Utf8), &mut input);
+        self.editor_replace_rows(rows);
+        Ok(())
+    }
+
+    // Replaces the whole buffer, keeping the cursor on the same line when it is
+    // in the unchanged part at the start or the end of the buffer.
+    fn editor_replace_rows(&mut self, rows: Vec<Vec<u8>>) {
+        let old: Vec<&[u8]> = self.erow.iter().map(|row| &row.chars[..row.size as usize]).collect();
+        if old.len() == rows.len() && old.iter().zip(rows.iter()).all(|(a, b)| *a == &b[..]) {
+            return;
+        }
+        let (prefix, suffix) = unchanged_rows(&old, &rows);
+        let count = old.len() - prefix - suffix;
+        let cy = self.cp.y as usize;
+        let new_cy = if cy < prefix || cy >= old.len() {
+            cy
+        }else if cy >= old.len() - suffix {
+            rows.len() - (old.len() - cy)
+        }else{
+            cy.min(rows.len().saturating_sub(1))
+        };
+        let end = rows.len() - suffix;
+        let rows = rows.into_iter().take(end).skip(prefix).collect();
+        self.editor_splice_rows(prefix, count, rows, new_cy as u32, self.cp.x);
+    }
+
+    // Replaces `count` rows at `at` with `rows` and puts the cursor at (`cy`,
+    // `cx`) or the nearest place. Folds outside the rows that changed stay closed.
+    fn editor_splice_rows(&mut self, at: usize, count: usize, rows: Vec<Vec<u8>>, cy: u32, cx: u16) {
+        self.folds.replace_rows(at as u32, (at + count) as u32, rows.len() as i64 - count as i64);
+        let added = rows.len();
+        let rows: Vec<Erow> = rows.into_iter().map(|row| {
+            let size = row.len() as u16;
+            self.editor_new_row(at, row, size)
+        }).collect();
+        self.erow.splice(at..at + count, rows);
+        self.numrows = self.erow.len() as u32;
+        // The row after them was highlighted after other rows.
+        if let Some(next) = self.erow.get_mut(at + added) {
+            next.hl_stale = true;
+        }
+        self.editor_invalidate_syntax(at);
+        self.cp.y = cy.min(self.numrows);
+        self.cp.x = match self.erow.get(self.cp.y as usize) {
+            Some(row) => cx.min(row.size),
+            None => 0,
+        };
+        self.mark = None;
+        self.snippet = None;
+        self.auto_closers.clear();
+        self.dirty = true;
+    }
+
+    // Records the edits made since the last undo step as a new one.
+    fn editor_undo_record(&mut self) {
+        self.undo.record(&self.erow);
+    }
+
+    fn editor_undo(&mut self, redo: bool) {
+        self.editor_undo_record();
+        let step = if redo { self.undo.redo(self.cp.y, self.cp.x) } else { self.undo.undo(self.cp.y, self.cp.x) };
+        match step {
+            Some(step) => self.editor_splice_rows(step.at, step.count, step.rows, step.cy, step.cx),
+            None => self.editor_set_status_message(String::from(if redo { "Nothing to redo" } else { "Nothing to undo" })),
+        }
+    }
+
+    // Comments the current row or the marked rows out, or back in when they are
+    // all comments. The marker goes at the smallest indentation of the rows.
+    fn editor_toggle_comment(&mut self) {
+        let (start, end) = match &self.editor_syntax.syntax {
+            Some(syntax) if !syntax.singleline_comment_start.is_empty() => {
+                (syntax.singleline_comment_start.clone().into_bytes(), Vec::new())
+            },
+            Some(syntax) if !syntax.multiline_comment_start.is_empty() => {
+                (syntax.multiline_comment_start.clone().into_bytes(), syntax.multiline_comment_end.clone().into_bytes())
+            },
+            _ => {
+                self.editor_set_status_message(String::from("No comment syntax for this file type"));
+                return;
+            },
+        };
+        if self.numrows == 0 {
+            return;
+        }
+        let (first, last) = self.editor_selected_rows();
+        let last = last.min(self.numrows as usize - 1);
+        let rows: Vec<usize> = (first..=last)
+            .filter(|idx| leading_whitespace(&self.erow[*idx].chars) < self.erow[*idx].size as usize)
+            .collect();
+        let commented = !rows.is_empty() && rows.iter().all(|idx| {
+            let chars = &self.erow[*idx].chars[..self.erow[*idx].size as usize];
+            let text = &chars[leading_whitespace(chars)..];
+            text.starts_with(&start) && text.ends_with(&end) && text.len() >= start.len() + end.len()
+        });
+        let column = rows.iter().map(|idx| leading_whitespace(&self.erow[*idx].chars)).min().unwrap_or(0);
+        for filerow in rows {
+            let mut chars = self.erow[filerow].chars[..self.erow[filerow].size as usize].to_vec();
+            let (at, delta) = if commented {
+                let at = leading_whitespace(&chars);
+                let mut open = start.len();
+                if chars.get(at + open) == Some(&b' ') {
+                    open += 1;
+                }
+                let mut close = end.len();
+                if close > 0 && chars.len() > at + open + close && chars[chars.len() - close - 1] == b' ' {
+                    close += 1;
+                }
+                chars.truncate(chars.len() - close);
+                chars.drain(at..at + open);
+                (at, -(open as isize))
+            }else{
+                let mut open = start.clone();
+                open.push(b' ');
+                if !end.is_empty() {
+                    chars.push(b' ');
+                    chars.extend_from_slice(&end);
+                }
+                chars.splice(column..column, open.iter().cloned());
+                (column, open.len() as isize)
+            };
+            chars.push(b'\0');
+            self.erow[filerow].chars = chars;
+            self.erow[filerow].size = (self.erow[filerow].chars.len() - 1) as u16;
+            self.erow[filerow].editor_update_row(self.indent.tab_stop);
+            self.editor_invalidate_syntax(filerow);
+            if self.cp.y as usize == filerow && self.cp.x as usize >= at {
+                let cx = (self.cp.x as isize + delta).max(at as isize) as u16;
+                self.cp.x = cx.min(self.erow[filerow].size);
+            }
+        }
+        self.dirty = true;
+    }
+
+    fn editor_fold_region(&mut self, filerow: usize) -> Option<usize> {
+        self.editor_highlight_upto(filerow + RILO_BRACKET_SCAN_ROWS, None);
+        fold_region(&self.erow, filerow, self.settings.fold_method, self.indent.tab_stop as usize)
+    }
+
+    // Closes the region starting on the cursor row, or else the nearest open
+    // one around it, and moves the cursor to its first row.
+    fn editor_fold(&mut self) {
+        let cy = self.cp.y as usize;
+        let lowest = cy.saturating_sub(RILO_BRACKET_SCAN_ROWS);
+        for start in (lowest..=cy).rev() {
+            if start >= self.numrows as usize || self.folds.is_hidden(start as u32) {
+                continue;
+            }
+            let end = match self.editor_fold_region(start) {
+                Some(end) if end >= cy => end,
+                _ => continue,
+            };
+            let fold = Fold { start: start as u32, end: end as u32 };
+            if self.folds.at(fold.start).map(|f| f.end >= fold.end).unwrap_or(false) {
+                continue;
+            }
+            self.folds.add(fold);
+            self.cp.y = fold.start;
+            self.cp.x = self.cp.x.min(self.erow[start].size);
+            return;
+        }
+        self.editor_set_status_message(String::from("Nothing to fold here"));
+    }
+
+    fn editor_unfold(&mut self) {
+        match self.folds.at(self.cp.y) {
+            Some(fold) => self.folds.remove(fold),
+            None => self.editor_set_status_message(String::from("No fold here")),
+        }
+    }
+
+    fn editor_toggle_fold(&mut self) {
+        if self.folds.at(self.cp.y).is_some() {
+            self.editor_unfold();
+        }else{
+            self.editor_fold();
+        }
+    }
+
+    // Opens every fold, or closes every outermost region when none is closed.
+    fn editor_toggle_all_folds(&mut self) {
+        if !self.folds.is_empty() {
+            self.folds.clear();
+            return;
+        }
+        let mut filerow = 0;
+        while filerow < self.numrows as usize {
+            match self.editor_fold_region(filerow) {
+                Some(end) => {
+                    self.folds.add(Fold { start: filerow as u32, end: end as u32 });
+                    filerow = end + 1;
+                },
+                None => filerow += 1,
+            }
+        }
+        if let Some(fold) = self.folds.hiding(self.cp.y) {
+            self.cp.y = fold.start;
+            self.cp.x = self.cp.x.min(self.erow[fold.start as usize].size);
+        }
+    }
+
+    // A closed fold's row ends with the closer it hides and its size.
+    fn editor_fold_summary(&self, fold: Fold) -> String {
+        let erow = &self.erow[fold.end as usize];
+        let closer = if is_closer_row(erow) {
+            let chars = &erow.chars[..erow.size as usize];
+            format!("{} ", String::from_utf8_lossy(&chars[leading_whitespace(chars)..]))
+        }else{
+            String::new()
+        };
+        let hidden = fold.end - fold.start;
+        format!(" ... {}[{} line{}]", closer, hidden, if hidden == 1 { "" } else { "s" })
+    }
+
+    // Gathers the items found on the rows when they were highlighted, with
+    // the rows they span, after the buffer changed: Rust items by their
+    // keywords, other file types by the `symbol` rules of their syntax.
+    fn editor_update_symbols(&mut self) {
+        if self.symbols.is_some() && !self.symbols_stale {
+            return;
+        }
+        let mut symbols: Vec<Symbol> = Vec::new();
+        let mut open: Vec<usize> = Vec::new();
+        for (row, erow) in self.erow.iter().enumerate() {
+            let (kind, name) = match &erow.symbol {
+                Some(found) => found.clone(),
+                None => continue,
+            };
+            let end = fold_region(&self.erow, row, self.settings.fold_method, self.indent.tab_stop as usize)
+                .unwrap_or(row);
+            open.retain(|end| *end >= row);
+            symbols.push(Symbol { kind, name, row, end, depth: open.len() });
+            open.push(end);
+        }
+        self.symbols = Some(symbols);
+        self.symbols_stale = false;
+    }
+
+    // Lists the symbols of the buffer to pick one to jump to, starting at the
+    // one the cursor is in.
+    fn editor_outline(&mut self) {
+        self.editor_highlight_upto(usize::MAX, None);
+        self.editor_update_symbols();
+        let symbols = self.symbols.as_deref().unwrap_or(&[]);
+        if symbols.is_empty() {
+            self.editor_set_status_message(String::from("No symbols"));
+            return;
+        }
+        let cy = self.cp.y as usize;
+        let selected = symbols.iter().rposition(|s| s.row <= cy && cy <= s.end)
+            .or_else(|| symbols.iter().rposition(|s| s.row <= cy))
+            .unwrap_or(0);
+        self.editor_fill_picker(Picker::Symbols { query: String::new() }, selected);
+    }
+
+    // Lists the files of the project of the buffer to open one of them.
+    fn editor_file_picker(&mut self) {
+        let root = project_root(&self.editor_file_dir());
+        let files = project_files(&root);
+        if files.is_empty() {
+            self.editor_set_status_message(format!("No files under {}", root.display()));
+            return;
+        }
+        self.editor_fill_picker(Picker::Files { query: String::new(), root, files }, 0);
+    }
+
+    fn editor_fill_picker(&mut self, picker: Picker, selected: usize) {
+        let (lines, rows) = match &picker {
+            Picker::Symbols { query } => {
+                let symbols = self.symbols.as_deref().unwrap_or(&[]);
+                let matches = filter_symbols(symbols, query);
+                let lines = matches.iter().map(|idx| {
+                    let symbol = &symbols[*idx];
+                    format!("{}{}", "  ".repeat(symbol.depth), symbol.label())
+                }).collect();
+                (lines, matches.iter().map(|idx| symbols[*idx].row).collect())
+            },
+            Picker::Files { query, files, .. } => {
+                let matches = rank_files(query, files);
+                (matches.iter().map(|idx| files[*idx].clone()).collect(), matches)
+            },
+        };
+        self.popup = Some(Popup { lines, completions: Vec::new(), selected, picker: Some(picker), rows });
+    }
+
+    // Typing filters the picker and Enter takes the selected item.
+    fn editor_picker_key(&mut self, key: &EditorKey) -> bool {
+        let mut popup = match self.popup.take() {
+            Some(popup) => popup,
+            None => return false,
+        };
+        let mut picker = match popup.picker.take() {
+            Some(picker) => picker,
+            None => return false,
+        };
+        let query = match &mut picker {
+            Picker::Symbols { query } | Picker::Files { query, .. } => query,
+        };
+        let count = popup.rows.len().max(1);
+        match key {
+            EditorKey::Arrow(Arrow::Down) | EditorKey::Arrow(Arrow::Up) | EditorKey::Else(0x0e) | EditorKey::Else(0x10) => {
+                let down = matches!(key, EditorKey::Arrow(Arrow::Down) | EditorKey::Else(0x0e));
+                popup.selected = (popup.selected + if down { 1 } else { count - 1 }) % count;
+                popup.picker = Some(picker);
+                self.popup = Some(popup);
+            },
+            EditorKey::Else(b'\r') | EditorKey::Else(b'\t') => {
+                match (popup.rows.get(popup.selected), picker) {
+                    (Some(row), Picker::Symbols { .. }) => {
+                        let col = leading_whitespace(&self.erow[*row].chars) as u32 + 1;
+                        self.editor_goto_position(*row as u32 + 1, col);
+                    },
+                    (Some(idx), Picker::Files { root, files, .. }) => {
+                        let path = root.join(&files[*idx]);
+                        let cwd = std::env::current_dir().unwrap_or_default();
+                        let file = path.strip_prefix(&cwd).unwrap_or(&path).to_string_lossy().into_owned();
+                        self.editor_goto_file(&file);
+                    },
+                    _ => (),
+                }
+            },
+            EditorKey::Else(b'\x1b') => (),
+            EditorKey::Function(Function::Backspace) | EditorKey::Else(0x08) => {
+                query.pop();
+                self.editor_fill_picker(picker, 0);
+            },
+            EditorKey::Else(c) if *c >= b' ' && *c < 127 => {
+                query.push(*c as char);
+                self.editor_fill_picker(picker, 0);
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    // The items the cursor is in, for the status bar, as last indexed.
+    fn editor_breadcrumb(&self) -> String {
+        let symbols = self.symbols.as_deref().unwrap_or(&[]);
+        let labels: Vec<String> = enclosing_symbols(symbols, self.cp.y as usize).iter().map(|s| s.label()).collect();
+        labels.join(" > ")
+    }
+
+    // Exchanges the buffer on screen with `buffer`.
+    fn editor_swap_buffer(&mut self, buffer: &mut EditorBuffer) {
+        self.editor_undo_record();
+        self.editor_lsp_sync();
+        mem::swap(&mut self.cp, &mut buffer.cp);
+        mem::swap(&mut self.off, &mut buffer.off);
+        mem::swap(&mut self.erow, &mut buffer.erow);
+        mem::swap(&mut self.numrows, &mut buffer.numrows);
+        mem::swap(&mut self.filename, &mut buffer.filename);
+        mem::swap(&mut self.scratch, &mut buffer.scratch);
+        mem::swap(&mut self.dirty, &mut buffer.dirty);
+        mem::swap(&mut self.editor_syntax, &mut buffer.editor_syntax);
+        mem::swap(&mut self.syntax_override, &mut buffer.syntax_override);
+        mem::swap(&mut self.highlighter, &mut buffer.highlighter);
+        mem::swap(&mut self.hl_stale_from, &mut buffer.hl_stale_from);
+        mem::swap(&mut self.indent, &mut buffer.indent);
+        mem::swap(&mut self.format, &mut buffer.format);
+        mem::swap(&mut self.mark, &mut buffer.mark);
+        mem::swap(&mut self.diagnostics, &mut buffer.diagnostics);
+        mem::swap(&mut self.lsp_version, &mut buffer.lsp_version);
+        mem::swap(&mut self.lsp_changed, &mut buffer.lsp_changed);
+        mem::swap(&mut self.undo, &mut buffer.undo);
+        mem::swap(&mut self.folds, &mut buffer.folds);
+        mem::swap(&mut self.symbols, &mut buffer.symbols);
+        mem::swap(&mut self.symbols_stale, &mut buffer.symbols_stale);
+        self.undo_typing = false;
+        self.popup = None;
+        self.snippet = None;
+        self.auto_closers.clear();
+        self.hl_marks.clear();
+        self.last_match = -1;
+        self.saved_hl_line = -1;
+    }
+
+    fn editor_empty_buffer(&self) -> EditorBuffer {
+        EditorBuffer {
+            cp: CurrentPosition{x: 0, y: 0},
+            off: Offset{ row: 0 ,col: 0},
+            erow: Vec::new(),
+            numrows: 0,
+            filename: Vec::new(),
+            scratch: None,
+            dirty: false,
+            editor_syntax: EditorSyntaxInf {syntax:None},
+            syntax_override: None,
+            highlighter: new_highlighter(&EditorSyntaxInf {syntax:None}, false),
+            hl_stale_from: 0,
+            indent: IndentStyle::new(self.settings.tab_stop, self.settings.expand_tabs),
+            format: FileFormat::new(),
+            mark: None,
+            diagnostics: Vec::new(),
+            lsp_version: 0,
+            lsp_changed: false,
+            undo: UndoJournal::new(),
+            folds: Folds::new(),
+            symbols: None,
+            symbols_stale: false,
+        }
+    }
+
+    // Buffers form a ring: the next one is at the front of `buffers` and the
+    // previous one at the back. A new buffer goes in front of the current one.
+    fn editor_push_buffer(&mut self, mut buffer: EditorBuffer) {
+        self.editor_swap_buffer(&mut buffer);
+        self.buffers.push(buffer);
+    }
+
+    fn editor_next_buffer(&mut self) {
+        if self.buffers.is_empty() {
+            self.editor_set_status_message(String::from("No other buffer"));
+            return;
+        }
+        let mut buffer = self.buffers.remove(0);
+        self.editor_swap_buffer(&mut buffer);
+        self.buffers.push(buffer);
+    }
+
+    fn editor_prev_buffer(&mut self) {
+        match self.buffers.pop() {
+            Some(mut buffer) => {
+                self.editor_swap_buffer(&mut buffer);
+                self.buffers.insert(0, buffer);
+            },
+            None => self.editor_set_status_message(String::from("No other buffer")),
+        }
+    }
+
+    fn editor_close_buffer(&mut self, force: bool) {
+        if self.dirty && !force && self.scratch.is_none() {
+            self.editor_set_status_message(String::from("Buffer has unsaved changes (close! discards them)"));
+            return;
+        }
+        if self.buffers.is_empty() {
+            self.editor_set_status_message(String::from("Cannot close the last buffer"));
+            return;
+        }
+        if let (Some(idx), Some(uri)) = (self.editor_lsp_index(), self.editor_lsp_uri()) {
+            self.lsp[idx].notify("textDocument/didClose", Json::object(vec![
+                ("textDocument", Json::object(vec![("uri", Json::from(uri))])),
+            ]));
+        }
+        if let Some(mut buffer) = self.buffers.pop() {
+            self.editor_swap_buffer(&mut buffer);
+        }
+    }
+
+    fn editor_list_buffers(&mut self) {
+        let mut names: Vec<String> = Vec::new();
+        let current = (&self.filename, &self.scratch, self.dirty);
+        let others = self.buffers.iter().map(|b| (&b.filename, &b.scratch, b.dirty));
+        for (idx, (filename, scratch, dirty)) in std::iter::once(current).chain(others).enumerate() {
+            let name = match scratch {
+                Some(name) if filename.is_empty() => name.clone(),
+                _ if filename.is_empty() => String::from("[No Name]"),
+                _ => String::from_utf8_lossy(filename).into_owned(),
+            };
+            names.push(format!("{}:{}{}", idx + 1, name, if dirty { "+" } else { "" }));
+        }
+        self.editor_set_status_message(names.join(" "));
+    }
+
+    // Scratch buffers are never reported as unsaved.
+    fn editor_unsaved_buffers(&self) -> usize {
+        let current = (self.dirty && self.scratch.is_none()) as usize;
+        current + self.buffers.iter().filter(|b| b.dirty && b.scratch.is_none()).count()
+    }
+
+    // The rows between the mark and the cursor, or the whole buffer.
+    fn editor_filter_command(&mut self, command: &str) {
+        if command.is_empty() || self.numrows == 0 {
+            return;
+        }
+        let (first, last) = match self.mark {
+            Some(_) => self.editor_selected_rows(),
+            None => (0, self.numrows as usize - 1),
+        };
+        let rows: Vec<&[u8]> = self.erow[first..last + 1].iter().map(|row| &row.chars[..row.size as usize]).collect();
+        let input = write_rows(&rows, &FileFormat::new());
+        let result = run_filter(command, &input);
+        enable_raw_mode();
+        match result {
+            Ok(output) => {
+                let rows = read_rows(&output, Some(Charset::Utf8), &mut FileFormat::new());
+                if first == 0 && last + 1 == self.numrows as usize {
+                    self.editor_replace_rows(rows);
+                }else{
+                    for _ in first..last + 1 {
+                        self.editor_delete_row(first);
+                    }
+                    for (idx, mut row) in rows.into_iter().enumerate() {
+                        let size = row.len() as u16;
+                        self.editor_insert_row(&((first + idx) as u32), &mut row, size);
+                    }
+                    self.cp.y = first as u32;
+                    self.cp.x = 0;
+                    self.mark = None;
+                }
+            },
+            Err(e) => self.editor_set_status_message(format!("Command failed: {}", e)),
+        }
+    }
+
+    fn editor_read_command(&mut self, command: &str) {
+        let result = run_filter(command, b"");
+        enable_raw_mode();
+        match result {
+            Ok(mut output) => {
+                if output.ends_with(b"\n") {
+                    output.pop();
+                }
+                self.editor_insert_text(&output);
+            },
+            Err(e) => self.editor_set_status_message(format!("Command failed: {}", e)),
+        }
+    }
+
+    fn editor_run_to_scratch(&mut self, command: &str) {
+        if command.is_empty() {
+            return;
+        }
+        let result = run_capture(command);
+        enable_raw_mode();
+        let (output, success) = match result {
+            Ok(result) => result,
+            Err(e) => {
+                self.editor_set_status_message(format!("Command failed: {}", e));
+                return;
+            },
+        };
+        let buffer = self.editor_empty_buffer();
+        self.editor_push_buffer(buffer);
+        self.scratch = Some(format!("[output: {}]", command));
+        for mut row in read_rows(&output, Some(Charset::Utf8), &mut FileFormat::new()) {
+            let at = self.numrows;
+            let size = row.len() as u16;
+            self.editor_insert_row(&at, &mut row, size);
+        }
+        self.undo.reset(&self.erow);
+        self.dirty = false;
+        if !success {
+            self.editor_set_status_message(format!("{} failed", command));
+        }
+    }
+
+    fn editor_sign(&self, filerow: usize) -> String {
+        let severity = self.diagnostics.iter()
+            .filter(|d| d.line as usize == filerow)
+            .map(|d| d.severity)
+            .min();
+        let (group, sign) = match severity {
+            Some(1) => ("diagnostic_error", "E"),
+            Some(2) => ("diagnostic_warning", "W"),
+            Some(3) => ("diagnostic_warning", "I"),
+            Some(_) => ("diagnostic_warning", "H"),
+            None => return String::from("  "),
+        };
+        let normal = self.theme.style("normal").escape(self.color_depth);
+        format!("{}{}{} ", self.theme.style(group).escape(self.color_depth), sign, normal)
+    }
+
+    // Render columns of a row covered by diagnostics. An empty range marks one character.
+    fn editor_diagnostic_spans(&self, filerow: usize) -> Vec<(usize, usize)> {
+        let mut spans = Vec::new();
+        let row = &self.erow[filerow];
+        let chars = &row.chars[..row.size as usize];
+        for d in self.diagnostics.iter() {
+            if (d.line as usize) > filerow || (d.end_line as usize) < filerow {
+                continue;
+            }
+            let start = if d.line as usize == filerow { byte_col(chars, d.start) } else { 0 };
+            let mut end = if d.end_line as usize == filerow { byte_col(chars, d.end) } else { chars.len() };
+            if end <= start {
+                end = (start + 1).min(chars.len());
+            }
+            let tab_stop = self.indent.tab_stop;
+            spans.push((editor_row_cxtorx(chars, start, tab_stop) as usize,
+                editor_row_cxtorx(chars, end, tab_stop) as usize));
+        }
+        spans
+    }
+
+    fn editor_list_diagnostics(&mut self) {
+        let filename = String::from_utf8_lossy(&self.filename).into_owned();
+        let items: Vec<ListItem> = self.diagnostics.iter().map(|d| {
+            let col = match self.erow.get(d.line as usize) {
+                Some(row) => byte_col(&row.chars[..row.size as usize], d.start) as u32 + 1,
+                None => 0,
+            };
+            ListItem { file: filename.clone(), line: d.line + 1, col, text: d.message.clone(), span: None }
+        }).collect();
+        if items.is_empty() {
+            self.editor_set_status_message(String::from("No diagnostics"));
+            return;
+        }
+        self.panel = Some(ListPanel::new(String::from("diagnostics"), items));
+        self.editor_panel_jump();
+    }
+
+    fn editor_lsp_uri(&self) -> Option<String> {
+        if self.filename.is_empty() {
+            return None;
+        }
+        let path = Path::new(str::from_utf8(&self.filename).ok()?).canonicalize().ok()?;
+        Some(path_to_uri(&path))
+    }
+
+    // The language server of the current file type, started when first needed.
+    fn editor_lsp_index(&mut self) -> Option<usize> {
+        let file_type = self.editor_syntax.syntax.as_ref()?.file_type.clone();
+        if let Some(idx) = self.lsp.iter().position(|client| client.language == file_type) {
+            return Some(idx);
+        }
+        let command = self.settings.lsp_servers.iter().find(|(ft, _)| *ft == file_type)?.1.clone();
+        let root = std::env::current_dir().ok()?;
+        match LspClient::start(&command, &file_type, &root) {
+            Ok(client) => {
+                self.lsp.push(client);
+                Some(self.lsp.len() - 1)
+            },
+            Err(e) => {
+                self.editor_set_status_message(format!("Language server failed: {}", e));
+                None
+            },
+        }
+    }
+
+    // The text sent to the server: the rows ending in newlines.
+    fn editor_lsp_text(&self) -> String {
+        let mut text = String::new();
+        for row in self.erow.iter() {
+            text.push_str(&String::from_utf8_lossy(&row.chars[..row.size as usize]));
+            text.push('\n');
+        }
+        text
+    }
+
+    fn editor_lsp_open(&mut self) {
+        let (idx, uri) = match (self.editor_lsp_index(), self.editor_lsp_uri()) {
+            (Some(idx), Some(uri)) => (idx, uri),
+            _ => return,
+        };
+        let file_type = self.lsp[idx].language.clone();
+        self.lsp_version = 1;
+        self.lsp_changed = false;
+        let text = self.editor_lsp_text();
+        self.lsp[idx].notify("textDocument/didOpen", Json::object(vec![
+            ("textDocument", Json::object(vec![
+                ("uri", Json::from(uri)),
+                ("languageId", Json::from(file_type)),
+                ("version", Json::from(self.lsp_version)),
+                ("text", Json::from(text)),
+            ])),
+        ]));
+    }
+
+    // Sends the whole buffer when it changed since the last time.
+    fn editor_lsp_sync(&mut self) {
+        if !self.lsp_changed || self.lsp.is_empty() {
+            return;
+        }
+        self.lsp_changed = false;
+        let (idx, uri) = match (self.editor_lsp_index(), self.editor_lsp_uri()) {
+            (Some(idx), Some(uri)) => (idx, uri),
+            _ => return,
+        };
+        self.lsp_version += 1;
+        let text = self.editor_lsp_text();
+        self.lsp[idx].notify("textDocument/didChange", Json::object(vec![
+            ("textDocument", Json::object(vec![
+                ("uri", Json::from(uri)),
+                ("version", Json::from(self.lsp_version)),
+            ])),
+            ("contentChanges", Json::from(vec![Json::object(vec![("text", Json::from(text))])])),
+        ]));
+    }
+
+    fn editor_lsp_request(&mut self, kind: LspRequest, method: &str, extra: Vec<(&str, Json)>) {
+        let (idx, uri) = match (self.editor_lsp_index(), self.editor_lsp_uri()) {
+            (Some(idx), Some(uri)) => (idx, uri),
+            _ => {
+                self.editor_set_status_message(String::from("No language server for this buffer"));
+                return;
+            },
+        };
+        self.editor_lsp_sync();
+        let character = match self.erow.get(self.cp.y as usize) {
+            Some(row) => utf16_col(&row.chars, self.cp.x as usize),
+            None => 0,
+        };
+        let mut params = vec![
+            ("textDocument", Json::object(vec![("uri", Json::from(uri))])),
+            ("position", position(self.cp.y, character)),
+        ];
+        params.extend(extra);
+        self.lsp[idx].request(kind, method, Json::object(params));
+    }
+
+    fn editor_lsp_references(&mut self) {
+        let context = Json::object(vec![("includeDeclaration", Json::from(true))]);
+        self.editor_lsp_request(LspRequest::References, "textDocument/references", vec![("context", context)]);
+    }
+
+    // Handles what the language servers sent. Returns whether to redraw.
+    fn editor_lsp_poll(&mut self) -> bool {
+        self.editor_lsp_sync();
+        let mut events = Vec::new();
+        for client in self.lsp.iter_mut() {
+            events.extend(client.poll());
+        }
+        let redraw = !events.is_empty();
+        for event in events {
+            match event {
+                LspEvent::Diagnostics(uri, diagnostics) => self.editor_set_diagnostics(&uri, diagnostics),
+                LspEvent::Message(text) => self.editor_set_status_message(text),
+                LspEvent::Exited => self.editor_set_status_message(String::from("Language server exited")),
+                LspEvent::Response(kind, result) => self.editor_lsp_response(kind, &result),
+            }
+        }
+        redraw
+    }
+
+    fn editor_set_diagnostics(&mut self, uri: &str, diagnostics: Vec<Diagnostic>) {
+        if self.editor_lsp_uri().as_deref() == Some(uri) {
+            self.diagnostics = diagnostics;
+            return;
+        }
+        let path = match uri_to_path(uri) {
+            Some(path) => path,
+            None => return,
+        };
+        for buffer in self.buffers.iter_mut() {
+            let name = String::from_utf8_lossy(&buffer.filename).into_owned();
+            if !name.is_empty() && Path::new(&name).canonicalize().ok().as_ref() == Some(&path) {
+                buffer.diagnostics = diagnostics;
+                return;
+            }
+        }
+    }
+
+    fn editor_lsp_response(&mut self, kind: LspRequest, result: &Json) {
+        match kind {
+            LspRequest::Hover => {
+                let mut lines: Vec<String> = self.diagnostics.iter()
+                    .filter(|d| d.line <= self.cp.y && self.cp.y <= d.end_line)
+                    .map(|d| d.message.clone())
+                    .collect();
+                lines.extend(hover_text(result).lines().map(String::from));
+                while lines.last().map(|line| line.trim().is_empty()).unwrap_or(false) {
+                    lines.pop();
+                }
+                if lines.is_empty() {
+                    self.editor_set_status_message(String::from("No information"));
+                }else{
+                    self.popup = Some(Popup { lines, completions: Vec::new(), selected: 0, picker: None, rows: Vec::new() });
+                }
+            },
+            LspRequest::Definition | LspRequest::References => {
+                let items = parse_lsp_locations(result);
+                if items.is_empty() {
+                    self.editor_set_status_message(String::from("Nothing found"));
+                    return;
+                }
+                if kind == LspRequest::Definition && items.len() == 1 {
+                    if self.editor_goto_file(&items[0].file) {
+                        self.editor_goto_position(items[0].line, items[0].col);
+                    }
+                    return;
+                }
+                let title = if kind == LspRequest::Definition { "definitions" } else { "references" };
+                self.panel = Some(ListPanel::new(String::from(title), items));
+                self.editor_panel_jump();
+            },
+            LspRequest::Rename => self.editor_apply_workspace_edit(result),
+            LspRequest::Completion => {
+                let items = match result {
+                    Json::Array(items) => items,
+                    _ => match result.get("items").and_then(Json::as_array) {
+                        Some(items) => items,
+                        None => return,
+                    },
+                };
+                let mut lines = Vec::new();
+                let mut completions = Vec::new();
+                for item in items {
+                    let label = match item.get("label").and_then(Json::as_str) {
+                        Some(label) => label,
+                        None => continue,
+                    };
+                    let text = item.path(&["textEdit", "newText"])
+                        .or_else(|| item.get("insertText"))
+                        .and_then(Json::as_str)
+                        .unwrap_or(label);
+                    lines.push(String::from(label));
+                    completions.push(String::from(text));
+                }
+                if completions.is_empty() {
+                    self.editor_set_status_message(String::from("No completions"));
+                }else{
+                    self.popup = Some(Popup { lines, completions, selected: 0, picker: None, rows: Vec::new() });
+                }
+            },
+            LspRequest::Initialize | LspRequest::Shutdown => (),
+        }
     }
 
-    fn editor_delete_row(&mut self, at: usize){
-        if at >= self.numrows as usize{
+    // Applies the edits of a rename to every file they touch, opening the
+    // files that are not open yet, and comes back to the current buffer.
+    fn editor_apply_workspace_edit(&mut self, edit: &Json) {
+        let mut files: Vec<(String, Vec<Json>)> = Vec::new();
+        if let Some(Json::Object(changes)) = edit.get("changes") {
+            for (uri, edits) in changes {
+                if let Some(edits) = edits.as_array() {
+                    files.push((uri.clone(), edits.clone()));
+                }
+            }
+        }
+        if let Some(changes) = edit.get("documentChanges").and_then(Json::as_array) {
+            for change in changes {
+                let uri = change.path(&["textDocument", "uri"]).and_then(Json::as_str);
+                let edits = change.get("edits").and_then(Json::as_array);
+                if let (Some(uri), Some(edits)) = (uri, edits) {
+                    files.push((String::from(uri), edits.clone()));
+                }
+            }
+        }
+        let home = String::from_utf8_lossy(&self.filename).into_owned();
+        let mut count = 0;
+        for (uri, edits) in files {
+            let path = match uri_to_path(&uri) {
+                Some(path) => path.to_string_lossy().into_owned(),
+                None => continue,
+            };
+            if self.editor_goto_file(&path) {
+                self.undo.begin(self.cp.y, self.cp.x);
+                self.editor_apply_text_edits(&edits);
+                self.editor_undo_record();
+                count += 1;
+            }
+        }
+        self.editor_goto_file(&home);
+        self.editor_set_status_message(format!("Changed {} file(s)", count));
+    }
+
+    fn editor_apply_text_edits(&mut self, edits: &[Json]) {
+        let rows: Vec<&[u8]> = self.erow.iter().map(|row| &row.chars[..row.size as usize]).collect();
+        let rows = apply_text_edits(&rows, edits);
+        self.editor_replace_rows(rows);
+    }
+
+    // Keys for the popup. Returns whether the key was used up.
+    fn editor_popup_key(&mut self, key: &EditorKey) -> bool {
+        let popup = match &mut self.popup {
+            Some(popup) => popup,
+            None => return false,
+        };
+        if popup.picker.is_some() {
+            return self.editor_picker_key(key);
+        }
+        if popup.completions.is_empty() {
+            self.popup = None;
+            return matches!(key, EditorKey::Else(b'\x1b'));
+        }
+        // Ctrl-n and Ctrl-p move like Down and Up.
+        match key {
+            EditorKey::Arrow(Arrow::Down) | EditorKey::Else(0x0e) => {
+                popup.selected = (popup.selected + 1) % popup.completions.len();
+                true
+            },
+            EditorKey::Arrow(Arrow::Up) | EditorKey::Else(0x10) => {
+                popup.selected = (popup.selected + popup.completions.len() - 1) % popup.completions.len();
+                true
+            },
+            EditorKey::Else(b'\r') | EditorKey::Else(b'\t') => {
+                let text = popup.completions[popup.selected].clone();
+                self.popup = None;
+                self.editor_complete_word(&text);
+                true
+            },
+            EditorKey::Else(b'\x1b') => {
+                self.popup = None;
+                true
+            },
+            _ => {
+                self.popup = None;
+                false
+            },
+        }
+    }
+
+    // Offers the words of the open buffers that complete the one before the
+    // cursor; Ctrl-p starts from the last one.
+    fn editor_word_completion(&mut self, forward: bool) {
+        let (cy, cx) = (self.cp.y as usize, self.cp.x as usize);
+        let prefix = match self.erow.get(cy) {
+            Some(row) => {
+                let len = row.chars[..cx].iter().rev().take_while(|c| is_ident_char(**c)).count();
+                row.chars[cx - len..cx].to_vec()
+            },
+            None => Vec::new(),
+        };
+        let mut words = HashMap::new();
+        let rows: Vec<&[u8]> = self.erow.iter().map(|row| &row.chars[..row.size as usize]).collect();
+        collect_words(&rows, Some((cy, cx)), &mut words);
+        for buffer in self.buffers.iter() {
+            let rows: Vec<&[u8]> = buffer.erow.iter().map(|row| &row.chars[..row.size as usize]).collect();
+            collect_words(&rows, None, &mut words);
+        }
+        let completions = rank_words(&prefix, &words, &self.recent_words);
+        if completions.is_empty() {
+            self.editor_set_status_message(String::from("No completions"));
             return;
         }
-        self.erow.remove(at);
-        self.numrows -= 1;
-        self.dirty = true;
+        let selected = if forward { 0 } else { completions.len() - 1 };
+        self.popup = Some(Popup {
+            lines: completions.clone(),
+            completions,
+            selected,
+            picker: None,
+            rows: Vec::new(),
+        });
     }
 
-    fn editor_delete_char(&mut self){
-        if self.cp.y == self.numrows || (self.cp.x == 0 && self.cp.y == 0) {
+    // Replaces the identifier part before the cursor with `text`.
+    fn editor_complete_word(&mut self, text: &str) {
+        self.recent_words.retain(|word| word != text);
+        self.recent_words.insert(0, String::from(text));
+        self.recent_words.truncate(RECENT_WORDS);
+        if self.cp.y >= self.numrows {
+            self.editor_insert_text(text.as_bytes());
             return;
         }
-        if self.cp.x > 0 {
-            let mut at: i16 = (self.cp.x - 1) as i16;
-            self.erow[self.cp.y as usize].editor_row_delete_char(&mut at, &mut self.editor_syntax);
-            self.cp.x -= 1;
+        let filerow = self.cp.y as usize;
+        let cx = self.cp.x as usize;
+        let chars = &self.erow[filerow].chars;
+        let start = cx - chars[..cx].iter().rev().take_while(|c| is_ident_char(**c)).count();
+        self.erow[filerow].chars.drain(start..cx);
+        self.erow[filerow].size = (self.erow[filerow].chars.len() - 1) as u16;
+        self.cp.x = start as u16;
+        self.editor_insert_text(text.as_bytes());
+    }
+
+    fn editor_draw_popup(&self, abuf: &mut AppendBuffer) {
+        let popup = match &self.popup {
+            Some(popup) => popup,
+            None => return,
+        };
+        let max_rows = (self.editor_text_rows() as usize / 2).max(1);
+        let first = if popup.selected >= max_rows { popup.selected + 1 - max_rows } else { 0 };
+        let mut lines: Vec<&String> = popup.lines.iter().skip(first).take(max_rows).collect();
+        let header = match &popup.picker {
+            Some(Picker::Symbols { query }) => Some(format!("Symbol: {}", query)),
+            Some(Picker::Files { query, .. }) => Some(format!("File: {}", query)),
+            None => None,
+        };
+        if let Some(header) = &header {
+            lines.insert(0, header);
+        }
+        let width = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0) + 2;
+        let width = width.min(self.screen.cols as usize);
+        let row = self.editor_cursor_screen_row() as usize;
+        let top = if header.is_some() {
+            0
+        }else if row + 1 + lines.len() <= self.editor_text_rows() as usize {
+            row + 1
         }else{
-            self.cp.x = self.erow[(self.cp.y - 1) as usize].size;
-            let mut temp_row = self.erow[self.cp.y as usize].chars.clone();
-            self.editor_row_append((self.cp.y - 1 )as usize, &mut temp_row);
-            self.editor_delete_row(self.cp.y as usize);
-            self.cp.y -= 1;
-            self.erow[self.cp.y as usize].editor_update_row(&mut self.editor_syntax);
+            row.saturating_sub(lines.len())
+        };
+        let col = if header.is_some() {
+            (self.editor_gutter_width() as usize).min(self.screen.cols as usize - width)
+        }else{
+            (self.editor_cursor_screen_col() as usize).min(self.screen.cols as usize - width)
+        };
+        let first = if header.is_some() { first as isize - 1 } else { first as isize };
+        let choosing = !popup.completions.is_empty() || header.is_some();
+        let mut style = self.theme.style("popup");
+        if style == self.theme.style("normal") {
+            style.reverse = true;
+        }
+        let mut selected = self.theme.style("selection");
+        if selected.bg == style.bg {
+            selected.reverse = !style.reverse;
+        }
+        for (idx, line) in lines.iter().enumerate() {
+            let style = if choosing && first + idx as isize == popup.selected as isize { selected } else { style };
+            let text: String = line.chars().take(width - 2).collect();
+            let padding = " ".repeat(width - 2 - text.chars().count());
+            let cell = format!("\x1b[{};{}H{} {}{} \x1b[0m", top + idx + 1, col + 1,
+                style.escape(self.color_depth), text, padding);
+            ab_append(abuf, &mut cell.into_bytes());
         }
-        self.dirty = true;
     }
-    
-    fn editor_rows_to_string(&mut self) -> Vec<u8> {
-        let mut buf_vec: Vec<u8> = Vec::new();
-        for v_erow in self.erow.iter_mut() {
-            let mut temp_vec = v_erow.chars.clone();
-            temp_vec.pop();
-            temp_vec.push('\n' as u8);
-            buf_vec.append(&mut temp_vec);
+
+    // Expands the snippet whose trigger is the word before the cursor.
+    fn editor_expand_snippet(&mut self) -> bool {
+        let file_type = match self.editor_syntax.syntax.as_ref() {
+            Some(syntax) => syntax.file_type.clone(),
+            None => return false,
+        };
+        let (cy, cx) = (self.cp.y as usize, self.cp.x as usize);
+        if cy >= self.numrows as usize {
+            return false;
         }
-        buf_vec
+        // The whole word before the cursor, or the identifier at its end.
+        let chars = &self.erow[cy].chars[..cx];
+        let word = chars.iter().rev().take_while(|c| !c.is_ascii_whitespace()).count();
+        let ident = chars.iter().rev().take_while(|c| is_ident_char(**c)).count();
+        let snippet = [word, ident].iter().filter(|len| **len > 0).find_map(|len| {
+            let trigger = &chars[cx - len..];
+            self.snippets.iter()
+                .find(|s| s.file_type == file_type && s.trigger.as_bytes() == trigger)
+                .map(|s| (*len, s.clone()))
+        });
+        let (len, snippet) = match snippet {
+            Some(found) => found,
+            None => return false,
+        };
+        let x = cx - len;
+        self.erow[cy].chars.drain(x..cx);
+        self.erow[cy].size = (self.erow[cy].chars.len() - 1) as u16;
+        self.cp.x = x as u16;
+        let indent = self.erow[cy].chars[..leading_whitespace(&self.erow[cy].chars)].to_vec();
+        let (lines, mut fields) = expand_snippet(&snippet.body, &indent, &self.editor_indent_unit());
+        self.editor_insert_text(&lines.join(&b'\n'));
+        for field in fields.iter_mut() {
+            if field.row == 0 {
+                field.start += x;
+                field.end += x;
+            }
+            field.row += cy;
+        }
+        self.editor_snippet_enter(SnippetSession::new(fields));
+        true
     }
-    
-    fn editor_save(&mut self) {
-        if self.filename.is_empty() {
-            let cb: Option<fn(&mut EditorConfig, &String, &EditorKey)> = None;
-            self.filename = self.editor_prompt(String::from("Save as: {} (ESC to cancel)"), cb);
-            if self.filename.len() == 0 { 
-                self.editor_set_status_message("Save aborted".to_string());
+
+    // Puts the cursor on the current placeholder. The snippet is done at an
+    // empty `$0`.
+    fn editor_snippet_enter(&mut self, mut session: SnippetSession) {
+        let field = session.active();
+        self.cp.y = field.row as u32;
+        self.cp.x = field.start as u16;
+        session.fresh = true;
+        self.snippet = if session.at_end() && field.start == field.end { None } else { Some(session) };
+    }
+
+    // Tab and Shift-Tab move between placeholders and Esc leaves the snippet.
+    // The first key typed on a placeholder replaces its default text.
+    fn editor_snippet_key(&mut self, key: &EditorKey) -> bool {
+        let mut session = match self.snippet.take() {
+            Some(session) => session,
+            None => return false,
+        };
+        match key {
+            EditorKey::Else(b'\t') => {
+                if !session.at_end() {
+                    session.current += 1;
+                    self.editor_snippet_enter(session);
+                }
+                return true;
+            },
+            EditorKey::Function(Function::BackTab) => {
+                session.current = session.current.saturating_sub(1);
+                self.editor_snippet_enter(session);
+                return true;
+            },
+            EditorKey::Else(b'\x1b') => return false,
+            _ => (),
+        }
+        let fresh = session.fresh;
+        session.fresh = false;
+        let field = session.active();
+        let clear = fresh && field.start < field.end;
+        self.snippet = Some(session);
+        match key {
+            EditorKey::Function(Function::Backspace) | EditorKey::Function(Function::Delete) if clear => {
+                self.editor_snippet_fill(b"");
+                true
+            },
+            EditorKey::Else(c) if clear && *c >= b' ' && *c != 127 => {
+                self.editor_snippet_fill(b"");
+                false
+            },
+            _ => false,
+        }
+    }
+
+    // Sets the text of the current placeholder and its mirrors.
+    fn editor_snippet_fill(&mut self, text: &[u8]) {
+        let session = match self.snippet.as_ref() {
+            Some(session) => session,
+            None => return,
+        };
+        let number = session.active().number;
+        let fields: Vec<usize> = (0..session.fields.len())
+            .filter(|idx| session.fields[*idx].number == number)
+            .collect();
+        for idx in fields {
+            self.editor_snippet_replace(idx, text);
+        }
+        if let Some(session) = self.snippet.as_ref() {
+            let field = session.active();
+            self.cp.y = field.row as u32;
+            self.cp.x = field.start as u16;
+        }
+    }
+
+    fn editor_snippet_replace(&mut self, idx: usize, text: &[u8]) {
+        let mut session = match self.snippet.take() {
+            Some(session) => session,
+            None => return,
+        };
+        let field = session.fields[idx];
+        if field.row < self.erow.len() && field.end <= self.erow[field.row].size as usize {
+            self.erow[field.row].chars.splice(field.start..field.end, text.iter().cloned());
+            self.erow[field.row].size = (self.erow[field.row].chars.len() - 1) as u16;
+            self.erow[field.row].editor_update_row(self.indent.tab_stop);
+            self.editor_invalidate_syntax(field.row);
+            let delta = text.len() as isize - (field.end - field.start) as isize;
+            session.shift(field.row, field.end, delta, Some(idx));
+            if self.cp.y as usize == field.row && self.cp.x as usize >= field.end {
+                self.cp.x = (self.cp.x as isize + delta) as u16;
+            }
+            self.dirty = true;
+        }
+        self.snippet = Some(session);
+    }
+
+    // The cursor row, its length and the row count before a key is handled,
+    // to see how the key changed the current placeholder.
+    fn editor_snippet_before(&self) -> Option<(u32, u16, u16, u32)> {
+        if self.snippet.is_none() || self.cp.y >= self.numrows {
+            return None;
+        }
+        Some((self.cp.y, self.cp.x, self.erow[self.cp.y as usize].size, self.numrows))
+    }
+
+    // Text typed on a placeholder goes to its mirrors too. Leaving the row of
+    // the placeholder or editing outside it leaves the snippet.
+    fn editor_snippet_after(&mut self, before: Option<(u32, u16, u16, u32)>) {
+        let (row, cx, size, numrows) = match before {
+            Some(before) => before,
+            None => return,
+        };
+        let mut session = match self.snippet.take() {
+            Some(session) => session,
+            None => return,
+        };
+        let field = session.active();
+        if self.numrows != numrows || self.cp.y != row || field.row != row as usize {
+            return;
+        }
+        let delta = self.erow[row as usize].size as isize - size as isize;
+        if delta == 0 {
+            self.snippet = Some(session);
+            return;
+        }
+        let at = std::cmp::min(cx, self.cp.x) as usize;
+        let removed = if delta < 0 { (-delta) as usize } else { 0 };
+        if at < field.start || at + removed > field.end {
+            return;
+        }
+        let edited = session.stops[session.current];
+        session.shift(field.row, field.end, delta, Some(edited));
+        let field = session.active();
+        let text = self.erow[field.row].chars[field.start..field.end].to_vec();
+        let mirrors: Vec<usize> = (0..session.fields.len())
+            .filter(|idx| *idx != edited && session.fields[*idx].number == field.number)
+            .collect();
+        self.snippet = Some(session);
+        for idx in mirrors {
+            self.editor_snippet_replace(idx, &text);
+        }
+    }
+
+    // Render columns of the placeholder being filled in and its mirrors.
+    fn editor_placeholder_spans(&self, filerow: usize) -> Vec<(usize, usize)> {
+        let session = match self.snippet.as_ref() {
+            Some(session) => session,
+            None => return Vec::new(),
+        };
+        let number = session.active().number;
+        let chars = &self.erow[filerow].chars;
+        let tab_stop = self.indent.tab_stop;
+        session.fields.iter()
+            .filter(|f| f.number == number && f.row == filerow && f.end < chars.len())
+            .map(|f| (editor_row_cxtorx(chars, f.start, tab_stop) as usize,
+                editor_row_cxtorx(chars, f.end, tab_stop) as usize))
+            .collect()
+    }
+
+    // Runs the build command, or `command` when given, and lists the file
+    // locations in its output.
+    fn editor_build(&mut self, command: &str) {
+        let command = if command.is_empty() { self.settings.build_command.clone() } else { String::from(command) };
+        if command.is_empty() {
+            self.editor_set_status_message(String::from("No build_command configured"));
+            return;
+        }
+        self.editor_set_status_message(format!("Running {}...", command));
+        self.editor_refresh_screen();
+        let result = run_capture(&command);
+        enable_raw_mode();
+        let (output, success) = match result {
+            Ok(result) => result,
+            Err(e) => {
+                self.editor_set_status_message(format!("Build failed: {}", e));
+                return;
+            },
+        };
+        let items = parse_locations(&String::from_utf8_lossy(&output));
+        if items.is_empty() {
+            self.panel = None;
+            let result = if success { "succeeded" } else { "failed" };
+            self.editor_set_status_message(format!("{} {}", command, result));
+            return;
+        }
+        self.panel = Some(ListPanel::new(command, items));
+        self.editor_panel_jump();
+    }
+
+    // Moves to the next or previous item of the list panel and jumps to it.
+    fn editor_panel_step(&mut self, forward: bool) {
+        let panel = match &mut self.panel {
+            Some(panel) => panel,
+            None => {
+                self.editor_set_status_message(String::from("No list"));
+                return;
+            },
+        };
+        let idx = panel.selected;
+        if forward && idx + 1 < panel.items.len() {
+            panel.select(idx + 1);
+        }else if !forward && idx > 0 {
+            panel.select(idx - 1);
+        }else{
+            let end = if forward { "last" } else { "first" };
+            self.editor_set_status_message(format!("At the {} item", end));
+            return;
+        }
+        self.editor_panel_jump();
+    }
+
+    fn editor_panel_jump(&mut self) {
+        let item = match self.panel.as_ref().and_then(|panel| panel.current()) {
+            Some(item) => item.clone(),
+            None => return,
+        };
+        if self.editor_goto_file(&item.file) {
+            self.editor_goto_position(item.line, item.col);
+            self.editor_set_status_message(item.label());
+        }
+    }
+
+    fn editor_word_at_cursor(&self) -> String {
+        let chars = match self.erow.get(self.cp.y as usize) {
+            Some(row) => &row.chars[..row.size as usize],
+            None => return String::new(),
+        };
+        let cx = self.cp.x as usize;
+        let start = cx - chars[..cx].iter().rev().take_while(|c| is_ident_char(**c)).count();
+        let end = cx + chars[cx..].iter().take_while(|c| is_ident_char(**c)).count();
+        String::from_utf8_lossy(&chars[start..end]).into_owned()
+    }
+
+    // The directory of the file of the buffer, or the current one.
+    fn editor_file_dir(&self) -> PathBuf {
+        let file = String::from_utf8_lossy(&self.filename).into_owned();
+        match Path::new(&file).canonicalize() {
+            Ok(path) if !file.is_empty() => path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            _ => std::env::current_dir().unwrap_or_default(),
+        }
+    }
+
+    // Jumps to the definition of `name` found in the nearest tags file above
+    // the file, or lists the definitions when there are several.
+    fn editor_jump_to_tag(&mut self, name: &str) {
+        if name.is_empty() {
+            self.editor_set_status_message(String::from("No identifier under the cursor"));
+            return;
+        }
+        let file = String::from_utf8_lossy(&self.filename).into_owned();
+        let path = match find_tags_file(&self.editor_file_dir()) {
+            Some(path) => path,
+            None => {
+                self.editor_set_status_message(String::from("No tags file found"));
+                return;
+            },
+        };
+        let items = match read_tags(&path, name) {
+            Ok(tags) => tag_locations(&tags, name),
+            Err(e) => {
+                self.editor_set_status_message(e);
+                return;
+            },
+        };
+        if items.is_empty() {
+            self.editor_set_status_message(format!("Tag not found: {}", name));
+            return;
+        }
+        let line = self.cp.y + 1;
+        self.tag_stack.push(ListItem { file, line, col: self.cp.x as u32 + 1, text: String::new(), span: None });
+        if items.len() > 1 {
+            self.panel = Some(ListPanel::new(format!("tag {}", name), items));
+            self.editor_panel_jump();
+        }else if self.editor_goto_file(&items[0].file) {
+            self.editor_goto_position(items[0].line, items[0].col);
+        }
+    }
+
+    fn editor_pop_tag(&mut self) {
+        let item = match self.tag_stack.pop() {
+            Some(item) => item,
+            None => {
+                self.editor_set_status_message(String::from("Tag stack is empty"));
                 return;
+            },
+        };
+        if self.editor_goto_file(&item.file) {
+            self.editor_goto_position(item.line, item.col);
+        }
+    }
+
+    // Shows the buffer of `file`, opening it when no buffer has it.
+    fn editor_goto_file(&mut self, file: &str) -> bool {
+        if file.is_empty() {
+            return true;
+        }
+        let target = match Path::new(file).canonicalize() {
+            Ok(path) => path,
+            Err(_) => {
+                self.editor_set_status_message(format!("Cannot open {}", file));
+                return false;
+            },
+        };
+        let same = |filename: &[u8]| {
+            let name = String::from_utf8_lossy(filename).into_owned();
+            !name.is_empty() && Path::new(&name).canonicalize().map(|p| p == target).unwrap_or(false)
+        };
+        if same(&self.filename) {
+            return true;
+        }
+        if let Some(idx) = self.buffers.iter().position(|b| same(&b.filename)) {
+            let mut buffer = self.buffers.remove(idx);
+            self.editor_swap_buffer(&mut buffer);
+            self.buffers.push(buffer);
+            return true;
+        }
+        let unused = self.filename.is_empty() && self.numrows == 0 && !self.dirty && self.scratch.is_none();
+        if !unused {
+            let buffer = self.editor_empty_buffer();
+            self.editor_push_buffer(buffer);
+        }
+        if let Err(e) = self.editor_open(&String::from(file)) {
+            // Back to the buffer we came from, dropping the new empty one.
+            if !unused {
+                if let Some(mut buffer) = self.buffers.pop() {
+                    self.editor_swap_buffer(&mut buffer);
+                }
             }
-            self.editor_select_syntax_highlight();
+            self.editor_set_status_message(e);
+            return false;
         }
-        let path = String::from_utf8(self.filename.clone()).unwrap();
-        let w_vec: Vec<u8> = self.editor_rows_to_string();
-        let len = w_vec.len();
-        std::fs::write(path, w_vec).unwrap();
-        self.editor_set_status_message(format!("{} bytes written to disk", len));
-        self.dirty = false;
-        self.quit_times = RILO_QUIT_TIMES;
+        true
     }
-    
+
+    // Puts the cursor on a 1-based line and column.
+    fn editor_goto_position(&mut self, line: u32, col: u32) {
+        if self.numrows == 0 {
+            return;
+        }
+        self.cp.y = line.saturating_sub(1).min(self.numrows - 1);
+        let size = self.erow[self.cp.y as usize].size;
+        self.cp.x = (col.saturating_sub(1) as u16).min(size);
+        // Put the line in the middle of the screen.
+        self.off.row = self.cp.y.saturating_sub(self.editor_text_rows() as u32 / 2);
+    }
+
+    // Inserts text at the cursor as typed, without auto-indentation.
+    fn editor_insert_text(&mut self, text: &[u8]) {
+        self.auto_closers.clear();
+        if self.cp.y == self.numrows {
+            let at = self.numrows;
+            self.editor_insert_row(&at, &mut Vec::new(), 0);
+        }
+        let first = self.cp.y as usize;
+        let tail = self.editor_split_row();
+        let mut lines = text.split(|c| *c == b'\n');
+        let mut line = lines.next().unwrap_or_default().to_vec();
+        line.push(b'\0');
+        self.editor_row_append(first, &mut line);
+        let mut last = first;
+        for line in lines {
+            last += 1;
+            let mut row = line.to_vec();
+            let size = row.len() as u16;
+            self.editor_insert_row(&(last as u32), &mut row, size);
+        }
+        self.cp.y = last as u32;
+        self.cp.x = self.erow[last].size;
+        let mut tail = tail;
+        tail.push(b'\0');
+        self.editor_row_append(last, &mut tail);
+        self.erow[first].editor_update_row(self.indent.tab_stop);
+        self.erow[last].editor_update_row(self.indent.tab_stop);
+        self.editor_invalidate_syntax(first);
+        self.mark = None;
+        self.dirty = true;
+    }
+
+    fn editor_command(&mut self){
+        let command = String::from_utf8(self.editor_prompt(String::from("Command: {}"), None)).unwrap();
+        if !command.is_empty() {
+            self.editor_execute_command(&command);
+        }
+    }
+
+    pub fn editor_execute_command(&mut self, command: &str) {
+        let command = command.trim();
+        let (name, arg) = match command.find(char::is_whitespace) {
+            Some(pos) => (&command[..pos], command[pos..].trim()),
+            None => (command, ""),
+        };
+        match name {
+            "filetype" | "ft" => self.editor_set_file_type(arg),
+            "scopes" => self.editor_show_scopes(),
+            "set" => self.editor_set_option(arg),
+            "bn" | "bnext" => self.editor_next_buffer(),
+            "bp" | "bprev" => self.editor_prev_buffer(),
+            "bd" | "close" => self.editor_close_buffer(false),
+            "bd!" | "close!" => self.editor_close_buffer(true),
+            "ls" | "buffers" => self.editor_list_buffers(),
+            "r" | "read" if arg.starts_with('!') => self.editor_read_command(arg[1..].trim()),
+            "run" | "sh" => self.editor_run_to_scratch(arg),
+            "build" | "make" => self.editor_build(arg),
+            "cn" | "cnext" => self.editor_panel_step(true),
+            "cp" | "cprev" => self.editor_panel_step(false),
+            "cclose" => self.panel = None,
+            "hover" => self.editor_lsp_request(LspRequest::Hover, "textDocument/hover", vec![]),
+            "def" | "definition" => {
+                self.editor_lsp_request(LspRequest::Definition, "textDocument/definition", vec![]);
+            },
+            "refs" | "references" => self.editor_lsp_references(),
+            "complete" => self.editor_lsp_request(LspRequest::Completion, "textDocument/completion", vec![]),
+            "rename" if !arg.is_empty() => {
+                self.editor_lsp_request(LspRequest::Rename, "textDocument/rename",
+                    vec![("newName", Json::from(arg))]);
+            },
+            "diagnostics" => self.editor_list_diagnostics(),
+            "undo" => self.editor_undo(false),
+            "redo" => self.editor_undo(true),
+            "comment" => self.editor_toggle_comment(),
+            "fold" => self.editor_fold(),
+            "unfold" => self.editor_unfold(),
+            "foldtoggle" => self.editor_toggle_fold(),
+            "foldall" => self.editor_toggle_all_folds(),
+            "outline" | "symbols" => self.editor_outline(),
+            "tag" if !arg.is_empty() => self.editor_jump_to_tag(arg),
+            "tag" => {
+                let name = self.editor_word_at_cursor();
+                self.editor_jump_to_tag(&name);
+            },
+            "pop" => self.editor_pop_tag(),
+            "files" => self.editor_file_picker(),
+            "grep" if !arg.is_empty() => self.editor_grep(arg),
+            "grep" => self.editor_grep_prompt(),
+            _ if name.starts_with('!') => {
+                let command = command[1..].trim();
+                self.editor_filter_command(command);
+            },
+            "format" => {
+                if self.editor_formatter().is_none() {
+                    self.editor_set_status_message(String::from("No formatter for this file type"));
+                }else if let Err(e) = self.editor_run_formatter() {
+                    self.editor_set_status_message(format!("Formatter failed: {}", e));
+                }
+            },
+            _ => self.editor_set_status_message(format!("Unknown command: {}", name)),
+        }
+    }
+
+    // Vim-style `set ft=rust`, `set ts=4`, `set sw=4`, `set et` and `set noet`.
+    fn editor_set_option(&mut self, arg: &str) {
+        let (key, value) = match arg.find('=') {
+            Some(pos) => (&arg[..pos], &arg[pos + 1..]),
+            None => (arg, ""),
+        };
+        let mut indent = self.indent;
+        match key {
+            "ft" | "filetype" => {
+                self.editor_set_file_type(value);
+                return;
+            },
+            "ts" | "tabstop" | "sw" | "shiftwidth" => {
+                let width = match value.parse::<u16>() {
+                    Ok(width) if (1..=32).contains(&width) => width,
+                    _ => {
+                        self.editor_set_status_message(format!("Invalid width: {}", value));
+                        return;
+                    },
+                };
+                // The indent size follows the tab width unless it was set apart.
+                if key.starts_with('s') || indent.indent_size == indent.tab_stop {
+                    indent.indent_size = width;
+                }
+                if key.starts_with('t') {
+                    indent.tab_stop = width;
+                }
+            },
+            "et" | "expandtab" => indent.expand_tabs = true,
+            "noet" | "noexpandtab" => indent.expand_tabs = false,
+            _ => {
+                self.editor_set_status_message(format!("Unknown option: {}", arg));
+                return;
+            },
+        }
+        self.editor_set_indent_style(indent);
+    }
+
     fn editor_find(&mut self){
         let saved_cx = self.cp.x;
         let saved_cy = self.cp.y;
@@ -620,6 +3293,25 @@ impl EditorConfig {
             numrows: 0,
             off: Offset{ row: 0 ,col: 0},
             filename: Vec::new(),
+            scratch: None,
+            buffers: Vec::new(),
+            panel: None,
+            tag_stack: Vec::new(),
+            popup: None,
+            recent_words: Vec::new(),
+            snippets: Vec::new(),
+            snippet: None,
+            auto_closers: Vec::new(),
+            undo: UndoJournal::new(),
+            folds: Folds::new(),
+            symbols: None,
+            symbols_stale: false,
+            undo_typing: false,
+            edits: 0,
+            lsp: Vec::new(),
+            diagnostics: Vec::new(),
+            lsp_version: 0,
+            lsp_changed: false,
             status: Status {message: Vec::new(), time: Instant::now()},
             dirty: false,
             quit_times: RILO_QUIT_TIMES,
@@ -628,26 +3320,22 @@ impl EditorConfig {
             current_color: Highlight::NONE,
             saved_hl: Vec::new(),
             saved_hl_line: -1,
-            editor_syntax: EditorSyntaxInf {syntax:None, in_string:0},
-            syntax_pattern: vec![
-                EditorSyntax::new("rust", 
-                    vec!["rs".to_string(), "toml".to_string()],
-                    vec![
-                        "if".to_string(),
-                        "while".to_string(),
-                        "for".to_string(),
-                        "continue".to_string(),
-                        "return".to_string(),
-                        "else".to_string(),
-                        "String|".to_string(),
-                        "usize|".to_string(),
-                        "u8|".to_string(),
-                        "u16|".to_string(),
-                        ],
-                    "//".to_string(), 
-                    HLFlags::HLF_NUMBERS | HLFlags::HLF_STRINGS),
-            ],
+            editor_syntax: EditorSyntaxInf {syntax:None},
+            syntax_pattern: Vec::new(),
+            syntax_override: None,
+            highlighter: new_highlighter(&EditorSyntaxInf {syntax:None}, false),
+            hl_stale_from: 0,
+            hl_marks: Vec::new(),
+            indent: IndentStyle::new(RILO_TAB_STOP, false),
+            format: FileFormat::new(),
+            mark: None,
+            settings: EditorSettings::new(),
+            project_loaded: false,
+            config_errors: Vec::new(),
+            theme: Theme::new(),
+            color_depth: ColorDepth::Ansi16,
         };
+        ec.editor_load_settings(None);
         if let Some((Width(w), Height(h))) = get_window_size() {
             ec.screen.rows = h - 2;
             ec.screen.cols = w;
@@ -660,13 +3348,25 @@ impl EditorConfig {
 }
 
 
-fn editor_row_cxtorx(vec: Vec<u8>, cx: usize) -> u16 {
+// How many rows at the start and at the end of `old` and `new` are the same.
+fn unchanged_rows(old: &[&[u8]], new: &[Vec<u8>]) -> (usize, usize) {
+    let prefix = old.iter().zip(new.iter()).take_while(|(a, b)| **a == &b[..]).count();
+    let suffix = old.iter().rev().zip(new.iter().rev())
+        .take_while(|(a, b)| **a == &b[..]).count()
+        .min(old.len().min(new.len()) - prefix);
+    (prefix, suffix)
+}
+
+fn leading_whitespace(chars: &[u8]) -> usize {
+    chars.iter().take_while(|c| **c == b' ' || **c == b'\t').count()
+}
+
+fn editor_row_cxtorx(vec: &[u8], cx: usize, tab_stop: u16) -> u16 {
     let mut rx: u16 = 0;
-    let t_vec: Vec<u8> = vec[0..cx].to_vec();
-    let v_iter = t_vec.iter();
+    let v_iter = vec[0..cx].iter();
     for ch in v_iter {
         if *ch == b'\t' {
-            rx += (RILO_TAB_STOP - 1) - ( rx % RILO_TAB_STOP) + 1;
+            rx += (tab_stop - 1) - ( rx % tab_stop) + 1;
         }else{
             rx += 1;
         }
@@ -674,13 +3374,81 @@ fn editor_row_cxtorx(vec: Vec<u8>, cx: usize) -> u16 {
     rx
 }
 
-fn editor_row_rxtocx(vec: Vec<u8>, rx: usize) -> u16 {
+// Whether a quote typed between `before` and `after` starts a lifetime or a
+// label rather than a character: right after `&` or `<`, after `,` or `:`
+// within generics as in `<'a, 'b>`, in bounds as in `T: 'a` and `Tr + 'a`,
+// after `break` or `continue`, or before a label's `name:`.
+fn starts_lifetime(before: &[u8], after: &[u8]) -> bool {
+    let name = after.iter().take_while(|c| is_ident_char(**c)).count();
+    if name > 0 && after.get(name) == Some(&b':') && after.get(name + 1) != Some(&b':') {
+        return true;
+    }
+    let end = match before.iter().rposition(|c| !c.is_ascii_whitespace()) {
+        Some(idx) => idx,
+        None => return false,
+    };
+    let blanks = end + 1 < before.len();
+    match before[end] {
+        b'&' | b'<' => !blanks,
+        b'+' => true,
+        b',' => in_generics(&before[..end]),
+        b':' => {
+            // A bound follows a type parameter, `Self` or a lifetime.
+            let to = before[..end].iter().rposition(|c| !c.is_ascii_whitespace()).map_or(0, |idx| idx + 1);
+            let from = before[..to].iter().rposition(|c| !is_ident_char(*c)).map_or(0, |idx| idx + 1);
+            let word = &before[from..to];
+            in_generics(&before[..end]) || word.first().is_some_and(|c| c.is_ascii_uppercase()) ||
+                (!word.is_empty() && from > 0 && before[from - 1] == b'\'')
+        },
+        _ => {
+            let from = before[..end].iter().rposition(|c| !is_ident_char(*c)).map_or(0, |idx| idx + 1);
+            blanks && matches!(&before[from..end + 1], b"break" | b"continue")
+        },
+    }
+}
+
+// Whether the end of `before` is within the unclosed `<` of generics, taken
+// for one when it directly follows a name or `::`.
+fn in_generics(before: &[u8]) -> bool {
+    let mut depth = 0;
+    for (idx, c) in before.iter().enumerate().rev() {
+        match c {
+            b'>' if idx > 0 && before[idx - 1] == b'-' => return false,
+            b'>' => depth += 1,
+            b'<' if depth > 0 => depth -= 1,
+            b'<' => return idx > 0 && (is_ident_char(before[idx - 1]) || before[idx - 1] == b':'),
+            b'(' | b')' | b'[' | b']' | b'{' | b'}' | b';' | b'=' => return false,
+            _ => (),
+        }
+    }
+    false
+}
+
+// The columns Backspace or Delete removes around `cx`: Delete has already
+// stepped over the character it removes. Backspace in an empty pair whose
+// closer was `inserted` with the opener removes both halves, and in space
+// indentation it goes back to the previous indent stop.
+fn deleted_columns(chars: &[u8], cx: usize, key: &Function, indent: &IndentStyle, inserted: bool) -> (usize, usize) {
+    if !matches!(key, Function::Backspace) {
+        return (cx - 1, cx);
+    }
+    if inserted && PAIRS.contains(&(chars[cx - 1], chars[cx])) {
+        return (cx - 1, cx + 1);
+    }
+    if indent.expand_tabs && chars[..cx].iter().all(|c| *c == b' ') {
+        let size = indent.indent_size as usize;
+        return ((cx - 1) / size * size, cx);
+    }
+    (cx - 1, cx)
+}
+
+fn editor_row_rxtocx(vec: &[u8], rx: usize, tab_stop: u16) -> u16 {
     let mut cx: u16 = 0;
     let mut cur_rx: u16 = 0;
     let v_iter = vec.iter();
     for ch in v_iter {
         if *ch == b'\t' {
-            cur_rx += (RILO_TAB_STOP - 1) - ( cur_rx % RILO_TAB_STOP) + 1;
+            cur_rx += (tab_stop - 1) - ( cur_rx % tab_stop) + 1;
         }else{
             cur_rx += 1;
         }
@@ -692,36 +3460,6 @@ fn editor_row_rxtocx(vec: Vec<u8>, rx: usize) -> u16 {
     cx
 }
 
-fn editor_syntax_to_color(hl: &Highlight) -> u8 {
-    match hl {
-        Highlight::NUMBER => 31,
-        Highlight::KEYWORD1 => 33,
-        Highlight::KEYWORD2 => 32,
-        Highlight::MATCH => 34,
-        Highlight::STRING => 35,
-        Highlight::COMMENT => 36,
-        _ => 37
-    }
-}
-
-fn editor_color_to_syntax(color: u8) -> Highlight {
-    if color == 31 {
-        Highlight::NUMBER
-    }else if color == 32 {
-        Highlight::KEYWORD2
-    }else if color == 33 {
-        Highlight::KEYWORD1
-    }else if color == 34 {
-        Highlight::MATCH
-    }else if color == 35 {
-        Highlight::STRING
-    }else if color == 36 {
-        Highlight::COMMENT
-    }else {
-        Highlight::NORMAL
-    }
-}
-
 fn editor_find_callback(ec: &mut EditorConfig, query: &String, key: &EditorKey) {
     if ec.saved_hl.len() != 0 {
         ec.erow[ec.saved_hl_line as usize].hl = ec.saved_hl.clone();
@@ -765,10 +3503,12 @@ fn editor_find_callback(ec: &mut EditorConfig, query: &String, key: &EditorKey)
             while pt <= erow.len() - q_len {
                 if query == &erow[pt..(pt + q_len)]{
                     ec.last_match = current;
-                    ec.cp.y = current as u16;
-                    ec.cp.x = editor_row_rxtocx(ec.erow[current as usize].chars.clone(), pt);
+                    ec.cp.y = current as u32;
+                    ec.cp.x = editor_row_rxtocx(&ec.erow[current as usize].chars, pt,
+                        ec.indent.tab_stop);
                     ec.off.row = ec.numrows;
-                    ec.saved_hl_line = current as i16;
+                    ec.editor_highlight_upto(current as usize, None);
+                    ec.saved_hl_line = current;
                     ec.saved_hl = ec.erow[current as usize].hl.clone();
                     let mut idx = 0;
                     while idx < q_len {
@@ -783,3 +3523,49 @@ fn editor_find_callback(ec: &mut EditorConfig, query: &String, key: &EditorKey)
         i += 1;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backspace_steps_back_to_indent_stops_and_delete_removes_one_column() {
+        let spaces = IndentStyle { tab_stop: 8, indent_size: 4, expand_tabs: true };
+        let row = b"      foo";
+        assert_eq!(deleted_columns(row, 6, &Function::Backspace, &spaces, false), (4, 6));
+        assert_eq!(deleted_columns(row, 4, &Function::Backspace, &spaces, false), (0, 4));
+        assert_eq!(deleted_columns(row, 1, &Function::Delete, &spaces, false), (0, 1));
+        assert_eq!(deleted_columns(row, 6, &Function::Delete, &spaces, false), (5, 6));
+        assert_eq!(deleted_columns(row, 8, &Function::Backspace, &spaces, false), (7, 8));
+        let tabs = IndentStyle::new(4, false);
+        assert_eq!(deleted_columns(row, 4, &Function::Backspace, &tabs, false), (3, 4));
+    }
+
+    #[test]
+    fn backspace_removes_both_halves_of_inserted_pairs_only() {
+        let indent = IndentStyle::new(4, false);
+        let row = b"f()\0";
+        assert_eq!(deleted_columns(row, 2, &Function::Backspace, &indent, true), (1, 3));
+        assert_eq!(deleted_columns(row, 2, &Function::Backspace, &indent, false), (1, 2));
+        assert_eq!(deleted_columns(row, 2, &Function::Delete, &indent, true), (1, 2));
+        assert_eq!(deleted_columns(b"f(x)\0", 2, &Function::Backspace, &indent, false), (1, 2));
+    }
+
+    #[test]
+    fn quotes_in_lifetime_places_start_lifetimes() {
+        for before in ["fn f<", "fn f<'a, ", "fn f<T: ", "impl<'a> X for Y<", "fn f(x: &", "where T: ",
+                "struct S<'a, T: Tr<'a> + ", "where 'a: ", "    break ", "continue "].iter() {
+            assert!(starts_lifetime(before.as_bytes(), b"\0"), "{}", before);
+        }
+        assert!(starts_lifetime(b"    ", b"outer: loop {\0"));
+    }
+
+    #[test]
+    fn other_quotes_start_characters() {
+        for before in ["let c = ", "    f(", "x & ", "if a < ", "    ", "", "f(x, ", "vec![", "vec!['a', ",
+                "let c: char = ", "S { c: ", "match c { 'a' | ", "f(a < b, ", "fn f() -> Vec<u8> { g(x, "].iter() {
+            assert!(!starts_lifetime(before.as_bytes(), b"\0"), "{}", before);
+        }
+        assert!(!starts_lifetime(b"f(", b"x::y)\0"));
+    }
+}
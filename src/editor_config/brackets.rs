@@ -0,0 +1,175 @@
+use super::{Erow, Highlight};
+
+pub enum BracketMatch {
+    Matched(usize, usize),
+    Unmatched,
+}
+
+// Brackets in strings and comments are text, not code.
+pub fn is_text(hl: Option<&Highlight>) -> bool {
+    is_comment(hl) || matches!(hl, Some(Highlight::STRING) | Some(Highlight::CHAR) | Some(Highlight::ESCAPE))
+}
+
+pub fn is_comment(hl: Option<&Highlight>) -> bool {
+    matches!(hl, Some(Highlight::COMMENT) | Some(Highlight::MLCOMMENT) | Some(Highlight::DOCCOMMENT))
+}
+
+// The other bracket of the pair and whether it comes after this one.
+fn partner(c: u8) -> Option<(u8, bool)> {
+    match c {
+        b'(' => Some((b')', true)),
+        b'[' => Some((b']', true)),
+        b'{' => Some((b'}', true)),
+        b')' => Some((b'(', false)),
+        b']' => Some((b'[', false)),
+        b'}' => Some((b'{', false)),
+        _ => None,
+    }
+}
+
+// Finds the partner of the bracket at render column `col` of `row`, looking at
+// most `max_rows` rows away. None when there is no bracket of code there.
+pub fn match_bracket(rows: &[Erow], row: usize, col: usize, max_rows: usize) -> Option<BracketMatch> {
+    let erow = rows.get(row)?;
+    if col >= erow._rsize as usize || is_text(erow.hl.get(col)) {
+        return None;
+    }
+    let (other, forward) = partner(erow.render[col])?;
+    let mut depth = 0;
+    let mut r = row;
+    let mut c = col;
+    loop {
+        if forward {
+            c += 1;
+            while c >= rows[r]._rsize as usize {
+                if r + 1 >= rows.len() || r + 1 > row + max_rows {
+                    return Some(BracketMatch::Unmatched);
+                }
+                r += 1;
+                c = 0;
+            }
+        }else{
+            while c == 0 {
+                if r == 0 || row - r >= max_rows {
+                    return Some(BracketMatch::Unmatched);
+                }
+                r -= 1;
+                c = rows[r]._rsize as usize;
+            }
+            c -= 1;
+        }
+        let ch = rows[r].render[c];
+        if is_text(rows[r].hl.get(c)) {
+            continue;
+        }
+        match partner(ch) {
+            Some((_, opens)) if opens == forward => depth += 1,
+            Some(_) if depth > 0 => depth -= 1,
+            Some(_) if ch == other => return Some(BracketMatch::Matched(r, c)),
+            Some(_) => return Some(BracketMatch::Unmatched),
+            None => (),
+        }
+    }
+}
+
+// The brackets of code in rows `first` to `last` without a partner, found by
+// balancing them against the brackets up to `context` rows around. Rows not
+// highlighted yet also end the context, and brackets whose partner may be
+// past its ends are not reported.
+pub fn unmatched_brackets(rows: &[Erow], first: usize, last: usize, context: usize) -> Vec<(usize, usize)> {
+    if first > last || last >= rows.len() {
+        return Vec::new();
+    }
+    let mut start = first;
+    while start > 0 && first - start < context && !rows[start - 1].hl_stale {
+        start -= 1;
+    }
+    let known_above = start == 0;
+    let mut end = last;
+    while end + 1 < rows.len() && end - last < context && !rows[end + 1].hl_stale {
+        end += 1;
+    }
+    let known_below = end + 1 == rows.len();
+    let shown = |row: usize| row >= first && row <= last;
+    let mut open: Vec<(usize, usize, u8)> = Vec::new();
+    let mut unmatched = Vec::new();
+    for (row, erow) in rows.iter().enumerate().take(end + 1).skip(start) {
+        for col in 0..erow._rsize as usize {
+            let (other, opens) = match partner(erow.render[col]) {
+                Some(pair) => pair,
+                None => continue,
+            };
+            if is_text(erow.hl.get(col)) {
+                continue;
+            }
+            if opens {
+                open.push((row, col, other));
+                continue;
+            }
+            match open.pop() {
+                Some((orow, ocol, closer)) if closer != erow.render[col] => {
+                    unmatched.extend([(orow, ocol), (row, col)].iter().filter(|(r, _)| shown(*r)));
+                },
+                Some(_) => (),
+                None if known_above && shown(row) => unmatched.push((row, col)),
+                None => (),
+            }
+        }
+    }
+    if known_below {
+        unmatched.extend(open.into_iter().filter(|(row, _, _)| shown(*row)).map(|(row, col, _)| (row, col)));
+    }
+    unmatched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::RowState;
+
+    fn rows(text: &[&str]) -> Vec<Erow> {
+        text.iter().map(|row| {
+            let mut chars = row.as_bytes().to_vec();
+            chars.push(b'\0');
+            Erow {
+                size: row.len() as u16,
+                chars,
+                _rsize: row.len() as u16,
+                render: row.as_bytes().to_vec(),
+                hl: vec![Highlight::NORMAL; row.len()],
+                hl_state: RowState::new(),
+                hl_stale: false,
+                tree: Vec::new(),
+                symbol: None,
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn flags_brackets_without_partners() {
+        let rows = rows(&["fn f() {", "    g(x];", "    h(", "}", ")"]);
+        assert_eq!(unmatched_brackets(&rows, 0, 4, 10), vec![(1, 5), (1, 7), (2, 5), (3, 0), (0, 7), (4, 0)]);
+        assert_eq!(unmatched_brackets(&rows, 1, 1, 10), vec![(1, 5), (1, 7)]);
+    }
+
+    #[test]
+    fn skips_brackets_in_strings_and_comments() {
+        let mut rows = rows(&["f(\")\");"]);
+        for col in 2..5 {
+            rows[0].hl[col] = Highlight::STRING;
+        }
+        assert!(unmatched_brackets(&rows, 0, 0, 10).is_empty());
+    }
+
+    #[test]
+    fn trusts_blocks_longer_than_the_context() {
+        let mut text = vec!["impl X {"];
+        text.extend(vec!["    x,"; 50]);
+        text.push("}");
+        let rows = rows(&text);
+        assert!(unmatched_brackets(&rows, 0, 0, 10).is_empty());
+        assert!(unmatched_brackets(&rows, 51, 51, 10).is_empty());
+        assert!(unmatched_brackets(&rows, 20, 30, 10).is_empty());
+        assert!(unmatched_brackets(&rows, 0, 51, 10).is_empty());
+    }
+}
@@ -0,0 +1,104 @@
+use super::erow::{is_ident_char, is_ident_start};
+
+use std::collections::HashMap;
+
+// Words offered at most, and accepted words remembered for ranking.
+const COMPLETION_LIMIT: usize = 100;
+pub const RECENT_WORDS: usize = 32;
+
+// Adds the identifiers of `rows` to `words` with their distance in rows from
+// the cursor, keeping the nearest occurrence. Rows of other buffers have no
+// cursor and come after every row of the current one. The word the cursor is
+// in is left out.
+pub fn collect_words(rows: &[&[u8]], cursor: Option<(usize, usize)>, words: &mut HashMap<Vec<u8>, usize>) {
+    for (y, row) in rows.iter().enumerate() {
+        let distance = match cursor {
+            Some((cy, _)) => y.abs_diff(cy),
+            None => usize::MAX,
+        };
+        let mut idx = 0;
+        while idx < row.len() {
+            if !is_ident_start(row[idx]) || (idx > 0 && is_ident_char(row[idx - 1])) {
+                idx += 1;
+                continue;
+            }
+            let start = idx;
+            while idx < row.len() && is_ident_char(row[idx]) {
+                idx += 1;
+            }
+            if cursor.map(|(cy, cx)| cy == y && start <= cx && cx <= idx).unwrap_or(false) {
+                continue;
+            }
+            let nearest = words.entry(row[start..idx].to_vec()).or_insert(distance);
+            if distance < *nearest {
+                *nearest = distance;
+            }
+        }
+    }
+}
+
+// Words starting with `prefix` come first, then those containing its
+// characters in order, ignoring case. Each group is ordered by how recently
+// the word was accepted, then by distance from the cursor.
+pub fn rank_words(prefix: &[u8], words: &HashMap<Vec<u8>, usize>, recent: &[String]) -> Vec<String> {
+    let mut ranked: Vec<(bool, usize, usize, &Vec<u8>)> = words.iter()
+        .filter(|(word, _)| word.as_slice() != prefix)
+        .filter_map(|(word, distance)| {
+            let fuzzy = if word.starts_with(prefix) {
+                false
+            }else if fuzzy_match(prefix, word) {
+                true
+            }else{
+                return None;
+            };
+            let age = recent.iter().position(|w| w.as_bytes() == word.as_slice()).unwrap_or(usize::MAX);
+            Some((fuzzy, age, *distance, word))
+        })
+        .collect();
+    ranked.sort();
+    ranked.into_iter()
+        .take(COMPLETION_LIMIT)
+        .map(|(_, _, _, word)| String::from_utf8_lossy(word).into_owned())
+        .collect()
+}
+
+pub fn fuzzy_match(pattern: &[u8], word: &[u8]) -> bool {
+    let mut chars = word.iter().map(u8::to_ascii_lowercase);
+    pattern.iter().all(|p| chars.any(|c| c == p.to_ascii_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(rows: &[&str], cursor: Option<(usize, usize)>) -> HashMap<Vec<u8>, usize> {
+        let rows: Vec<&[u8]> = rows.iter().map(|row| row.as_bytes()).collect();
+        let mut words = HashMap::new();
+        collect_words(&rows, cursor, &mut words);
+        words
+    }
+
+    #[test]
+    fn keeps_the_nearest_occurrence_of_each_word() {
+        let words = words(&["let count = 1;", "count += step_2;", "pri"], Some((2, 3)));
+        assert_eq!(words.get(&b"count"[..]), Some(&1));
+        assert_eq!(words.get(&b"let"[..]), Some(&2));
+        assert_eq!(words.get(&b"step_2"[..]), Some(&1));
+        assert!(!words.contains_key(&b"pri"[..]));
+        assert!(!words.contains_key(&b"2"[..]) && !words.contains_key(&b"1"[..]));
+        let mut more = words.clone();
+        collect_words(&[b"count printer"], None, &mut more);
+        assert_eq!(more.get(&b"count"[..]), Some(&1));
+        assert_eq!(more.get(&b"printer"[..]), Some(&usize::MAX));
+    }
+
+    #[test]
+    fn ranks_prefixes_then_fuzzy_matches_by_recency_and_distance() {
+        let words = words(&["parse_args", "", "parser", "", "", "compare_paths", "pa", "pear"], Some((1, 0)));
+        assert_eq!(rank_words(b"pa", &words, &[]), vec!["parse_args", "parser", "compare_paths", "pear"]);
+        let recent = vec![String::from("pear"), String::from("parser")];
+        assert_eq!(rank_words(b"pa", &words, &recent), vec!["parser", "parse_args", "pear", "compare_paths"]);
+        assert_eq!(rank_words(b"PSR", &words, &[]), vec!["parse_args", "parser"]);
+        assert!(rank_words(b"xyz", &words, &[]).is_empty());
+    }
+}
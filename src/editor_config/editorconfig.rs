@@ -0,0 +1,255 @@
+use super::file_format::{FileFormat, LineEnding, Charset, parse_line_ending, parse_charset};
+use super::glob::glob_match_path;
+use super::indent::IndentStyle;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const EDITORCONFIG_FILE: &str = ".editorconfig";
+
+// The properties the matching `.editorconfig` sections set for one file.
+#[derive(Clone)]
+pub struct FileProperties {
+    pub expand_tabs: Option<bool>,
+    // `indent_size = tab` is kept as 0 and means the tab width.
+    pub indent_size: Option<u16>,
+    pub tab_width: Option<u16>,
+    pub end_of_line: Option<LineEnding>,
+    pub charset: Option<Charset>,
+    pub trim_trailing_whitespace: Option<bool>,
+    pub insert_final_newline: Option<bool>,
+    pub max_line_length: Option<Option<usize>>,
+}
+
+impl FileProperties {
+    pub fn new() -> FileProperties {
+        FileProperties {
+            expand_tabs: None,
+            indent_size: None,
+            tab_width: None,
+            end_of_line: None,
+            charset: None,
+            trim_trailing_whitespace: None,
+            insert_final_newline: None,
+            max_line_length: None,
+        }
+    }
+
+    // Reads every `.editorconfig` from the file's directory up to the first one
+    // marked `root = true`, applying the outermost first so nearer files win.
+    pub fn load(file: &Path) -> FileProperties {
+        let mut configs: Vec<(PathBuf, String)> = Vec::new();
+        let mut current = file.parent();
+        while let Some(dir) = current {
+            if let Ok(text) = fs::read_to_string(dir.join(EDITORCONFIG_FILE)) {
+                let root = is_root(&text);
+                configs.insert(0, (dir.to_path_buf(), text));
+                if root {
+                    break;
+                }
+            }
+            current = dir.parent();
+        }
+        let mut props = FileProperties::new();
+        for (dir, text) in configs.iter() {
+            if let Ok(relative) = file.strip_prefix(dir) {
+                props.apply(text, &relative.to_string_lossy());
+            }
+        }
+        props
+    }
+
+    // Sections are matched against the path relative to the `.editorconfig`;
+    // a glob without a `/` matches the file name in any directory.
+    fn apply(&mut self, text: &str, relative: &str) {
+        let mut matched = false;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                let glob = &line[1..line.len() - 1];
+                matched = if glob.contains('/') {
+                    glob_match_path(glob.strip_prefix('/').unwrap_or(glob), relative)
+                }else{
+                    glob_match_path(&format!("**/{}", glob), relative)
+                };
+                continue;
+            }
+            if let (true, Some(pos)) = (matched, line.find('=')) {
+                self.set(&line[..pos].trim().to_lowercase(), &line[pos + 1..].trim().to_lowercase());
+            }
+        }
+    }
+
+    // Unknown properties and values are ignored, as the format asks.
+    fn set(&mut self, key: &str, value: &str) {
+        let unset = value == "unset";
+        match key {
+            "indent_style" => {
+                self.expand_tabs = match value {
+                    "space" => Some(true),
+                    "tab" => Some(false),
+                    _ => None,
+                };
+            },
+            "indent_size" if value == "tab" => self.indent_size = Some(0),
+            "indent_size" => self.indent_size = parse_width(value),
+            "tab_width" => self.tab_width = parse_width(value),
+            "end_of_line" => self.end_of_line = parse_line_ending(value),
+            "charset" => self.charset = parse_charset(value),
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = parse_bool(value),
+            "insert_final_newline" => self.insert_final_newline = parse_bool(value),
+            "max_line_length" if value == "off" || unset => {
+                self.max_line_length = if unset { None } else { Some(None) };
+            },
+            "max_line_length" => self.max_line_length = value.parse::<usize>().ok().map(Some),
+            _ => (),
+        }
+    }
+
+    // Spaces indent by `indent_size` and tabs are displayed `tab_width` wide,
+    // which defaults to the indent size; an indent size of `tab` is the tab width.
+    pub fn indent_style(&self, fallback: IndentStyle) -> IndentStyle {
+        let expand_tabs = self.expand_tabs.unwrap_or(fallback.expand_tabs);
+        let tab_stop = self.tab_width.or(self.indent_size.filter(|size| *size != 0)).unwrap_or(fallback.tab_stop);
+        let indent_size = match self.indent_size {
+            Some(0) => tab_stop,
+            Some(size) => size,
+            None => fallback.indent_size,
+        };
+        IndentStyle { tab_stop, indent_size, expand_tabs }
+    }
+
+    pub fn sets_indent(&self) -> bool {
+        self.expand_tabs.is_some() || self.indent_size.is_some() || self.tab_width.is_some()
+    }
+
+    // The charset is applied when the file is read.
+    pub fn apply_format(&self, format: &mut FileFormat) {
+        if let Some(line_ending) = self.end_of_line {
+            format.line_ending = line_ending;
+        }
+        if let Some(trim) = self.trim_trailing_whitespace {
+            format.trim_trailing_whitespace = trim;
+        }
+        if let Some(final_newline) = self.insert_final_newline {
+            format.final_newline = final_newline;
+        }
+        if let Some(max_line_length) = self.max_line_length {
+            format.max_line_length = max_line_length;
+        }
+    }
+}
+
+fn is_root(text: &str) -> bool {
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            return false;
+        }
+        if let Some(pos) = line.find('=') {
+            if line[..pos].trim().eq_ignore_ascii_case("root") {
+                return line[pos + 1..].trim().eq_ignore_ascii_case("true");
+            }
+        }
+    }
+    false
+}
+
+fn parse_width(value: &str) -> Option<u16> {
+    value.parse::<u16>().ok().filter(|width| (1..=32).contains(width))
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "root = true\n\
+        [*]\n\
+        indent_style = space\n\
+        indent_size = 4\n\
+        ; a comment\n\
+        [*.{go,mk}]\n\
+        Indent_Style = Tab\n\
+        [lib/*.rs]\n\
+        indent_size = 2\n\
+        max_line_length = 100\n\
+        [docs/**]\n\
+        trim_trailing_whitespace = false\n\
+        max_line_length = off\n";
+
+    fn props(relative: &str) -> FileProperties {
+        let mut props = FileProperties::new();
+        props.apply(CONFIG, relative);
+        props
+    }
+
+    #[test]
+    fn later_sections_override_earlier_ones() {
+        for (path, expand_tabs, indent_size, max_line_length) in [
+            ("main.c", Some(true), Some(4), None),
+            ("cmd/main.go", Some(false), Some(4), None),
+            ("rules.mk", Some(false), Some(4), None),
+            ("lib/a.rs", Some(true), Some(2), Some(Some(100))),
+            ("src/lib/a.rs", Some(true), Some(4), None),
+            ("docs/a/b.md", Some(true), Some(4), Some(None)),
+        ].iter() {
+            let props = props(path);
+            assert_eq!(props.expand_tabs, *expand_tabs, "{}", path);
+            assert_eq!(props.indent_size, *indent_size, "{}", path);
+            assert_eq!(props.max_line_length, *max_line_length, "{}", path);
+        }
+        assert_eq!(props("docs/x").trim_trailing_whitespace, Some(false));
+        assert!(is_root(CONFIG));
+        assert!(!is_root("[*]\nroot = true\n"));
+    }
+
+    #[test]
+    fn resolves_indent_size_and_tab_width() {
+        let fallback = IndentStyle { tab_stop: 8, indent_size: 8, expand_tabs: false };
+        for (indent_size, tab_width, tab_stop, size) in [
+            (None, None, 8, 8),
+            (Some(4), None, 4, 4),
+            (Some(4), Some(8), 8, 4),
+            (Some(0), Some(3), 3, 3),
+            (Some(0), None, 8, 8),
+            (None, Some(2), 2, 8),
+        ].iter() {
+            let mut props = FileProperties::new();
+            props.indent_size = *indent_size;
+            props.tab_width = *tab_width;
+            let style = props.indent_style(fallback);
+            assert_eq!((style.tab_stop, style.indent_size), (*tab_stop, *size), "{:?} {:?}", indent_size, tab_width);
+        }
+        let mut props = FileProperties::new();
+        props.set("indent_size", "tab");
+        props.set("tab_width", "99");
+        assert_eq!((props.indent_size, props.tab_width), (Some(0), None));
+    }
+
+    #[test]
+    fn reads_configurations_up_to_the_root() {
+        let top = std::env::temp_dir().join(format!("rilo-editorconfig-{}", std::process::id()));
+        let root = top.join("project");
+        let _ = fs::remove_dir_all(&top);
+        fs::create_dir_all(root.join("sub/deeper")).unwrap();
+        fs::write(top.join(EDITORCONFIG_FILE), "[*]\ntab_width = 3\ncharset = latin1\n").unwrap();
+        fs::write(root.join(EDITORCONFIG_FILE), "root = true\n[*]\nindent_size = 8\ntab_width = 8\n").unwrap();
+        fs::write(root.join("sub").join(EDITORCONFIG_FILE), "[*.rs]\nindent_size = 2\n[deeper/*]\nend_of_line = crlf\n").unwrap();
+        let props = FileProperties::load(&root.join("sub/deeper/file.rs"));
+        fs::remove_dir_all(&top).unwrap();
+        assert_eq!((props.indent_size, props.tab_width), (Some(2), Some(8)));
+        assert_eq!(props.end_of_line.map(|e| e.name()), Some("crlf"));
+        assert!(props.charset.is_none());
+    }
+}
@@ -1,6 +1,33 @@
-use super::{Highlight, EditorSyntax, EditorSyntaxInf, HLFlags};
+use super::{Highlight, EditorSyntax, EditorSyntaxInf, HLFlags, NumFlags, Scope, TreeEvent};
 
-pub const RILO_TAB_STOP: u16 = 8;
+// What is still open at the end of a row: a block comment, a string (by its
+// delimiter) and, for raw strings, the number of `#` closing it.
+#[derive(Clone, Copy, PartialEq)]
+pub struct HlOpen {
+    pub comment: bool,
+    pub string: u8,
+    pub raw: Option<u16>,
+}
+
+impl HlOpen {
+    pub fn new() -> HlOpen {
+        HlOpen { comment: false, string: 0, raw: None }
+    }
+}
+
+// The state at the end of a row: `open` for the rule engine, `scopes` for the
+// structural parsers.
+#[derive(Clone, PartialEq)]
+pub struct RowState {
+    pub open: HlOpen,
+    pub scopes: Vec<Scope>,
+}
+
+impl RowState {
+    pub fn new() -> RowState {
+        RowState { open: HlOpen::new(), scopes: Vec::new() }
+    }
+}
 
 pub struct Erow {
     pub size: u16,
@@ -8,120 +35,219 @@ pub struct Erow {
     pub _rsize: u16,
     pub render: Vec<u8>,
     pub hl: Vec<Highlight>,
+    pub hl_state: RowState,
+    pub hl_stale: bool,
+    pub tree: Vec<TreeEvent>,
+    pub symbol: Option<(String, String)>,
 }
 
 impl Erow {
-    pub fn editor_update_syntax(&mut self, si: &mut EditorSyntaxInf){
+    // Highlights the row starting in the state left open by the previous row and
+    // returns whether the state left open at the end of this row changed.
+    pub fn editor_update_syntax(&mut self, si: &EditorSyntaxInf, open: HlOpen) -> bool {
         self.hl.clear();
-        self.hl = vec![Highlight::NORMAL; self.render.len()];
-        let es: &EditorSyntax;
-        match &si.syntax {
+        self.hl.resize(self.render.len(), Highlight::NORMAL);
+        let es: &EditorSyntax = match &si.syntax {
             None => {
-                return;
+                return self.editor_set_open_state(HlOpen::new());
             },
-            Some(val) => es = val,
-        }
-        let scs_len = es.singleline_comment_start.len();
-        let scs = es.singleline_comment_start.clone();
+            Some(val) => val,
+        };
+        let rsize = self._rsize as usize;
+        let render = &self.render[..rsize];
+        let hl = &mut self.hl;
+        let scs = es.singleline_comment_start.as_bytes();
+        let mcs = es.multiline_comment_start.as_bytes();
+        let mce = es.multiline_comment_end.as_bytes();
+        let escapes = es.flags.contains(HLFlags::HLF_ESCAPES);
         let mut idx = 0;
         let mut prev_sep = true;
+        let mut after_fn = false;
         let mut prev_hl: Highlight;
-        let erow_str: String = String::from_utf8(self.render.clone()).unwrap();
-        while idx < self.render.len() {
+        let mut state = open;
+        if mcs.is_empty() {
+            state.comment = false;
+        }
+        'row: while idx < rsize {
             if idx > 0 {
-                prev_hl = self.hl[idx - 1].clone();
+                prev_hl = hl[idx - 1].clone();
             }else{
                 prev_hl = Highlight::NORMAL;
             }
-            if scs_len != 0 && si.in_string == 0 {
-                if (self._rsize as usize) - idx >= scs_len && 
-                    self.render[idx..(idx + scs_len)] == scs.as_bytes().to_vec() {
-                    while idx < self.render.len() {
-                        self.hl[idx] = Highlight::COMMENT;
+            if state.string == 0 && !state.comment {
+                let doc = es.doc_comment_start.iter().any(|d| render[idx..].starts_with(d.as_bytes()));
+                if doc || (!scs.is_empty() && render[idx..].starts_with(scs)) {
+                    let hlk = if doc { Highlight::DOCCOMMENT } else { Highlight::COMMENT };
+                    fill(hl, idx, rsize, hlk);
+                    break;
+                }
+            }
+            if !mcs.is_empty() && !mce.is_empty() && state.string == 0 {
+                if state.comment {
+                    if render[idx..].starts_with(mce) {
+                        idx = fill(hl, idx, idx + mce.len(), Highlight::MLCOMMENT);
+                        state.comment = false;
+                        prev_sep = true;
+                    }else{
+                        hl[idx] = Highlight::MLCOMMENT;
                         idx += 1;
                     }
-                    return;
+                    continue;
+                }else if render[idx..].starts_with(mcs) {
+                    idx = fill(hl, idx, idx + mcs.len(), Highlight::MLCOMMENT);
+                    state.comment = true;
+                    continue;
                 }
             }
-            if es.flags.contains(HLFlags::HLF_STRINGS) {
-                if si.in_string != 0 {
-                    self.hl[idx] = Highlight::STRING;
-                    if self.render[idx] as char == '\\' && idx + 1 < self._rsize as usize {
-                        self.hl[idx + 1] = Highlight::STRING;
-                        idx += 2;
-                        continue;
+            if state.string != 0 {
+                prev_sep = true;
+                if let Some(hashes) = state.raw {
+                    let end = idx + 1 + hashes as usize;
+                    if render[idx] == state.string && end <= rsize &&
+                            render[idx + 1..end].iter().all(|c| *c == b'#') {
+                        idx = fill(hl, idx, end, Highlight::STRING);
+                        state.string = 0;
+                        state.raw = None;
+                    }else{
+                        hl[idx] = Highlight::STRING;
+                        idx += 1;
                     }
-                    if self.render[idx] == si.in_string { si.in_string = 0; }
-                    idx += 1;
-                    prev_sep = true;
                     continue;
-                }else{
-                    if self.render[idx] as char == '"' || self.render[idx] as char == '\'' {
-                        si.in_string = self.render[idx];
-                        self.hl[idx] = Highlight::STRING;
-                        idx += 1;
+                }
+                if render[idx] == b'\\' && idx + 1 < rsize {
+                    if escapes {
+                        idx = fill(hl, idx, scan_escape(render, idx), Highlight::ESCAPE);
+                    }else{
+                        idx = fill(hl, idx, idx + 2, Highlight::STRING);
+                    }
+                    continue;
+                }
+                hl[idx] = Highlight::STRING;
+                if render[idx] == state.string { state.string = 0; }
+                idx += 1;
+                continue;
+            }
+            if prev_sep {
+                for prefix in es.raw_string_prefixes.iter() {
+                    if !render[idx..].starts_with(prefix.as_bytes()) {
                         continue;
                     }
+                    let mut end = idx + prefix.len();
+                    while end < rsize && render[end] == b'#' {
+                        end += 1;
+                    }
+                    if end < rsize && render[end] == b'"' {
+                        state.string = b'"';
+                        state.raw = Some((end - idx - prefix.len()) as u16);
+                        idx = fill(hl, idx, end + 1, Highlight::STRING);
+                        continue 'row;
+                    }
                 }
             }
-            if es.flags.contains(HLFlags::HLF_NUMBERS) {
-                if (self.render[idx] as char).is_numeric() &&
-                        ( matches!(prev_hl, Highlight::NUMBER) || prev_sep){
-                    self.hl[idx] = Highlight::NUMBER;
-                    idx += 1;
+            if es.char_delimiter != 0 && render[idx] == es.char_delimiter {
+                if let Some(end) = scan_char(render, idx) {
+                    fill(hl, idx, end, Highlight::CHAR);
+                    if escapes && render[idx + 1] == b'\\' {
+                        fill(hl, idx + 1, end - 1, Highlight::ESCAPE);
+                    }
+                    idx = end;
+                    prev_sep = false;
+                    continue;
+                }
+                if es.flags.contains(HLFlags::HLF_LIFETIMES) && idx + 1 < rsize &&
+                        is_ident_start(render[idx + 1]) {
+                    idx = fill(hl, idx, scan_ident(render, idx + 1), Highlight::LIFETIME);
                     prev_sep = false;
                     continue;
                 }
             }
+            if es.flags.contains(HLFlags::HLF_STRINGS) && es.string_delimiters.contains(&render[idx]) {
+                state.string = render[idx];
+                hl[idx] = Highlight::STRING;
+                idx += 1;
+                continue;
+            }
             if prev_sep {
-                let mut kwd: String;
-                let mut hlk: Highlight;
-                let mut k_idx: usize = 0;
-                while k_idx < es.keywords.len() {
-                    kwd = es.keywords[k_idx].clone();
-                    if &kwd[kwd.len() - 1..kwd.len()] == "|" {
-                        kwd.pop();
-                        hlk = Highlight::KEYWORD2;
-                    }else{
-                        hlk = Highlight::KEYWORD1;
-                    }
-                    if (self._rsize as usize) - idx >= kwd.len() && 
-                            &erow_str[idx..(idx + kwd.len())] == &kwd[..] {
-                        let hl_max = idx + kwd.len();
-                        while idx < hl_max {
-                            self.hl[idx] = hlk.clone();
-                            idx += 1;
-                        }
-                        break;
-                    }
-                    k_idx += 1;
+                if let Some(start) = es.attribute_starts.iter().find(|a| render[idx..].starts_with(a.as_bytes())) {
+                    idx = fill(hl, idx, scan_attribute(render, idx + start.len()), Highlight::ATTRIBUTE);
+                    continue;
                 }
-                if k_idx != es.keywords.len(){
+            }
+            if es.flags.contains(HLFlags::HLF_NUMBERS) && (render[idx] as char).is_numeric() &&
+                    ( matches!(prev_hl, Highlight::NUMBER) || prev_sep) {
+                idx = fill(hl, idx, scan_number(render, idx, es.number_flags), Highlight::NUMBER);
+                prev_sep = false;
+                continue;
+            }
+            if prev_sep {
+                if let Some((len, hlk)) = es.keyword_matcher.find(&render[idx..]) {
+                    let kwd = &render[idx..idx + len];
+                    after_fn = es.function_keywords.iter().any(|f| f.as_bytes() == kwd);
+                    idx = fill(hl, idx, idx + len, hlk);
                     prev_sep = false;
                     continue;
                 }
             }
-            prev_sep = is_separator(self.render[idx] as char);
+            if prev_sep && is_ident_start(render[idx]) {
+                let mut end = scan_ident(render, idx);
+                let next = |i: usize| if i < rsize { render[i] } else { 0 };
+                let hlk = if es.flags.contains(HLFlags::HLF_MACROS) && next(end) == b'!' &&
+                        next(end + 1) != b'=' {
+                    end += 1;
+                    Highlight::MACRO
+                }else if after_fn || (es.flags.contains(HLFlags::HLF_FUNCTIONS) && next(end) == b'(') {
+                    Highlight::FUNCTION
+                }else if es.flags.contains(HLFlags::HLF_CAPITALIZED_TYPES) &&
+                        render[idx].is_ascii_uppercase() &&
+                        render[idx..end].iter().any(|c| c.is_ascii_lowercase()) {
+                    Highlight::TYPE
+                }else{
+                    Highlight::NORMAL
+                };
+                idx = fill(hl, idx, end, hlk);
+                after_fn = false;
+                prev_sep = false;
+                continue;
+            }
+            if es.operators.contains(&render[idx]) {
+                hl[idx] = Highlight::OPERATOR;
+            }
+            prev_sep = is_separator(render[idx] as char);
             idx += 1;
         }
+        if !es.flags.contains(HLFlags::HLF_MULTILINE_STRINGS) {
+            state.string = 0;
+            state.raw = None;
+        }
+        self.editor_set_open_state(state)
+    }
+
+    fn editor_set_open_state(&mut self, open: HlOpen) -> bool {
+        self.editor_set_state(RowState { open, scopes: Vec::new() })
     }
 
-    pub fn editor_row_insert_character(&mut self, at: &mut i16, c: u8, si: &mut EditorSyntaxInf){
+    pub fn editor_set_state(&mut self, state: RowState) -> bool {
+        let changed = self.hl_state != state;
+        self.hl_state = state;
+        changed
+    }
+
+    pub fn editor_row_insert_character(&mut self, at: &mut i16, c: u8, tab_stop: u16){
         if *at < 0 || *at > self.size as i16 {
             *at = self.size as i16;
         }
         self.chars.insert(*at as usize, c);
         self.size += 1;
-        self.editor_update_row(si);
+        self.editor_update_row(tab_stop);
     }
 
-    pub fn editor_row_delete_char(&mut self, at: &mut i16, si: &mut EditorSyntaxInf){
+    pub fn editor_row_delete_char(&mut self, at: &mut i16, tab_stop: u16){
         self.chars.remove(*at as usize);
         self.size -= 1;
-        self.editor_update_row(si);
+        self.editor_update_row(tab_stop);
     }
 
-    pub fn editor_update_row(&mut self, si: &mut EditorSyntaxInf) {
+    pub fn editor_update_row(&mut self, tab_stop: u16) {
         let temp_vec = self.chars.clone();
         let v_iter = temp_vec.iter();
         let mut new_vec: Vec<u8> = Vec::new();
@@ -130,7 +256,7 @@ impl Erow {
                 let mut idx = new_vec.len();
                 new_vec.push(b' ');
                 idx += 1;
-                while idx % RILO_TAB_STOP as usize != 0 {
+                while !idx.is_multiple_of(tab_stop as usize) {
                     new_vec.push(b' ');
                     idx += 1;
                 }
@@ -140,16 +266,147 @@ impl Erow {
         }
         self.render = new_vec;
         self._rsize = (self.render.len() - 1) as u16;
-        self.editor_update_syntax(si);
     } 
 
 }
 
 
-fn is_separator(c: char) -> bool {
-    match c {
-        ' ' | ',' | '.' | '(' | ')' | '+' | '-' | '/' | '*' |
-        '=' | '~' | '%' | '<' | '>' | '[' | ']' | ';' => true,
-        _ => false,
+fn fill(hl: &mut [Highlight], start: usize, end: usize, hlk: Highlight) -> usize {
+    for h in hl[start..end].iter_mut() {
+        *h = hlk.clone();
+    }
+    end
+}
+
+pub fn is_ident_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_'
+}
+
+pub fn is_ident_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+pub fn scan_ident(render: &[u8], start: usize) -> usize {
+    let mut idx = start;
+    while idx < render.len() && is_ident_char(render[idx]) {
+        idx += 1;
+    }
+    idx
+}
+
+// End of the escape sequence starting with the backslash at `start`.
+pub fn scan_escape(render: &[u8], start: usize) -> usize {
+    let mut idx = start + 2;
+    match render[start + 1] {
+        b'x' => {
+            while idx < render.len() && idx < start + 4 && render[idx].is_ascii_hexdigit() {
+                idx += 1;
+            }
+        },
+        b'u' if idx < render.len() && render[idx] == b'{' => {
+            while idx < render.len() && render[idx] != b'}' {
+                idx += 1;
+            }
+            if idx < render.len() { idx += 1; }
+        },
+        _ => (),
+    }
+    idx
+}
+
+// End of the character literal starting at `start`, if there is one.
+pub fn scan_char(render: &[u8], start: usize) -> Option<usize> {
+    let quote = render[start];
+    if start + 1 >= render.len() {
+        return None;
     }
+    let end = if render[start + 1] == b'\\' {
+        if start + 2 >= render.len() {
+            return None;
+        }
+        scan_escape(render, start + 1)
+    }else{
+        let c = render[start + 1];
+        let len = if c < 0x80 { 1 } else if c >= 0xf0 { 4 } else if c >= 0xe0 { 3 } else { 2 };
+        start + 1 + len
+    };
+    if end < render.len() && render[end] == quote && render[start + 1] != quote {
+        Some(end + 1)
+    }else{
+        None
+    }
+}
+
+// End of an attribute whose opening bracket ends just before `start`.
+fn scan_attribute(render: &[u8], start: usize) -> usize {
+    let mut depth = 1;
+    let mut idx = start;
+    while idx < render.len() {
+        match render[idx] {
+            b'[' => depth += 1,
+            b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return idx + 1;
+                }
+            },
+            _ => (),
+        }
+        idx += 1;
+    }
+    idx
+}
+
+// Returns the end of the number literal starting at `start`.
+pub fn scan_number(render: &[u8], start: usize, nf: NumFlags) -> usize {
+    let at = |i: usize| if i < render.len() { render[i] } else { 0 };
+    let digit = |c: u8, hex: bool| c.is_ascii_digit() || (hex && c.is_ascii_hexdigit()) ||
+        (nf.contains(NumFlags::NUM_UNDERSCORE) && c == b'_');
+    let mut idx = start;
+    let radix = match (at(idx), at(idx + 1)) {
+        (b'0', b'x') | (b'0', b'X') => NumFlags::NUM_HEX,
+        (b'0', b'o') | (b'0', b'O') => NumFlags::NUM_OCTAL,
+        (b'0', b'b') | (b'0', b'B') => NumFlags::NUM_BINARY,
+        _ => NumFlags::empty(),
+    };
+    if !radix.is_empty() && nf.contains(radix) {
+        idx += 2;
+        while digit(at(idx), radix == NumFlags::NUM_HEX) {
+            idx += 1;
+        }
+    }else{
+        while digit(at(idx), false) {
+            idx += 1;
+        }
+        if nf.contains(NumFlags::NUM_FLOAT) {
+            if at(idx) == b'.' && at(idx + 1).is_ascii_digit() {
+                idx += 1;
+                while digit(at(idx), false) {
+                    idx += 1;
+                }
+            }
+            if at(idx) == b'e' || at(idx) == b'E' {
+                let mut exp = idx + 1;
+                if at(exp) == b'+' || at(exp) == b'-' {
+                    exp += 1;
+                }
+                if at(exp).is_ascii_digit() {
+                    idx = exp;
+                    while digit(at(idx), false) {
+                        idx += 1;
+                    }
+                }
+            }
+        }
+    }
+    if nf.contains(NumFlags::NUM_SUFFIX) {
+        while at(idx).is_ascii_alphanumeric() || at(idx) == b'_' {
+            idx += 1;
+        }
+    }
+    if idx == start { start + 1 } else { idx }
+}
+
+fn is_separator(c: char) -> bool {
+    c.is_ascii_whitespace() || c == '\0' || ",.()+-/*=~%<>[];:{}!&|^?#@\"'".contains(c)
 }
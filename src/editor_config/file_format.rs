@@ -0,0 +1,220 @@
+const UTF8_BOM: [u8; 3] = [0xef, 0xbb, 0xbf];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Charset {
+    Utf8,
+    Utf8Bom,
+    Latin1,
+    Utf16Be,
+    Utf16Le,
+}
+
+// How the buffer is written back. Rows always hold UTF-8 without line endings.
+#[derive(Clone, Copy)]
+pub struct FileFormat {
+    pub line_ending: LineEnding,
+    pub charset: Charset,
+    pub final_newline: bool,
+    pub trim_trailing_whitespace: bool,
+    pub max_line_length: Option<usize>,
+}
+
+impl LineEnding {
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            LineEnding::Lf => b"\n",
+            LineEnding::CrLf => b"\r\n",
+            LineEnding::Cr => b"\r",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "lf",
+            LineEnding::CrLf => "crlf",
+            LineEnding::Cr => "cr",
+        }
+    }
+}
+
+impl Charset {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Charset::Utf8 => "utf-8",
+            Charset::Utf8Bom => "utf-8-bom",
+            Charset::Latin1 => "latin1",
+            Charset::Utf16Be => "utf-16be",
+            Charset::Utf16Le => "utf-16le",
+        }
+    }
+}
+
+impl FileFormat {
+    pub fn new() -> FileFormat {
+        FileFormat {
+            line_ending: LineEnding::Lf,
+            charset: Charset::Utf8,
+            final_newline: true,
+            trim_trailing_whitespace: false,
+            max_line_length: None,
+        }
+    }
+}
+
+pub fn parse_line_ending(value: &str) -> Option<LineEnding> {
+    [LineEnding::Lf, LineEnding::CrLf, LineEnding::Cr].iter().find(|e| e.name() == value).copied()
+}
+
+pub fn parse_charset(value: &str) -> Option<Charset> {
+    [Charset::Utf8, Charset::Utf8Bom, Charset::Latin1, Charset::Utf16Be, Charset::Utf16Le]
+        .iter().find(|c| c.name() == value).copied()
+}
+
+// Splits a file into rows, decoding it from `charset` or from the charset its
+// byte order mark names. The charset, line ending and final newline found are
+// recorded in `format` so saving writes the file back the same way.
+pub fn read_rows(bytes: &[u8], charset: Option<Charset>, format: &mut FileFormat) -> Vec<Vec<u8>> {
+    let charset = match charset {
+        Some(charset) => charset,
+        None if bytes.starts_with(&UTF8_BOM) => Charset::Utf8Bom,
+        None if bytes.starts_with(&[0xff, 0xfe]) => Charset::Utf16Le,
+        None if bytes.starts_with(&[0xfe, 0xff]) => Charset::Utf16Be,
+        None => Charset::Utf8,
+    };
+    format.charset = charset;
+    let text = decode(bytes, charset);
+    format.line_ending = match text.iter().position(|c| *c == b'\n') {
+        Some(pos) if pos > 0 && text[pos - 1] == b'\r' => LineEnding::CrLf,
+        Some(_) => LineEnding::Lf,
+        None if text.contains(&b'\r') => LineEnding::Cr,
+        None => LineEnding::Lf,
+    };
+    let separator = if format.line_ending == LineEnding::Cr { b'\r' } else { b'\n' };
+    format.final_newline = text.is_empty() || text.ends_with(&[separator]);
+    let mut rows: Vec<Vec<u8>> = text.split(|c| *c == separator).map(|row| {
+        let mut row = row.to_vec();
+        if row.ends_with(b"\r") {
+            row.pop();
+        }
+        row
+    }).collect();
+    if format.final_newline {
+        rows.pop();
+    }
+    rows
+}
+
+pub fn write_rows(rows: &[&[u8]], format: &FileFormat) -> Vec<u8> {
+    let mut text: Vec<u8> = Vec::new();
+    for (idx, row) in rows.iter().enumerate() {
+        text.extend_from_slice(row);
+        if idx + 1 < rows.len() || format.final_newline {
+            text.extend_from_slice(format.line_ending.as_bytes());
+        }
+    }
+    encode(&text, format.charset)
+}
+
+fn decode(bytes: &[u8], charset: Charset) -> Vec<u8> {
+    match charset {
+        Charset::Utf8 => bytes.to_vec(),
+        Charset::Utf8Bom => bytes.strip_prefix(&UTF8_BOM[..]).unwrap_or(bytes).to_vec(),
+        Charset::Latin1 => bytes.iter().map(|c| *c as char).collect::<String>().into_bytes(),
+        Charset::Utf16Be | Charset::Utf16Le => {
+            let units: Vec<u16> = bytes.chunks(2).filter(|pair| pair.len() == 2).map(|pair| {
+                if charset == Charset::Utf16Be {
+                    u16::from_be_bytes([pair[0], pair[1]])
+                }else{
+                    u16::from_le_bytes([pair[0], pair[1]])
+                }
+            }).collect();
+            let units = match units.first() {
+                Some(0xfeff) => &units[1..],
+                _ => &units[..],
+            };
+            String::from_utf16_lossy(units).into_bytes()
+        },
+    }
+}
+
+// Characters latin1 cannot hold are written as `?`.
+fn encode(text: &[u8], charset: Charset) -> Vec<u8> {
+    match charset {
+        Charset::Utf8 => text.to_vec(),
+        Charset::Utf8Bom => {
+            let mut bytes = UTF8_BOM.to_vec();
+            bytes.extend_from_slice(text);
+            bytes
+        },
+        Charset::Latin1 => {
+            String::from_utf8_lossy(text).chars()
+                .map(|c| if (c as u32) < 256 { c as u8 } else { b'?' })
+                .collect()
+        },
+        Charset::Utf16Be | Charset::Utf16Le => {
+            let mut bytes: Vec<u8> = Vec::new();
+            for unit in std::iter::once(0xfeff).chain(String::from_utf8_lossy(text).encode_utf16()) {
+                if charset == Charset::Utf16Be {
+                    bytes.extend_from_slice(&unit.to_be_bytes());
+                }else{
+                    bytes.extend_from_slice(&unit.to_le_bytes());
+                }
+            }
+            bytes
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_and_writes_files_back_the_same_way() {
+        for (bytes, rows, line_ending, charset, final_newline) in [
+            (&b"a\nb\n"[..], vec!["a", "b"], "lf", "utf-8", true),
+            (b"a\r\nb", vec!["a", "b"], "crlf", "utf-8", false),
+            (b"a\rb\r", vec!["a", "b"], "cr", "utf-8", true),
+            (b"\n\n", vec!["", ""], "lf", "utf-8", true),
+            (b"", vec![], "lf", "utf-8", true),
+            (b"\xef\xbb\xbfx\r\n", vec!["x"], "crlf", "utf-8-bom", true),
+            (b"\xff\xfeh\0\xe9\0\n\0", vec!["h\u{e9}"], "lf", "utf-16le", true),
+            (b"\xfe\xff\0h\0\xe9\0\n", vec!["h\u{e9}"], "lf", "utf-16be", true),
+        ].iter() {
+            let mut format = FileFormat::new();
+            let read = read_rows(bytes, None, &mut format);
+            let expected: Vec<Vec<u8>> = rows.iter().map(|row| row.as_bytes().to_vec()).collect();
+            assert_eq!(read, expected);
+            assert_eq!(format.line_ending.name(), *line_ending);
+            assert_eq!(format.charset.name(), *charset);
+            assert_eq!(format.final_newline, *final_newline);
+            let read: Vec<&[u8]> = read.iter().map(|row| &row[..]).collect();
+            assert_eq!(write_rows(&read, &format), bytes.to_vec());
+        }
+    }
+
+    #[test]
+    fn converts_latin1_when_asked() {
+        let mut format = FileFormat::new();
+        let rows = read_rows(b"caf\xe9\n", Some(Charset::Latin1), &mut format);
+        assert_eq!(rows, vec!["caf\u{e9}".as_bytes().to_vec()]);
+        assert_eq!(write_rows(&[&rows[0][..], "\u{20ac}".as_bytes()], &format), b"caf\xe9\n?\n".to_vec());
+    }
+
+    #[test]
+    fn writes_the_chosen_line_ending() {
+        let mut format = FileFormat::new();
+        format.line_ending = parse_line_ending("crlf").unwrap();
+        format.final_newline = false;
+        assert_eq!(write_rows(&[b"a", b"b"], &format), b"a\r\nb".to_vec());
+        assert!(parse_line_ending("dos").is_none());
+        assert_eq!(parse_charset("utf-16le").map(|c| c.name()), Some("utf-16le"));
+    }
+}
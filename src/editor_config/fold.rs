@@ -0,0 +1,268 @@
+use super::brackets::{BracketMatch, match_bracket};
+use super::erow::Erow;
+use super::RILO_BRACKET_SCAN_ROWS;
+
+// Rows `start + 1` to `end` are hidden under the row `start`.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Fold {
+    pub start: u32,
+    pub end: u32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FoldMethod {
+    Auto,
+    Braces,
+    Indent,
+}
+
+pub fn parse_fold_method(value: &str) -> Result<FoldMethod, String> {
+    match value {
+        "auto" => Ok(FoldMethod::Auto),
+        "braces" => Ok(FoldMethod::Braces),
+        "indent" => Ok(FoldMethod::Indent),
+        _ => Err(format!("invalid fold method '{}' (expected auto, braces or indent)", value)),
+    }
+}
+
+// The closed folds of a buffer. Folds may nest; rows move with the edits
+// made above them.
+pub struct Folds {
+    folds: Vec<Fold>,
+}
+
+impl Folds {
+    pub fn new() -> Folds {
+        Folds { folds: Vec::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.folds.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.folds.clear();
+    }
+
+    pub fn add(&mut self, fold: Fold) {
+        if fold.end > fold.start && !self.folds.contains(&fold) {
+            self.folds.push(fold);
+        }
+    }
+
+    pub fn remove(&mut self, fold: Fold) {
+        self.folds.retain(|f| *f != fold);
+    }
+
+    pub fn is_hidden(&self, row: u32) -> bool {
+        self.folds.iter().any(|f| f.start < row && row <= f.end)
+    }
+
+    // The outermost fold hiding `row`.
+    pub fn hiding(&self, row: u32) -> Option<Fold> {
+        self.folds.iter().filter(|f| f.start < row && row <= f.end).min_by_key(|f| f.start).copied()
+    }
+
+    // The largest fold starting at `row`, which is what the row shows.
+    pub fn at(&self, row: u32) -> Option<Fold> {
+        self.folds.iter().filter(|f| f.start == row).max_by_key(|f| f.end).copied()
+    }
+
+    // Opens every fold hiding `row`.
+    pub fn reveal(&mut self, row: u32) {
+        self.folds.retain(|f| !(f.start < row && row <= f.end));
+    }
+
+    pub fn insert_row(&mut self, at: u32) {
+        for fold in self.folds.iter_mut() {
+            if fold.start >= at {
+                fold.start += 1;
+                fold.end += 1;
+            }else if fold.end >= at {
+                fold.end += 1;
+            }
+        }
+    }
+
+    // A fold loses its row; one whose first row goes is dropped.
+    pub fn delete_row(&mut self, at: u32) {
+        self.folds.retain(|f| f.start != at);
+        for fold in self.folds.iter_mut() {
+            if fold.start > at {
+                fold.start -= 1;
+                fold.end -= 1;
+            }else if fold.end >= at {
+                fold.end -= 1;
+            }
+        }
+        self.folds.retain(|f| f.end > f.start);
+    }
+
+    // Keeps the folds in the `prefix` rows that did not change and moves the
+    // ones in the unchanged `suffix` rows by `delta` when rows are replaced.
+    pub fn replace_rows(&mut self, prefix: u32, suffix_start: u32, delta: i64) {
+        self.folds.retain(|f| f.end < prefix || f.start >= suffix_start);
+        for fold in self.folds.iter_mut() {
+            if fold.start >= suffix_start {
+                fold.start = (fold.start as i64 + delta) as u32;
+                fold.end = (fold.end as i64 + delta) as u32;
+            }
+        }
+    }
+}
+
+// The last row of the region starting at `row`: the row of the partner of the
+// last bracket opened on it, or the rows indented under it. Brackets are only
+// found in rows that are highlighted.
+pub fn fold_region(rows: &[Erow], row: usize, method: FoldMethod, tab_stop: usize) -> Option<usize> {
+    if method != FoldMethod::Indent {
+        if let Some(end) = bracket_region(rows, row) {
+            return Some(end);
+        }
+        if method == FoldMethod::Braces {
+            return None;
+        }
+    }
+    indent_region(rows, row, tab_stop)
+}
+
+fn bracket_region(rows: &[Erow], row: usize) -> Option<usize> {
+    let erow = rows.get(row)?;
+    for rx in (0..erow._rsize as usize).rev() {
+        if !matches!(erow.render[rx], b'{' | b'(' | b'[') {
+            continue;
+        }
+        if let Some(BracketMatch::Matched(mrow, _)) = match_bracket(rows, row, rx, RILO_BRACKET_SCAN_ROWS) {
+            if mrow <= row {
+                continue;
+            }
+            // A row going on after the closer, like `} else {`, stays visible.
+            let end = if is_closer_row(&rows[mrow]) { mrow } else { mrow - 1 };
+            return if end > row { Some(end) } else { None };
+        }
+    }
+    None
+}
+
+// Whether the row holds nothing but closers and separators, like `});`.
+pub fn is_closer_row(erow: &Erow) -> bool {
+    let text = erow_text(erow);
+    let text = &text[text.iter().take_while(|c| c.is_ascii_whitespace()).count()..];
+    !text.is_empty() && text.iter().all(|c| b")]};,".contains(c))
+}
+
+// The last row of the block under `row`: the following rows indented deeper,
+// with the blank rows among them but not after them.
+fn indent_region(rows: &[Erow], row: usize, tab_stop: usize) -> Option<usize> {
+    let indent = indent_width(erow_text(rows.get(row)?), tab_stop)?;
+    let mut end = None;
+    for (idx, erow) in rows.iter().enumerate().skip(row + 1) {
+        match indent_width(erow_text(erow), tab_stop) {
+            Some(width) if width > indent => end = Some(idx),
+            Some(_) => break,
+            None => (),
+        }
+    }
+    end
+}
+
+fn erow_text(erow: &Erow) -> &[u8] {
+    &erow.chars[..erow.size as usize]
+}
+
+// The display width of a row's indentation; None for a blank row.
+fn indent_width(row: &[u8], tab_stop: usize) -> Option<usize> {
+    let mut width = 0;
+    for c in row {
+        match c {
+            b' ' => width += 1,
+            b'\t' => width += tab_stop - width % tab_stop,
+            _ => return Some(width),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Highlight, RowState};
+
+    // Rows without tabs, highlighted as plain code.
+    fn rows(text: &[&str]) -> Vec<Erow> {
+        text.iter().map(|row| {
+            let mut chars = row.as_bytes().to_vec();
+            chars.push(b'\0');
+            Erow {
+                size: row.len() as u16,
+                chars,
+                _rsize: row.len() as u16,
+                render: row.as_bytes().to_vec(),
+                hl: vec![Highlight::NORMAL; row.len()],
+                hl_state: RowState::new(),
+                hl_stale: false,
+                tree: Vec::new(),
+                symbol: None,
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn folds_bracket_regions() {
+        let rows = rows(&[
+            "fn f() {",
+            "    if x {",
+            "        a();",
+            "    } else {",
+            "        b();",
+            "    }",
+            "    g(|| {",
+            "        c",
+            "    });",
+            "}",
+        ]);
+        for (row, end) in [(0, Some(9)), (1, Some(2)), (3, Some(5)), (6, Some(8)), (2, None), (9, None)].iter() {
+            assert_eq!(fold_region(&rows, *row, FoldMethod::Braces, 8), *end, "row {}", row);
+        }
+    }
+
+    #[test]
+    fn folds_indented_regions() {
+        let python = rows(&["def f():", "    a", "", "    b", "", "x = [", "  1]", "def g():", "  pass"]);
+        for (row, method, end) in [
+            (0, FoldMethod::Indent, Some(3)),
+            (0, FoldMethod::Auto, Some(3)),
+            (0, FoldMethod::Braces, None),
+            (5, FoldMethod::Auto, Some(6)),
+            (5, FoldMethod::Indent, Some(6)),
+            (7, FoldMethod::Indent, Some(8)),
+            (1, FoldMethod::Indent, None),
+            (2, FoldMethod::Indent, None),
+        ].iter() {
+            assert_eq!(fold_region(&python, *row, *method, 8), *end, "row {}", row);
+        }
+        let tabs = rows(&["a", "\tb", "        c", "d"]);
+        assert_eq!(fold_region(&tabs, 1, FoldMethod::Indent, 8), None);
+        assert_eq!(fold_region(&tabs, 1, FoldMethod::Indent, 4), Some(2));
+    }
+
+    #[test]
+    fn moves_folds_with_edits() {
+        let mut folds = Folds::new();
+        folds.add(Fold { start: 2, end: 5 });
+        folds.add(Fold { start: 3, end: 4 });
+        folds.add(Fold { start: 8, end: 8 });
+        assert!(folds.is_hidden(4) && !folds.is_hidden(2) && !folds.is_hidden(6));
+        let end = |fold: Option<Fold>| fold.map(|f| f.end);
+        assert_eq!(folds.hiding(4).map(|f| (f.start, f.end)), Some((2, 5)));
+        folds.insert_row(4);
+        assert_eq!((end(folds.at(2)), end(folds.at(3))), (Some(6), Some(5)));
+        folds.delete_row(3);
+        assert_eq!((end(folds.at(2)), end(folds.at(3))), (Some(5), None));
+        folds.add(Fold { start: 10, end: 12 });
+        folds.replace_rows(4, 9, 2);
+        assert_eq!((end(folds.at(2)), end(folds.at(12))), (None, Some(14)));
+        folds.reveal(13);
+        assert!(folds.is_empty());
+    }
+}
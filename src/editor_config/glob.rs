@@ -0,0 +1,285 @@
+// Shell style wildcard matching: `*`, `?`, `[abc]`, `[a-z]`, `[!abc]` and `\` escapes.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    match_from(&p, &t)
+}
+
+fn match_from(p: &[char], t: &[char]) -> bool {
+    let mut pi = 0;
+    let mut ti = 0;
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() {
+            match p[pi] {
+                '*' => {
+                    star = Some((pi, ti));
+                    pi += 1;
+                    continue;
+                },
+                '?' => {
+                    pi += 1;
+                    ti += 1;
+                    continue;
+                },
+                '[' => {
+                    if let Some((matched, next)) = match_class(p, pi, t[ti]) {
+                        if matched {
+                            pi = next;
+                            ti += 1;
+                            continue;
+                        }
+                    }else if t[ti] == '[' {
+                        pi += 1;
+                        ti += 1;
+                        continue;
+                    }
+                },
+                '\\' if pi + 1 < p.len() => {
+                    if p[pi + 1] == t[ti] {
+                        pi += 2;
+                        ti += 1;
+                        continue;
+                    }
+                },
+                c => {
+                    if c == t[ti] {
+                        pi += 1;
+                        ti += 1;
+                        continue;
+                    }
+                },
+            }
+        }
+        match star {
+            Some((sp, st)) => {
+                pi = sp + 1;
+                ti = st + 1;
+                star = Some((sp, st + 1));
+            },
+            None => return false,
+        }
+    }
+    while pi < p.len() && p[pi] == '*' {
+        pi += 1;
+    }
+    pi == p.len()
+}
+
+// Returns whether `c` is in the class starting at `p[start]` and the index after the class,
+// or None when the class is not terminated.
+fn match_class(p: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut idx = start + 1;
+    let negate = idx < p.len() && (p[idx] == '!' || p[idx] == '^');
+    if negate {
+        idx += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    while idx < p.len() && (first || p[idx] != ']') {
+        first = false;
+        let lo = p[idx];
+        if idx + 2 < p.len() && p[idx + 1] == '-' && p[idx + 2] != ']' {
+            if lo <= c && c <= p[idx + 2] {
+                matched = true;
+            }
+            idx += 3;
+        }else{
+            if lo == c {
+                matched = true;
+            }
+            idx += 1;
+        }
+    }
+    if idx >= p.len() {
+        return None;
+    }
+    Some((matched != negate, idx + 1))
+}
+
+// Path wildcards as used by `.editorconfig`: `*` and `?` stop at `/`, `**` crosses it,
+// and `{a,b}` or `{1..3}` match any of the alternatives.
+pub fn glob_match_path(pattern: &str, path: &str) -> bool {
+    let t: Vec<char> = path.chars().collect();
+    expand_braces(pattern).iter().any(|p| {
+        let p: Vec<char> = p.chars().collect();
+        match_path(&p, &t)
+    })
+}
+
+fn match_path(p: &[char], t: &[char]) -> bool {
+    if p.is_empty() {
+        return t.is_empty();
+    }
+    match p[0] {
+        '*' if p.len() > 1 && p[1] == '*' => {
+            // `a/**/b` also matches `a/b`.
+            if p.len() > 2 && p[2] == '/' && match_path(&p[3..], t) {
+                return true;
+            }
+            (0..=t.len()).any(|idx| match_path(&p[2..], &t[idx..]))
+        },
+        '*' => {
+            let mut idx = 0;
+            loop {
+                if match_path(&p[1..], &t[idx..]) {
+                    return true;
+                }
+                if idx == t.len() || t[idx] == '/' {
+                    return false;
+                }
+                idx += 1;
+            }
+        },
+        _ if t.is_empty() => false,
+        '?' => t[0] != '/' && match_path(&p[1..], &t[1..]),
+        '[' => {
+            match match_class(p, 0, t[0]) {
+                Some((matched, next)) => matched && t[0] != '/' && match_path(&p[next..], &t[1..]),
+                None => t[0] == '[' && match_path(&p[1..], &t[1..]),
+            }
+        },
+        '\\' if p.len() > 1 => p[1] == t[0] && match_path(&p[2..], &t[1..]),
+        c => c == t[0] && match_path(&p[1..], &t[1..]),
+    }
+}
+
+// Expands the brace groups of a pattern into one pattern per alternative.
+// A group without a comma or a number range is taken literally.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let p: Vec<char> = pattern.chars().collect();
+    let mut idx = 0;
+    while idx < p.len() {
+        if p[idx] == '\\' {
+            idx += 2;
+            continue;
+        }
+        if p[idx] == '{' {
+            if let Some(end) = closing_brace(&p, idx) {
+                let inner: String = p[idx + 1..end].iter().collect();
+                if let Some(alternatives) = brace_alternatives(&inner) {
+                    let prefix: String = p[..idx].iter().collect();
+                    let suffix: String = p[end + 1..].iter().collect();
+                    return alternatives.iter()
+                        .flat_map(|alt| expand_braces(&format!("{}{}{}", prefix, alt, suffix)))
+                        .collect();
+                }
+            }
+        }
+        idx += 1;
+    }
+    vec![String::from(pattern)]
+}
+
+fn closing_brace(p: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut idx = start;
+    while idx < p.len() {
+        match p[idx] {
+            '\\' => idx += 1,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(idx);
+                }
+            },
+            _ => (),
+        }
+        idx += 1;
+    }
+    None
+}
+
+// Ranges longer than this are taken literally.
+const MAX_BRACE_RANGE: i64 = 1000;
+
+fn brace_alternatives(inner: &str) -> Option<Vec<String>> {
+    let mut parts: Vec<String> = Vec::new();
+    let mut part = String::new();
+    let mut depth = 0;
+    let mut escaped = false;
+    for c in inner.chars() {
+        if escaped {
+            escaped = false;
+        }else if c == '\\' {
+            escaped = true;
+        }else if c == '{' {
+            depth += 1;
+        }else if c == '}' {
+            depth -= 1;
+        }else if c == ',' && depth == 0 {
+            parts.push(part);
+            part = String::new();
+            continue;
+        }
+        part.push(c);
+    }
+    parts.push(part);
+    if parts.len() > 1 {
+        return Some(parts);
+    }
+    let pos = inner.find("..")?;
+    let lo = inner[..pos].parse::<i64>().ok()?;
+    let hi = inner[pos + 2..].parse::<i64>().ok()?;
+    let (lo, hi) = (lo.min(hi), lo.max(hi));
+    if hi - lo > MAX_BRACE_RANGE {
+        return None;
+    }
+    Some((lo..=hi).map(|n| n.to_string()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_shell_wildcards() {
+        for (pattern, text, matched) in [
+            ("*.rs", "main.rs", true),
+            ("*.rs", "main.rc", false),
+            ("Makefile*", "Makefile.am", true),
+            ("?.c", "a.c", true),
+            ("?.c", "ab.c", false),
+            ("[abc].h", "b.h", true),
+            ("[a-c].h", "d.h", false),
+            ("[!a-c].h", "d.h", true),
+            ("[^x]", "x", false),
+            ("\\*.md", "*.md", true),
+            ("\\*.md", "a.md", false),
+            ("[unclosed", "[unclosed", true),
+            ("*a*b", "xaybzb", true),
+            ("", "", true),
+        ].iter() {
+            assert_eq!(glob_match(pattern, text), *matched, "{} ~ {}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn matches_editorconfig_paths() {
+        for (pattern, path, matched) in [
+            ("*.py", "main.py", true),
+            ("*.py", "lib/main.py", false),
+            ("lib/*.py", "lib/main.py", true),
+            ("**.py", "lib/a/main.py", true),
+            ("lib/**/test.py", "lib/test.py", true),
+            ("lib/**/test.py", "lib/a/b/test.py", true),
+            ("lib/**/test.py", "src/test.py", false),
+            ("?.c", "/.c", false),
+            ("[!/]x", "/x", false),
+            ("*.{js,ts}", "a.ts", true),
+            ("*.{js,ts}", "a.rs", false),
+            ("{lib,src}/**.{c,h}", "src/x/y.h", true),
+            ("{a,{b,c}}.txt", "c.txt", true),
+            ("{single}.txt", "{single}.txt", true),
+            ("file{1..3}.txt", "file2.txt", true),
+            ("file{1..3}.txt", "file4.txt", false),
+            ("file{3..1}.txt", "file1.txt", true),
+            ("file{-1..1}.txt", "file-1.txt", true),
+            ("file{0..5000}.txt", "file{0..5000}.txt", true),
+            ("\\{a,b\\}", "{a,b}", true),
+        ].iter() {
+            assert_eq!(glob_match_path(pattern, path), *matched, "{} ~ {}", pattern, path);
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use super::{Erow, RowState, EditorSyntaxInf};
+use super::parser::StructuralHighlighter;
+
+// Highlights one row at a time starting in `before`, the state the previous
+// row ended in, and returns whether the state at the end of the row changed.
+// Rows keep their end state, so a highlighter can resume at any row after an edit.
+pub trait Highlighter {
+    fn highlight_row(&self, row: &mut Erow, before: &RowState) -> bool;
+}
+
+// The token rules of the syntax definition.
+pub struct RuleHighlighter {
+    syntax: EditorSyntaxInf,
+}
+
+impl Highlighter for RuleHighlighter {
+    fn highlight_row(&self, row: &mut Erow, before: &RowState) -> bool {
+        row.tree.clear();
+        row.editor_update_syntax(&self.syntax, before.open)
+    }
+}
+
+// Uses the structural parser named by the definition's `parser` key when
+// `structural` is on, and the rule engine otherwise.
+pub fn new_highlighter(syntax: &EditorSyntaxInf, structural: bool) -> Box<dyn Highlighter> {
+    if let Some(es) = &syntax.syntax {
+        if let (Some(language), true) = (es.parser, structural) {
+            return Box::new(StructuralHighlighter::new(es.clone(), language));
+        }
+    }
+    Box::new(RuleHighlighter { syntax: syntax.clone() })
+}
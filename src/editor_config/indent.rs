@@ -0,0 +1,98 @@
+// Rows looked at when guessing the indentation of a file.
+const DETECT_ROWS: usize = 1000;
+
+// Tabs are displayed `tab_stop` wide; Tab, Backspace and dedenting step by
+// `indent_size` columns of spaces when `expand_tabs` is on.
+#[derive(Clone, Copy, PartialEq)]
+pub struct IndentStyle {
+    pub tab_stop: u16,
+    pub indent_size: u16,
+    pub expand_tabs: bool,
+}
+
+impl IndentStyle {
+    pub fn new(width: u16, expand_tabs: bool) -> IndentStyle {
+        IndentStyle { tab_stop: width, indent_size: width, expand_tabs }
+    }
+
+    pub fn describe(&self) -> String {
+        if self.expand_tabs {
+            format!("spaces:{}", self.indent_size)
+        }else{
+            format!("tabs:{}", self.tab_stop)
+        }
+    }
+}
+
+// Tabs win when more rows start with a tab than with spaces. For spaces the
+// width is the most common step between the indents of neighbouring rows,
+// which skips the odd one-space continuation of a block comment.
+pub fn detect_indent(rows: &[&[u8]], fallback: IndentStyle) -> IndentStyle {
+    let mut tabs = 0;
+    let mut spaces = 0;
+    let mut steps = [0usize; 9];
+    let mut prev = 0;
+    for row in rows.iter().take(DETECT_ROWS) {
+        if row.iter().all(|c| *c == b' ' || *c == b'\t') {
+            continue;
+        }
+        if row[0] == b'\t' {
+            tabs += 1;
+            prev = 0;
+            continue;
+        }
+        let indent = row.iter().take_while(|c| **c == b' ').count();
+        if indent > 0 {
+            spaces += 1;
+        }
+        let step = indent.abs_diff(prev);
+        if (2..steps.len()).contains(&step) {
+            steps[step] += 1;
+        }
+        prev = indent;
+    }
+    if tabs == 0 && spaces == 0 {
+        return fallback;
+    }
+    if tabs > spaces {
+        return IndentStyle { expand_tabs: false, ..fallback };
+    }
+    let mut width = fallback.indent_size;
+    let mut best = 0;
+    for (step, count) in steps.iter().enumerate() {
+        if *count > best {
+            best = *count;
+            width = step as u16;
+        }
+    }
+    IndentStyle { tab_stop: fallback.tab_stop, indent_size: width, expand_tabs: true }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(text: &str, fallback: IndentStyle) -> (u16, u16, bool) {
+        let rows: Vec<&[u8]> = text.lines().map(|line| line.as_bytes()).collect();
+        let style = detect_indent(&rows, fallback);
+        (style.tab_stop, style.indent_size, style.expand_tabs)
+    }
+
+    #[test]
+    fn guesses_the_indentation_of_a_file() {
+        let tabs = IndentStyle::new(8, false);
+        let spaces = IndentStyle::new(4, true);
+        for (text, fallback, expected) in [
+            ("fn f() {\n    if x {\n        y\n    }\n}\n", tabs, (8, 4, true)),
+            ("a:\n  b:\n    c\n  d\n", tabs, (8, 2, true)),
+            ("int f() {\n\treturn 0;\n}\n", spaces, (4, 4, false)),
+            ("\tone\n\ttwo\n    three\n", spaces, (4, 4, false)),
+            // The one-space continuation of a block comment is not a step.
+            ("/*\n * a\n * b\n */\nint f() {\n    x;\n}\n", tabs, (8, 4, true)),
+            ("no indentation\nat all\n", spaces, (4, 4, true)),
+            ("\n   \n\t\n", tabs, (8, 8, false)),
+        ].iter() {
+            assert_eq!(detect(text, *fallback), *expected, "{:?}", text);
+        }
+    }
+}
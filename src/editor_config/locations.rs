@@ -0,0 +1,128 @@
+use super::panel::ListItem;
+
+use std::path::Path;
+
+// Finds the file locations in compiler output: rustc's `--> file:line:col`
+// under an `error:` or `warning:` line, and `file:line:col: message` or
+// `file:line: message` as printed by gcc and most other tools. Locations in
+// files that do not exist are skipped.
+pub fn parse_locations(output: &str) -> Vec<ListItem> {
+    let mut items: Vec<ListItem> = Vec::new();
+    let mut header = "";
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if let Some(location) = trimmed.strip_prefix("--> ") {
+            if let Some(mut item) = parse_location(location.trim()) {
+                item.text = String::from(header);
+                push_item(&mut items, item);
+            }
+        }else if trimmed.starts_with("error") || trimmed.starts_with("warning") {
+            header = trimmed;
+        }else if let Some(item) = parse_location(trimmed) {
+            push_item(&mut items, item);
+        }
+    }
+    items
+}
+
+fn push_item(items: &mut Vec<ListItem>, item: ListItem) {
+    if Path::new(&item.file).is_file() {
+        items.push(item);
+    }
+}
+
+fn parse_location(text: &str) -> Option<ListItem> {
+    let mut parts = text.splitn(3, ':');
+    let file = parts.next()?;
+    if file.is_empty() || file.contains(char::is_whitespace) {
+        return None;
+    }
+    let line = parts.next()?.parse::<u32>().ok()?;
+    let rest = parts.next().unwrap_or("");
+    let (col, message) = match rest.find(':') {
+        Some(pos) if rest[..pos].parse::<u32>().is_ok() => {
+            (rest[..pos].parse::<u32>().unwrap(), &rest[pos + 1..])
+        },
+        _ => match rest.parse::<u32>() {
+            Ok(col) => (col, ""),
+            Err(_) => (0, rest),
+        },
+    };
+    Some(ListItem {
+        file: String::from(file),
+        line,
+        col,
+        text: String::from(message.trim()),
+        span: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests run from the crate root, where these files exist.
+    fn locations(output: &str) -> Vec<(String, u32, u32, String)> {
+        parse_locations(output).into_iter().map(|item| (item.file, item.line, item.col, item.text)).collect()
+    }
+
+    fn found(file: &str, line: u32, col: u32, text: &str) -> (String, u32, u32, String) {
+        (String::from(file), line, col, String::from(text))
+    }
+
+    #[test]
+    fn finds_rustc_locations_under_their_message() {
+        let output = "\
+warning: unused variable: `x`
+  --> src/main.rs:10:9
+   |
+10 |     let x = 1;
+   |         ^ help: if this is intentional, prefix it with an underscore: `_x`
+   |
+   = note: `#[warn(unused_variables)]` on by default
+
+error[E0308]: mismatched types
+   --> src/editor_config.rs:120:5
+    |
+120 |     x
+    |     ^ expected `u32`, found `usize`
+
+error: could not compile `rilo` (bin \"rilo\") due to 1 previous error
+";
+        assert_eq!(locations(output), vec![
+            found("src/main.rs", 10, 9, "warning: unused variable: `x`"),
+            found("src/editor_config.rs", 120, 5, "error[E0308]: mismatched types"),
+        ]);
+    }
+
+    #[test]
+    fn finds_gcc_style_locations() {
+        let output = "\
+src/main.rs: In function 'main':
+src/main.rs:12:5: error: 'y' undeclared (first use in this function)
+   12 |     y = 2;
+      |     ^
+src/main.rs:12:5: note: each undeclared identifier is reported only once
+make: *** [Makefile:2: main] Error 1
+";
+        assert_eq!(locations(output), vec![
+            found("src/main.rs", 12, 5, "error: 'y' undeclared (first use in this function)"),
+            found("src/main.rs", 12, 5, "note: each undeclared identifier is reported only once"),
+        ]);
+    }
+
+    #[test]
+    fn finds_generic_locations_in_existing_files_only() {
+        let output = "\
+Cargo.toml:3: unknown key
+Cargo.toml:4:12
+missing.c:1:1: error: no such file
+./README.md:1:2:3: odd
+";
+        assert_eq!(locations(output), vec![
+            found("Cargo.toml", 3, 0, "unknown key"),
+            found("Cargo.toml", 4, 12, ""),
+            found("./README.md", 1, 2, "3: odd"),
+        ]);
+    }
+}
@@ -0,0 +1,541 @@
+use super::json::Json;
+use super::panel::ListItem;
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+// What a request was for, so its response can be handled when it arrives.
+#[derive(Clone, Copy, PartialEq)]
+pub enum LspRequest {
+    Initialize,
+    Hover,
+    Definition,
+    References,
+    Rename,
+    Completion,
+    Shutdown,
+}
+
+pub enum LspEvent {
+    Response(LspRequest, Json),
+    Diagnostics(String, Vec<Diagnostic>),
+    Message(String),
+    Exited,
+}
+
+// Positions are the server's: 0-based lines and UTF-16 columns.
+#[derive(Clone)]
+pub struct Diagnostic {
+    pub line: u32,
+    pub start: u32,
+    pub end_line: u32,
+    pub end: u32,
+    pub severity: u32,
+    pub message: String,
+}
+
+// A language server child process speaking JSON-RPC on its stdin and stdout.
+// Messages to it are written on one thread, so a server that stops reading
+// cannot block the editor, and its output is read on another and picked up
+// with `poll`.
+pub struct LspClient {
+    pub language: String,
+    child: Child,
+    outgoing: Sender<String>,
+    messages: Receiver<Option<Json>>,
+    next_id: i64,
+    pending: Vec<(i64, LspRequest)>,
+    initialized: bool,
+    queue: Vec<Json>,
+}
+
+impl LspClient {
+    pub fn start(command: &str, language: &str, root: &Path) -> Result<LspClient, String> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("{}: {}", command, e))?;
+        let mut stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (outgoing, bodies) = channel::<String>();
+        thread::spawn(move || {
+            for body in bodies {
+                let written = write!(stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+                    .and_then(|_| stdin.flush());
+                if written.is_err() {
+                    return;
+                }
+            }
+        });
+        let (sender, messages) = channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            while let Some(message) = read_message(&mut reader) {
+                if sender.send(Some(message)).is_err() {
+                    return;
+                }
+            }
+            let _ = sender.send(None);
+        });
+        let mut client = LspClient {
+            language: String::from(language),
+            child,
+            outgoing,
+            messages,
+            next_id: 1,
+            pending: Vec::new(),
+            initialized: false,
+            queue: Vec::new(),
+        };
+        let capabilities = Json::object(vec![
+            ("textDocument", Json::object(vec![
+                ("synchronization", Json::object(vec![("didSave", Json::from(true))])),
+                ("hover", Json::object(vec![("contentFormat", Json::from(vec![Json::from("plaintext")]))])),
+                ("definition", Json::object(vec![])),
+                ("references", Json::object(vec![])),
+                ("rename", Json::object(vec![])),
+                ("completion", Json::object(vec![])),
+                ("publishDiagnostics", Json::object(vec![])),
+            ])),
+        ]);
+        client.request(LspRequest::Initialize, "initialize", Json::object(vec![
+            ("processId", Json::from(std::process::id())),
+            ("rootUri", Json::from(path_to_uri(root))),
+            ("capabilities", capabilities),
+        ]));
+        Ok(client)
+    }
+
+    pub fn request(&mut self, kind: LspRequest, method: &str, params: Json) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.push((id, kind));
+        self.send(Json::object(vec![
+            ("jsonrpc", Json::from("2.0")),
+            ("id", Json::from(id)),
+            ("method", Json::from(method)),
+            ("params", params),
+        ]));
+    }
+
+    pub fn notify(&mut self, method: &str, params: Json) {
+        self.send(Json::object(vec![
+            ("jsonrpc", Json::from("2.0")),
+            ("method", Json::from(method)),
+            ("params", params),
+        ]));
+    }
+
+    // Anything but the initialize request waits until the server has answered it.
+    fn send(&mut self, message: Json) {
+        let initialize = message.get("method").and_then(Json::as_str) == Some("initialize");
+        if !self.initialized && !initialize {
+            self.queue.push(message);
+            return;
+        }
+        let _ = self.outgoing.send(message.to_string());
+    }
+
+    pub fn poll(&mut self) -> Vec<LspEvent> {
+        let mut events = Vec::new();
+        while let Ok(message) = self.messages.try_recv() {
+            match message {
+                Some(message) => self.handle(message, &mut events),
+                None => events.push(LspEvent::Exited),
+            }
+        }
+        events
+    }
+
+    fn handle(&mut self, message: Json, events: &mut Vec<LspEvent>) {
+        let method = message.get("method").and_then(Json::as_str).map(String::from);
+        let id = message.get("id").cloned();
+        match (method, id) {
+            // A request from the server: answer it without doing anything.
+            (Some(method), Some(id)) => {
+                let result = match (method.as_str(), message.path(&["params", "items"])) {
+                    ("workspace/configuration", Some(Json::Array(items))) => {
+                        Json::Array(items.iter().map(|_| Json::Null).collect())
+                    },
+                    _ => Json::Null,
+                };
+                self.send(Json::object(vec![
+                    ("jsonrpc", Json::from("2.0")),
+                    ("id", id),
+                    ("result", result),
+                ]));
+            },
+            (Some(method), None) => {
+                let params = message.get("params");
+                if method == "textDocument/publishDiagnostics" {
+                    let uri = params.and_then(|p| p.get("uri")).and_then(Json::as_str).unwrap_or("");
+                    let diagnostics = params.and_then(|p| p.get("diagnostics"))
+                        .and_then(Json::as_array)
+                        .map(|items| items.iter().filter_map(parse_diagnostic).collect())
+                        .unwrap_or_default();
+                    events.push(LspEvent::Diagnostics(String::from(uri), diagnostics));
+                }else if method == "window/showMessage" {
+                    if let Some(text) = params.and_then(|p| p.get("message")).and_then(Json::as_str) {
+                        events.push(LspEvent::Message(String::from(text)));
+                    }
+                }
+            },
+            (None, Some(id)) => {
+                let id = id.as_i64().unwrap_or(-1);
+                let kind = match self.pending.iter().position(|(pending, _)| *pending == id) {
+                    Some(idx) => self.pending.remove(idx).1,
+                    None => return,
+                };
+                if let Some(error) = message.get("error") {
+                    let text = error.get("message").and_then(Json::as_str).unwrap_or("request failed");
+                    events.push(LspEvent::Message(String::from(text)));
+                    return;
+                }
+                if kind == LspRequest::Initialize {
+                    self.initialized = true;
+                    self.notify("initialized", Json::object(vec![]));
+                    for message in std::mem::take(&mut self.queue) {
+                        self.send(message);
+                    }
+                    return;
+                }
+                events.push(LspEvent::Response(kind, message.get("result").cloned().unwrap_or(Json::Null)));
+            },
+            (None, None) => (),
+        }
+    }
+
+    pub fn shutdown(&mut self) {
+        if self.initialized {
+            self.request(LspRequest::Shutdown, "shutdown", Json::Null);
+            self.notify("exit", Json::Null);
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Reads one `Content-Length` framed message; None when the server has gone.
+fn read_message(reader: &mut impl BufRead) -> Option<Json> {
+    loop {
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).ok()? == 0 {
+                return None;
+            }
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok()?;
+            }
+        }
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).ok()?;
+        if let Ok(message) = Json::parse(&String::from_utf8_lossy(&body)) {
+            return Some(message);
+        }
+    }
+}
+
+fn parse_diagnostic(item: &Json) -> Option<Diagnostic> {
+    let range = item.get("range")?;
+    Some(Diagnostic {
+        line: range.path(&["start", "line"])?.as_u32()?,
+        start: range.path(&["start", "character"])?.as_u32()?,
+        end_line: range.path(&["end", "line"])?.as_u32()?,
+        end: range.path(&["end", "character"])?.as_u32()?,
+        severity: item.get("severity").and_then(Json::as_u32).unwrap_or(1),
+        message: String::from(item.get("message")?.as_str()?),
+    })
+}
+
+// The text of a hover result, which may be a string, a MarkedString, a list of
+// them or MarkupContent.
+pub fn hover_text(result: &Json) -> String {
+    match result.get("contents") {
+        Some(Json::Array(items)) => items.iter().map(marked_string).collect::<Vec<_>>().join("\n"),
+        Some(contents) => marked_string(contents),
+        None => String::new(),
+    }
+}
+
+fn marked_string(item: &Json) -> String {
+    match item {
+        Json::String(text) => text.clone(),
+        _ => String::from(item.get("value").and_then(Json::as_str).unwrap_or("")),
+    }
+}
+
+// The places a definition or references result points to, a Location, a list
+// of them or of LocationLinks, with the text of their lines read from disk.
+pub fn parse_lsp_locations(result: &Json) -> Vec<ListItem> {
+    let locations: Vec<&Json> = match result {
+        Json::Array(items) => items.iter().collect(),
+        Json::Null => Vec::new(),
+        location => vec![location],
+    };
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let mut items = Vec::new();
+    for location in locations {
+        let uri = location.get("uri").or_else(|| location.get("targetUri")).and_then(Json::as_str);
+        let start = location.path(&["range", "start"])
+            .or_else(|| location.path(&["targetSelectionRange", "start"]));
+        let (path, start) = match (uri.and_then(uri_to_path), start) {
+            (Some(path), Some(start)) => (path, start),
+            _ => continue,
+        };
+        let line = start.get("line").and_then(Json::as_u32).unwrap_or(0);
+        let character = start.get("character").and_then(Json::as_u32).unwrap_or(0);
+        let text = fs::read(&path).ok()
+            .and_then(|bytes| bytes.split(|c| *c == b'\n').nth(line as usize).map(|row| row.to_vec()))
+            .unwrap_or_default();
+        let file = path.strip_prefix(&cwd).unwrap_or(&path);
+        items.push(ListItem {
+            file: file.to_string_lossy().into_owned(),
+            line: line + 1,
+            col: byte_col(&text, character) as u32 + 1,
+            text: String::from_utf8_lossy(&text).trim().to_string(),
+            span: None,
+        });
+    }
+    items
+}
+
+// The rows after applying LSP text edits, last first so earlier offsets stay
+// valid.
+pub fn apply_text_edits(rows: &[&[u8]], edits: &[Json]) -> Vec<Vec<u8>> {
+    let mut text: Vec<u8> = Vec::new();
+    let mut starts: Vec<usize> = Vec::new();
+    for (idx, row) in rows.iter().enumerate() {
+        starts.push(text.len());
+        text.extend_from_slice(row);
+        if idx + 1 < rows.len() {
+            text.push(b'\n');
+        }
+    }
+    let offset = |pos: &Json| -> Option<usize> {
+        let line = pos.get("line")?.as_u32()? as usize;
+        let character = pos.get("character")?.as_u32()?;
+        match rows.get(line) {
+            Some(row) => Some(starts[line] + byte_col(row, character)),
+            None => Some(text.len()),
+        }
+    };
+    let mut changes: Vec<(usize, usize, Vec<u8>)> = edits.iter().filter_map(|edit| {
+        let start = offset(edit.path(&["range", "start"])?)?;
+        let end = offset(edit.path(&["range", "end"])?)?;
+        let new_text = edit.get("newText").and_then(Json::as_str).unwrap_or("");
+        Some((start, end.max(start), new_text.as_bytes().to_vec()))
+    }).collect();
+    changes.sort_by_key(|change| std::cmp::Reverse(change.0));
+    for (start, end, new_text) in changes {
+        text.splice(start..end, new_text);
+    }
+    text.split(|c| *c == b'\n').map(|row| row.to_vec()).collect()
+}
+
+pub fn position(line: u32, character: u32) -> Json {
+    Json::object(vec![("line", Json::from(line)), ("character", Json::from(character))])
+}
+
+pub fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for b in path.to_string_lossy().bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            uri.push(b as char);
+        }else{
+            uri.push_str(&format!("%{:02X}", b));
+        }
+    }
+    uri
+}
+
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut idx = 0;
+    while idx < path.len() {
+        if path[idx] == b'%' && idx + 2 < path.len() {
+            let hex = std::str::from_utf8(&path[idx + 1..idx + 3]).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            idx += 3;
+        }else{
+            bytes.push(path[idx]);
+            idx += 1;
+        }
+    }
+    Some(PathBuf::from(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+// Converts between byte columns of a row and the UTF-16 columns of the protocol.
+pub fn utf16_col(row: &[u8], byte_col: usize) -> u32 {
+    let end = byte_col.min(row.len());
+    String::from_utf8_lossy(&row[..end]).encode_utf16().count() as u32
+}
+
+pub fn byte_col(row: &[u8], utf16: u32) -> usize {
+    let text = String::from_utf8_lossy(row);
+    let mut units = 0;
+    for (idx, c) in text.char_indices() {
+        if units >= utf16 {
+            return idx;
+        }
+        units += c.len_utf16() as u32;
+    }
+    row.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn frame(body: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    #[test]
+    fn reads_framed_messages() {
+        let input = format!("{}Content-Type: application/vscode-jsonrpc\r\n{}{}{}",
+            frame("{\"id\":1}"), frame("{\"id\":\"é\"}"), frame("not json"), frame("[2]"));
+        let mut reader = BufReader::new(input.as_bytes());
+        assert_eq!(read_message(&mut reader).unwrap().get("id"), Some(&Json::Number(1.0)));
+        assert_eq!(read_message(&mut reader).unwrap().get("id").and_then(Json::as_str), Some("é"));
+        assert_eq!(read_message(&mut reader), Some(Json::Array(vec![Json::Number(2.0)])));
+        assert_eq!(read_message(&mut reader), None);
+        let mut short = BufReader::new("Content-Length: 10\r\n\r\n{}".as_bytes());
+        assert_eq!(read_message(&mut short), None);
+    }
+
+    #[test]
+    fn converts_columns() {
+        let row = "aé😀b".as_bytes();
+        assert_eq!(utf16_col(row, 0), 0);
+        assert_eq!(utf16_col(row, 3), 2);
+        assert_eq!(utf16_col(row, 7), 4);
+        assert_eq!(utf16_col(row, 100), 5);
+        assert_eq!(byte_col(row, 2), 3);
+        assert_eq!(byte_col(row, 4), 7);
+        assert_eq!(byte_col(row, 5), 8);
+        assert_eq!(byte_col(row, 9), 8);
+    }
+
+    #[test]
+    fn converts_uris() {
+        let path = Path::new("/tmp/a dir/é%.rs");
+        let uri = path_to_uri(path);
+        assert_eq!(uri, "file:///tmp/a%20dir/%C3%A9%25.rs");
+        assert_eq!(uri_to_path(&uri), Some(path.to_path_buf()));
+        assert_eq!(uri_to_path("file:///x/y_z.rs"), Some(PathBuf::from("/x/y_z.rs")));
+        assert_eq!(uri_to_path("https://example.com/"), None);
+    }
+
+    // The mock server is built with the other examples next to the test binary.
+    fn mock_lsp() -> PathBuf {
+        let exe = std::env::current_exe().unwrap();
+        let path = exe.parent().unwrap().parent().unwrap().join("examples").join("mock_lsp");
+        assert!(path.is_file(), "{} is missing: cargo build --example mock_lsp", path.display());
+        path
+    }
+
+    fn wait_for<T>(client: &mut LspClient, mut pick: impl FnMut(LspEvent) -> Option<T>) -> T {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            for event in client.poll() {
+                if let Some(found) = pick(event) {
+                    return found;
+                }
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        panic!("no answer from the server");
+    }
+
+    fn response(client: &mut LspClient, kind: LspRequest, method: &str, params: Json) -> Json {
+        client.request(kind, method, params);
+        wait_for(client, |event| match event {
+            LspEvent::Response(k, result) if k == kind => Some(result),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn talks_to_the_mock_server() {
+        let dir = std::env::temp_dir().join(format!("rilo-lsp-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.rs");
+        let text = "fn main() {\n    let value = 1; // TODO\n    value + va\n}";
+        fs::write(&file, text).unwrap();
+        let uri = path_to_uri(&file);
+        let command = mock_lsp().to_string_lossy().into_owned();
+        let mut client = LspClient::start(&command, "rust", &dir).unwrap();
+
+        // Held back until the server has answered the initialize request.
+        client.notify("textDocument/didOpen", Json::object(vec![
+            ("textDocument", Json::object(vec![
+                ("uri", Json::from(uri.as_str())),
+                ("languageId", Json::from("rust")),
+                ("version", Json::from(1u32)),
+                ("text", Json::from(text)),
+            ])),
+        ]));
+        let (diagnostics_uri, diagnostics) = wait_for(&mut client, |event| match event {
+            LspEvent::Diagnostics(uri, diagnostics) => Some((uri, diagnostics)),
+            _ => None,
+        });
+        assert!(client.initialized);
+        assert_eq!(diagnostics_uri, uri);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].line, diagnostics[0].start, diagnostics[0].end), (1, 22, 26));
+        assert_eq!(diagnostics[0].severity, 2);
+
+        let at = |line: u32, character: u32| Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", Json::from(uri.as_str()))])),
+            ("position", position(line, character)),
+        ]);
+        let hover = response(&mut client, LspRequest::Hover, "textDocument/hover", at(2, 6));
+        assert_eq!(hover_text(&hover), "mock hover: value");
+
+        let definition = response(&mut client, LspRequest::Definition, "textDocument/definition", at(2, 6));
+        let items = parse_lsp_locations(&definition);
+        assert_eq!(items.len(), 1);
+        assert_eq!((items[0].line, items[0].col), (2, 9));
+        assert_eq!(items[0].text, "let value = 1; // TODO");
+
+        let references = response(&mut client, LspRequest::References, "textDocument/references", at(2, 6));
+        let lines: Vec<(u32, u32)> = parse_lsp_locations(&references).iter().map(|item| (item.line, item.col)).collect();
+        assert_eq!(lines, vec![(2, 9), (3, 5)]);
+
+        let mut params = at(1, 8);
+        if let Json::Object(members) = &mut params {
+            members.push((String::from("newName"), Json::from("total")));
+        }
+        let rename = response(&mut client, LspRequest::Rename, "textDocument/rename", params);
+        let edits = rename.path(&["changes", uri.as_str()]).and_then(Json::as_array).unwrap();
+        let rows: Vec<&[u8]> = text.split('\n').map(str::as_bytes).collect();
+        let renamed: Vec<String> = apply_text_edits(&rows, edits).iter()
+            .map(|row| String::from_utf8_lossy(row).into_owned())
+            .collect();
+        assert_eq!(renamed, vec!["fn main() {", "    let total = 1; // TODO", "    total + va", "}"]);
+
+        let completion = response(&mut client, LspRequest::Completion, "textDocument/completion", at(2, 14));
+        let labels: Vec<&str> = completion.as_array().unwrap().iter()
+            .filter_map(|item| item.get("label").and_then(Json::as_str))
+            .collect();
+        assert_eq!(labels, vec!["value"]);
+
+        client.shutdown();
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
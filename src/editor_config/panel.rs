@@ -0,0 +1,76 @@
+// Item rows the list panel shows at most.
+pub const PANEL_ROWS: usize = 8;
+
+use super::search::ProjectSearch;
+
+// A place in a file. An empty `file` is the current buffer; `line` and `col`
+// count from 1 and are 0 when unknown. `span` is a part of `text` to show
+// highlighted.
+#[derive(Clone)]
+pub struct ListItem {
+    pub file: String,
+    pub line: u32,
+    pub col: u32,
+    pub text: String,
+    pub span: Option<(usize, usize)>,
+}
+
+// A list shown under the text, such as build errors, with one selected item.
+// A project search still running adds its matches to it.
+pub struct ListPanel {
+    pub title: String,
+    pub items: Vec<ListItem>,
+    pub selected: usize,
+    pub top: usize,
+    pub search: Option<ProjectSearch>,
+}
+
+impl ListItem {
+    pub fn label(&self) -> String {
+        let mut label = self.file.clone();
+        if self.line > 0 {
+            label.push_str(&format!(":{}", self.line));
+            if self.col > 0 {
+                label.push_str(&format!(":{}", self.col));
+            }
+        }
+        if !self.text.is_empty() {
+            if !label.is_empty() {
+                label.push_str(": ");
+            }
+            label.push_str(&self.text);
+        }
+        label
+    }
+}
+
+impl ListPanel {
+    pub fn new(title: String, items: Vec<ListItem>) -> ListPanel {
+        ListPanel { title, items, selected: 0, top: 0, search: None }
+    }
+
+    // The title row and the item rows.
+    pub fn height(&self) -> u16 {
+        (1 + self.items.len().min(PANEL_ROWS)) as u16
+    }
+
+    pub fn select(&mut self, idx: usize) {
+        if idx >= self.items.len() {
+            return;
+        }
+        self.selected = idx;
+        if idx < self.top {
+            self.top = idx;
+        }else if idx >= self.top + PANEL_ROWS {
+            self.top = idx + 1 - PANEL_ROWS;
+        }
+    }
+
+    pub fn current(&self) -> Option<&ListItem> {
+        self.items.get(self.selected)
+    }
+
+    pub fn visible(&self) -> impl Iterator<Item = (usize, &ListItem)> {
+        self.items.iter().enumerate().skip(self.top).take(PANEL_ROWS)
+    }
+}
@@ -0,0 +1,1099 @@
+use super::{Highlight, Erow, RowState, HlOpen, EditorSyntax, HLFlags};
+use super::erow::{is_ident_start, is_ident_char, scan_ident, scan_escape, scan_char, scan_number};
+use super::highlighter::Highlighter;
+use super::tree::TreeEvent;
+
+// Deepest nesting kept in a row state.
+const MAX_SCOPES: usize = 128;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Language {
+    Rust,
+    C,
+    Json,
+    Toml,
+}
+
+pub fn parse_language(name: &str) -> Result<Language, String> {
+    match name {
+        "rust" => Ok(Language::Rust),
+        "c" => Ok(Language::C),
+        "json" => Ok(Language::Json),
+        "toml" => Ok(Language::Toml),
+        _ => Err(format!("unknown parser '{}' (expected rust, c, json or toml)", name)),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ItemKind {
+    Fn,
+    Struct,
+    Enum,
+    Trait,
+    Alias,
+}
+
+// The nodes of the parse tree open at the end of a row, outermost first. Each
+// row keeps its own copy, so parsing resumes at an edited row and stops as
+// soon as a row ends in the same scopes as before.
+#[derive(Clone, PartialEq)]
+pub enum Scope {
+    Comment(bool),          // block comment, true for doc comments
+    Str(u8),                // string closed by this byte
+    RawStr(usize),          // raw string closed by `"` and this many `#`
+    MultiStr(u8),           // TOML string closed by three of this byte
+    Attribute(usize),       // Rust attribute by bracket depth
+    Directive,              // C preprocessor line continued with `\`
+    Block,                  // `{ }` holding code
+    Fields,                 // `{ }` of a struct or enum definition
+    Params,                 // `( )` of a function signature
+    Group(u8),              // `( )` or `[ ]` by closing byte
+    TypeGroup(u8),          // `( )` or `[ ]` inside a type
+    Angle,                  // `< >` of generic parameters or arguments
+    Type,                   // a type after `:` or `->`
+    TypeHeader,             // impl header, supertraits or where clause
+    Let,                    // pattern of a `let`, `const` or `static`
+    Item(ItemKind, bool),   // item keyword seen, true once it is named
+    Expect(Highlight),      // the next identifier gets this group
+    Typedef,                // C typedef up to its `;`
+    Object(bool),           // JSON object or TOML inline table, true when a key comes next
+    Array,
+}
+
+impl Scope {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scope::Comment(_) => "comment",
+            Scope::Str(_) | Scope::RawStr(_) | Scope::MultiStr(_) => "string",
+            Scope::Attribute(_) => "attribute",
+            Scope::Directive => "directive",
+            Scope::Block => "block",
+            Scope::Fields => "fields",
+            Scope::Params => "params",
+            Scope::Group(_) => "group",
+            Scope::TypeGroup(_) => "type group",
+            Scope::Angle => "generics",
+            Scope::Type => "type",
+            Scope::TypeHeader => "type header",
+            Scope::Let => "binding",
+            Scope::Item(ItemKind::Fn, _) => "fn",
+            Scope::Item(ItemKind::Struct, _) => "struct",
+            Scope::Item(ItemKind::Enum, _) => "enum",
+            Scope::Item(ItemKind::Trait, _) => "trait",
+            Scope::Item(ItemKind::Alias, _) => "type alias",
+            Scope::Expect(_) => "name",
+            Scope::Typedef => "typedef",
+            Scope::Object(_) => "object",
+            Scope::Array => "array",
+        }
+    }
+
+    fn closer(&self) -> u8 {
+        match self {
+            Scope::Block | Scope::Fields | Scope::Object(_) => b'}',
+            Scope::Params => b')',
+            Scope::Group(c) | Scope::TypeGroup(c) => *c,
+            Scope::Array => b']',
+            Scope::Angle => b'>',
+            _ => 0,
+        }
+    }
+}
+
+pub struct StructuralHighlighter {
+    syntax: EditorSyntax,
+    language: Language,
+}
+
+impl StructuralHighlighter {
+    pub fn new(syntax: EditorSyntax, language: Language) -> StructuralHighlighter {
+        StructuralHighlighter { syntax, language }
+    }
+}
+
+impl Highlighter for StructuralHighlighter {
+    fn highlight_row(&self, row: &mut Erow, before: &RowState) -> bool {
+        let rsize = row._rsize as usize;
+        row.hl.clear();
+        row.hl.resize(row.render.len(), Highlight::NORMAL);
+        let mut parser = Parser {
+            es: &self.syntax,
+            text: &row.render[..rsize],
+            hl: &mut row.hl,
+            idx: 0,
+            stack: before.scopes.clone(),
+            tree: Vec::new(),
+        };
+        match self.language {
+            Language::Rust => parser.rust(),
+            Language::C => parser.c(),
+            Language::Json => parser.json(),
+            Language::Toml => parser.toml(),
+        }
+        let mut scopes = parser.stack;
+        row.tree = parser.tree;
+        // Past the limit the outermost scopes go, so rows below still know
+        // the innermost ones they are in.
+        if scopes.len() > MAX_SCOPES {
+            scopes.drain(..scopes.len() - MAX_SCOPES);
+        }
+        row.editor_set_state(RowState { open: HlOpen::new(), scopes })
+    }
+}
+
+struct Parser<'a> {
+    es: &'a EditorSyntax,
+    text: &'a [u8],
+    hl: &'a mut [Highlight],
+    idx: usize,
+    stack: Vec<Scope>,
+    tree: Vec<TreeEvent>,
+}
+
+impl<'a> Parser<'a> {
+    fn at(&self, i: usize) -> u8 {
+        if i < self.text.len() { self.text[i] } else { 0 }
+    }
+
+    fn skip_blank(&self, start: usize) -> usize {
+        let mut i = start;
+        while i < self.text.len() && self.text[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        i
+    }
+
+    fn mark(&mut self, end: usize, hlk: Highlight) {
+        let end = end.min(self.text.len());
+        for h in self.hl[self.idx..end].iter_mut() {
+            *h = hlk.clone();
+        }
+        self.idx = end;
+    }
+
+    fn operator(&mut self) {
+        let hlk = if self.es.operators.contains(&self.text[self.idx]) {
+            Highlight::OPERATOR
+        }else{
+            Highlight::NORMAL
+        };
+        self.mark(self.idx + 1, hlk);
+    }
+
+    fn escapes(&self) -> bool {
+        self.es.flags.contains(HLFlags::HLF_ESCAPES)
+    }
+
+    fn top(&self) -> Option<&Scope> {
+        self.stack.last()
+    }
+
+    // Scopes open and close nodes of the tree at the current column.
+    fn open(&mut self, scope: Scope) {
+        self.tree.push(TreeEvent::Open(scope.clone(), self.idx));
+        self.stack.push(scope);
+    }
+
+    fn shut(&mut self) {
+        if self.stack.pop().is_some() {
+            self.tree.push(TreeEvent::Close(self.idx));
+        }
+    }
+
+    fn pop_while(&mut self, f: fn(&Scope) -> bool) {
+        while let Some(scope) = self.top() {
+            if !f(scope) {
+                break;
+            }
+            self.shut();
+        }
+    }
+
+    // Pops everything up to and including the innermost scope closed by `c`.
+    fn close(&mut self, c: u8) {
+        if let Some(pos) = self.stack.iter().rposition(|s| s.closer() == c) {
+            while self.stack.len() > pos {
+                self.shut();
+            }
+        }
+    }
+
+    fn keyword_at(&self, start: usize, end: usize) -> Option<Highlight> {
+        match self.es.keyword_matcher.find(&self.text[start..]) {
+            Some((len, hlk)) if start + len == end => Some(hlk),
+            _ => None,
+        }
+    }
+
+    // Continues a comment, string or attribute left open by an earlier row or token.
+    fn lexical(&mut self, nested_comments: bool) -> bool {
+        match self.top() {
+            Some(Scope::Comment(doc)) => {
+                let doc = *doc;
+                self.block_comment(doc, nested_comments);
+            },
+            Some(Scope::Str(quote)) => {
+                let quote = *quote;
+                self.string(quote);
+            },
+            Some(Scope::RawStr(hashes)) => {
+                let hashes = *hashes;
+                self.raw_string(hashes);
+            },
+            Some(Scope::MultiStr(quote)) => {
+                let quote = *quote;
+                self.multi_string(quote);
+            },
+            Some(Scope::Attribute(_)) => self.attribute(),
+            _ => return false,
+        }
+        true
+    }
+
+    fn block_comment(&mut self, doc: bool, nested: bool) {
+        let hlk = if doc { Highlight::DOCCOMMENT } else { Highlight::MLCOMMENT };
+        while self.idx < self.text.len() {
+            let rest = &self.text[self.idx..];
+            if rest.starts_with(b"*/") {
+                self.mark(self.idx + 2, hlk);
+                self.shut();
+                return;
+            }
+            if nested && rest.starts_with(b"/*") {
+                self.mark(self.idx + 2, hlk.clone());
+                self.open(Scope::Comment(doc));
+                continue;
+            }
+            self.mark(self.idx + 1, hlk.clone());
+        }
+    }
+
+    fn escape(&mut self, hlk: &Highlight) {
+        let end = scan_escape(self.text, self.idx);
+        if *hlk == Highlight::STRING && self.escapes() {
+            self.mark(end, Highlight::ESCAPE);
+        }else{
+            self.mark(end, hlk.clone());
+        }
+    }
+
+    fn string(&mut self, quote: u8) {
+        while self.idx < self.text.len() {
+            let c = self.text[self.idx];
+            if c == b'\\' && self.idx + 1 < self.text.len() {
+                self.escape(&Highlight::STRING);
+                continue;
+            }
+            self.mark(self.idx + 1, Highlight::STRING);
+            if c == quote {
+                self.shut();
+                return;
+            }
+        }
+    }
+
+    fn raw_string(&mut self, hashes: usize) {
+        while self.idx < self.text.len() {
+            let end = self.idx + 1 + hashes;
+            if self.text[self.idx] == b'"' && end <= self.text.len() &&
+                    self.text[self.idx + 1..end].iter().all(|c| *c == b'#') {
+                self.mark(end, Highlight::STRING);
+                self.shut();
+                return;
+            }
+            self.mark(self.idx + 1, Highlight::STRING);
+        }
+    }
+
+    fn multi_string(&mut self, quote: u8) {
+        let close = [quote; 3];
+        while self.idx < self.text.len() {
+            if self.text[self.idx..].starts_with(&close) {
+                self.mark(self.idx + 3, Highlight::STRING);
+                self.shut();
+                return;
+            }
+            if quote == b'"' && self.text[self.idx] == b'\\' && self.idx + 1 < self.text.len() {
+                self.escape(&Highlight::STRING);
+                continue;
+            }
+            self.mark(self.idx + 1, Highlight::STRING);
+        }
+    }
+
+    fn attribute(&mut self) {
+        while self.idx < self.text.len() {
+            let c = self.text[self.idx];
+            self.mark(self.idx + 1, Highlight::ATTRIBUTE);
+            if let Some(Scope::Attribute(depth)) = self.stack.last_mut() {
+                match c {
+                    b'[' => *depth = depth.saturating_add(1),
+                    b']' => {
+                        *depth -= 1;
+                        if *depth == 0 {
+                            self.shut();
+                            return;
+                        }
+                    },
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    // A string or character literal on one row, starting at the quote.
+    fn quoted(&mut self, hlk: Highlight) {
+        let quote = self.text[self.idx];
+        self.mark(self.idx + 1, hlk.clone());
+        while self.idx < self.text.len() {
+            let c = self.text[self.idx];
+            if c == b'\\' && quote == b'"' && self.idx + 1 < self.text.len() {
+                self.escape(&hlk);
+                continue;
+            }
+            self.mark(self.idx + 1, hlk.clone());
+            if c == quote {
+                return;
+            }
+        }
+    }
+
+    fn char_literal(&mut self) -> bool {
+        let start = self.idx;
+        match scan_char(self.text, start) {
+            Some(end) => {
+                self.mark(end, Highlight::CHAR);
+                if self.escapes() && self.text[start + 1] == b'\\' {
+                    for h in self.hl[start + 1..end - 1].iter_mut() {
+                        *h = Highlight::ESCAPE;
+                    }
+                }
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn number(&mut self) {
+        let end = scan_number(self.text, self.idx, self.es.number_flags);
+        self.mark(end, Highlight::NUMBER);
+    }
+
+    fn in_type(&self) -> bool {
+        matches!(self.top(), Some(Scope::Type) | Some(Scope::Angle) | Some(Scope::TypeHeader) |
+            Some(Scope::TypeGroup(_)))
+    }
+
+    fn rust(&mut self) {
+        let text = self.text;
+        while self.idx < text.len() {
+            if self.lexical(true) {
+                continue;
+            }
+            let c = text[self.idx];
+            let rest = &text[self.idx..];
+            if c.is_ascii_whitespace() {
+                self.idx += 1;
+            }else if rest.starts_with(b"//") {
+                let doc = (rest.starts_with(b"///") && !rest.starts_with(b"////")) ||
+                    rest.starts_with(b"//!");
+                self.mark(text.len(), if doc { Highlight::DOCCOMMENT } else { Highlight::COMMENT });
+            }else if rest.starts_with(b"/*") {
+                let doc = (rest.starts_with(b"/**") && !rest.starts_with(b"/**/") &&
+                    !rest.starts_with(b"/***")) || rest.starts_with(b"/*!");
+                self.open(Scope::Comment(doc));
+                self.mark(self.idx + 2, if doc { Highlight::DOCCOMMENT } else { Highlight::MLCOMMENT });
+            }else if rest.starts_with(b"#[") || rest.starts_with(b"#![") {
+                let len = if rest[1] == b'!' { 3 } else { 2 };
+                self.open(Scope::Attribute(1));
+                self.mark(self.idx + len, Highlight::ATTRIBUTE);
+            }else if c == b'"' {
+                self.open(Scope::Str(b'"'));
+                self.mark(self.idx + 1, Highlight::STRING);
+            }else if c == b'\'' {
+                self.rust_quote();
+            }else if c.is_ascii_digit() {
+                self.number();
+            }else if is_ident_start(c) {
+                if !self.rust_string_prefix() {
+                    self.rust_ident();
+                }
+            }else{
+                self.rust_punct();
+            }
+        }
+    }
+
+    fn rust_quote(&mut self) {
+        if self.char_literal() {
+            return;
+        }
+        if is_ident_start(self.at(self.idx + 1)) {
+            let end = scan_ident(self.text, self.idx + 1);
+            self.mark(end, Highlight::LIFETIME);
+        }else{
+            self.operator();
+        }
+    }
+
+    // Byte, C and raw strings: `b"..."`, `c"..."`, `r#"..."#`, `br"..."` and `b'x'`.
+    fn rust_string_prefix(&mut self) -> bool {
+        let start = self.idx;
+        let mut i = start;
+        if self.at(i) == b'b' || self.at(i) == b'c' {
+            i += 1;
+        }
+        if self.at(i) == b'r' {
+            let mut j = i + 1;
+            while self.at(j) == b'#' {
+                j += 1;
+            }
+            if self.at(j) != b'"' {
+                return false;
+            }
+            self.open(Scope::RawStr(j - i - 1));
+            self.mark(j + 1, Highlight::STRING);
+            return true;
+        }
+        if i == start {
+            return false;
+        }
+        if self.at(i) == b'"' {
+            self.open(Scope::Str(b'"'));
+            self.mark(i + 1, Highlight::STRING);
+            return true;
+        }
+        if self.at(i) == b'\'' {
+            self.idx = i;
+            if self.char_literal() {
+                self.hl[start] = Highlight::CHAR;
+                return true;
+            }
+            self.idx = start;
+        }
+        false
+    }
+
+    fn rust_ident(&mut self) {
+        let text = self.text;
+        let start = self.idx;
+        let end = scan_ident(text, start);
+        let word = &text[start..end];
+        if let Some(hlk) = self.keyword_at(start, end) {
+            self.mark(end, hlk);
+            self.rust_keyword(word);
+            return;
+        }
+        if let Some(Scope::Item(kind, named)) = self.stack.last_mut() {
+            if !*named {
+                *named = true;
+                let hlk = if *kind == ItemKind::Fn { Highlight::FUNCTION } else { Highlight::TYPE };
+                self.mark(end, hlk);
+                return;
+            }
+        }
+        if let Some(Scope::Expect(hlk)) = self.top() {
+            let hlk = hlk.clone();
+            self.shut();
+            self.mark(end, hlk);
+            return;
+        }
+        let after = self.skip_blank(end);
+        let path = self.at(after) == b':' && self.at(after + 1) == b':';
+        let capitalized = word[0].is_ascii_uppercase() && word.iter().any(|c| c.is_ascii_lowercase());
+        if self.at(end) == b'!' && self.at(end + 1) != b'=' && !self.in_type() {
+            self.mark(end + 1, Highlight::MACRO);
+            return;
+        }
+        let hlk = if self.in_type() {
+            if path && !capitalized { Highlight::NORMAL } else { Highlight::TYPE }
+        }else if self.at(after) == b'(' {
+            Highlight::FUNCTION
+        }else if capitalized {
+            Highlight::TYPE
+        }else{
+            Highlight::NORMAL
+        };
+        self.mark(end, hlk);
+    }
+
+    fn rust_keyword(&mut self, word: &[u8]) {
+        if self.in_type() || matches!(self.top(), Some(Scope::Expect(_))) {
+            if word == b"where" && self.top() == Some(&Scope::Type) {
+                self.shut();
+                self.open(Scope::TypeHeader);
+            }
+            return;
+        }
+        let scope = match word {
+            b"fn" => {
+                if self.top() == Some(&Scope::Let) {
+                    self.shut();
+                }
+                Scope::Item(ItemKind::Fn, false)
+            },
+            b"struct" | b"union" => Scope::Item(ItemKind::Struct, false),
+            b"enum" => Scope::Item(ItemKind::Enum, false),
+            b"trait" => Scope::Item(ItemKind::Trait, false),
+            b"type" => Scope::Item(ItemKind::Alias, false),
+            b"impl" | b"where" => Scope::TypeHeader,
+            b"let" | b"const" | b"static" => Scope::Let,
+            b"as" => Scope::Expect(Highlight::TYPE),
+            _ => return,
+        };
+        self.open(scope);
+    }
+
+    fn rust_punct(&mut self) {
+        let c = self.text[self.idx];
+        let next = self.at(self.idx + 1);
+        match c {
+            b'{' => {
+                self.pop_while(|s| matches!(s, Scope::Type | Scope::TypeHeader | Scope::Expect(_) |
+                    Scope::Angle));
+                let scope = match self.top() {
+                    Some(Scope::Item(kind, _)) => {
+                        let fields = *kind == ItemKind::Struct || *kind == ItemKind::Enum;
+                        self.shut();
+                        if fields { Scope::Fields } else { Scope::Block }
+                    },
+                    Some(Scope::Fields) => Scope::Fields,
+                    _ => Scope::Block,
+                };
+                self.open(scope);
+            },
+            b'(' => {
+                let scope = match self.top() {
+                    Some(Scope::Item(ItemKind::Fn, _)) => Scope::Params,
+                    Some(Scope::Item(..)) | Some(Scope::Fields) => Scope::TypeGroup(b')'),
+                    _ if self.in_type() => Scope::TypeGroup(b')'),
+                    _ => Scope::Group(b')'),
+                };
+                self.open(scope);
+            },
+            b'[' => {
+                let scope = if self.in_type() { Scope::TypeGroup(b']') } else { Scope::Group(b']') };
+                self.open(scope);
+            },
+            b'}' | b')' | b']' => self.close(c),
+            b';' => self.pop_while(|s| matches!(s, Scope::Type | Scope::TypeHeader | Scope::Let |
+                Scope::Item(..) | Scope::Expect(_) | Scope::Angle)),
+            b',' => self.pop_while(|s| matches!(s, Scope::Type | Scope::Expect(_))),
+            b'=' if next != b'=' && next != b'>' => {
+                self.pop_while(|s| matches!(s, Scope::Type | Scope::Expect(_)));
+                match self.top() {
+                    Some(Scope::Let) => {
+                        self.shut();
+                    },
+                    Some(Scope::Item(ItemKind::Alias, _)) => {
+                        self.shut();
+                        self.open(Scope::Type);
+                    },
+                    _ => (),
+                }
+            },
+            b':' if next == b':' => {
+                self.mark(self.idx + 2, Highlight::NORMAL);
+                if self.at(self.idx) == b'<' {
+                    self.open(Scope::Angle);
+                    self.operator();
+                }
+                return;
+            },
+            b':' => {
+                match self.top() {
+                    Some(Scope::Params) | Some(Scope::Fields) | Some(Scope::Let) => {
+                        self.open(Scope::Type);
+                    },
+                    Some(Scope::Item(_, true)) => self.open(Scope::TypeHeader),
+                    _ => (),
+                }
+            },
+            b'-' if next == b'>' => {
+                if !self.in_type() {
+                    self.open(Scope::Type);
+                }
+                self.mark(self.idx + 2, Highlight::OPERATOR);
+                return;
+            },
+            b'<' if next != b'=' && (self.in_type() || matches!(self.top(), Some(Scope::Item(..)))) => {
+                self.open(Scope::Angle);
+            },
+            b'>' if self.top() == Some(&Scope::Angle) => {
+                self.shut();
+            },
+            _ => (),
+        }
+        self.operator();
+    }
+
+    fn c(&mut self) {
+        let text = self.text;
+        let continued = self.top() == Some(&Scope::Directive);
+        if continued {
+            self.shut();
+        }
+        let mut directive = continued;
+        let first = self.skip_blank(0);
+        if !continued && self.at(first) == b'#' && !matches!(self.top(), Some(Scope::Comment(_))) {
+            directive = true;
+            self.c_directive(first);
+        }
+        let mut prev = if matches!(self.top(), Some(Scope::Group(_))) { b'=' } else { b';' };
+        while self.idx < text.len() {
+            if self.lexical(false) {
+                continue;
+            }
+            let c = text[self.idx];
+            let rest = &text[self.idx..];
+            if c.is_ascii_whitespace() {
+                self.idx += 1;
+            }else if rest.starts_with(b"//") {
+                self.mark(text.len(), Highlight::COMMENT);
+            }else if rest.starts_with(b"/*") {
+                self.open(Scope::Comment(false));
+                self.mark(self.idx + 2, Highlight::MLCOMMENT);
+            }else if c == b'"' {
+                self.open(Scope::Str(b'"'));
+                self.mark(self.idx + 1, Highlight::STRING);
+                prev = b'a';
+            }else if c == b'\'' {
+                if !self.char_literal() {
+                    self.operator();
+                }
+                prev = b'a';
+            }else if c.is_ascii_digit() {
+                self.number();
+                prev = b'a';
+            }else if is_ident_start(c) {
+                prev = self.c_ident(prev);
+            }else{
+                self.c_punct(c);
+                prev = c;
+            }
+        }
+        if let Some(Scope::Str(_)) = self.top() {
+            self.shut();
+        }
+        if directive && text.last() == Some(&b'\\') {
+            self.open(Scope::Directive);
+        }
+    }
+
+    fn c_directive(&mut self, first: usize) {
+        let text = self.text;
+        let name_start = self.skip_blank(first + 1);
+        let name_end = scan_ident(text, name_start);
+        self.idx = first;
+        let hlk = self.keyword_at(first, name_end).unwrap_or(Highlight::MACRO);
+        self.mark(name_end, hlk);
+        let name = &text[name_start..name_end];
+        let arg = self.skip_blank(name_end);
+        if name == b"include" && self.at(arg) == b'<' {
+            let end = text[arg..].iter().position(|c| *c == b'>').map_or(text.len(), |p| arg + p + 1);
+            self.idx = arg;
+            self.mark(end, Highlight::STRING);
+        }else if name == b"define" && is_ident_start(self.at(arg)) {
+            self.idx = arg;
+            self.mark(scan_ident(text, arg), Highlight::MACRO);
+        }
+    }
+
+    // Returns the kind of token just read for the declaration check of the next one.
+    fn c_ident(&mut self, prev: u8) -> u8 {
+        let text = self.text;
+        let start = self.idx;
+        let end = scan_ident(text, start);
+        let word = &text[start..end];
+        if let Some(hlk) = self.keyword_at(start, end) {
+            self.mark(end, hlk);
+            match word {
+                b"struct" | b"union" | b"enum" => self.open(Scope::Expect(Highlight::TYPE)),
+                b"typedef" => self.open(Scope::Typedef),
+                _ => (),
+            }
+            return match word {
+                b"const" | b"static" | b"extern" | b"volatile" | b"register" | b"inline" |
+                    b"restrict" | b"auto" | b"typedef" => b';',
+                _ => b'k',
+            };
+        }
+        if let Some(Scope::Expect(hlk)) = self.top() {
+            let hlk = hlk.clone();
+            self.shut();
+            self.mark(end, hlk);
+            return b'a';
+        }
+        let after = self.skip_blank(end);
+        let all_caps = word.len() > 1 && word.iter().any(|c| c.is_ascii_uppercase()) &&
+            word.iter().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || *c == b'_');
+        let boundary = matches!(prev, b';' | b'{' | b'}') ||
+            (matches!(prev, b'(' | b',') && !self.stack.contains(&Scope::Block));
+        let hlk = if all_caps {
+            Highlight::MACRO
+        }else if self.at(after) == b'(' {
+            Highlight::FUNCTION
+        }else if word.ends_with(b"_t") ||
+                (self.at(after) == b';' && self.top() == Some(&Scope::Typedef)) ||
+                (boundary && self.c_declares(end)) {
+            Highlight::TYPE
+        }else{
+            Highlight::NORMAL
+        };
+        self.mark(end, hlk);
+        b'a'
+    }
+
+    // Whether the identifier ending at `end` is followed by a declared name, as
+    // in `Foo bar` or `Foo **bar`, which makes it a type.
+    fn c_declares(&self, end: usize) -> bool {
+        let mut i = self.skip_blank(end);
+        while self.at(i) == b'*' || self.at(i) == b'&' {
+            i = self.skip_blank(i + 1);
+        }
+        if !is_ident_start(self.at(i)) {
+            return false;
+        }
+        let word_end = scan_ident(self.text, i);
+        self.keyword_at(i, word_end).is_none()
+    }
+
+    fn c_punct(&mut self, c: u8) {
+        match c {
+            b'{' => {
+                self.pop_while(|s| matches!(s, Scope::Expect(_)));
+                self.open(Scope::Block);
+            },
+            b'(' => self.open(Scope::Group(b')')),
+            b'[' => self.open(Scope::Group(b']')),
+            b'}' | b')' | b']' => self.close(c),
+            b';' => self.pop_while(|s| matches!(s, Scope::Expect(_) | Scope::Typedef)),
+            _ => (),
+        }
+        self.operator();
+    }
+
+    fn json(&mut self) {
+        let text = self.text;
+        while self.idx < text.len() {
+            if self.lexical(false) {
+                continue;
+            }
+            let c = text[self.idx];
+            let rest = &text[self.idx..];
+            if c.is_ascii_whitespace() {
+                self.idx += 1;
+            }else if rest.starts_with(b"//") {
+                self.mark(text.len(), Highlight::COMMENT);
+            }else if rest.starts_with(b"/*") {
+                self.open(Scope::Comment(false));
+                self.mark(self.idx + 2, Highlight::MLCOMMENT);
+            }else if c == b'"' {
+                let key = self.top() == Some(&Scope::Object(true));
+                self.quoted(if key { Highlight::KEYWORD2 } else { Highlight::STRING });
+            }else if c.is_ascii_digit() || (c == b'-' && self.at(self.idx + 1).is_ascii_digit()) {
+                self.value_token(Highlight::NUMBER);
+            }else if is_ident_start(c) {
+                let end = scan_ident(text, self.idx);
+                let hlk = self.keyword_at(self.idx, end).unwrap_or(Highlight::NORMAL);
+                self.mark(end, hlk);
+            }else{
+                self.structure(c);
+                self.operator();
+            }
+        }
+    }
+
+    // Brackets and separators of JSON and TOML values.
+    fn structure(&mut self, c: u8) {
+        match c {
+            b'{' => self.open(Scope::Object(true)),
+            b'[' => self.open(Scope::Array),
+            b'}' | b']' => self.close(c),
+            b':' | b'=' => {
+                if let Some(Scope::Object(key)) = self.stack.last_mut() {
+                    *key = false;
+                }
+            },
+            b',' => {
+                if let Some(Scope::Object(key)) = self.stack.last_mut() {
+                    *key = true;
+                }
+            },
+            _ => (),
+        }
+    }
+
+    // A number, date or time: everything up to the next separator.
+    fn value_token(&mut self, hlk: Highlight) {
+        let mut end = self.idx + 1;
+        while is_ident_char(self.at(end)) || matches!(self.at(end), b'.' | b'+' | b'-' | b':') {
+            end += 1;
+        }
+        self.mark(end, hlk);
+    }
+
+    fn toml(&mut self) {
+        let text = self.text;
+        let mut expect_key = self.stack.is_empty();
+        let first = self.skip_blank(0);
+        if expect_key && self.at(first) == b'[' {
+            self.idx = first;
+            self.toml_header();
+            expect_key = false;
+        }
+        while self.idx < text.len() {
+            if self.lexical(false) {
+                continue;
+            }
+            let c = text[self.idx];
+            if c.is_ascii_whitespace() {
+                self.idx += 1;
+                continue;
+            }
+            if c == b'#' {
+                self.mark(text.len(), Highlight::COMMENT);
+                break;
+            }
+            if (expect_key || self.top() == Some(&Scope::Object(true))) && c != b'}' && c != b',' {
+                self.toml_key();
+                expect_key = false;
+                continue;
+            }
+            let rest = &text[self.idx..];
+            if rest.starts_with(b"\"\"\"") || rest.starts_with(b"'''") {
+                self.open(Scope::MultiStr(c));
+                self.mark(self.idx + 3, Highlight::STRING);
+            }else if c == b'"' || c == b'\'' {
+                self.quoted(Highlight::STRING);
+            }else if c.is_ascii_digit() || ((c == b'+' || c == b'-') && self.at(self.idx + 1) != b' ') {
+                self.value_token(Highlight::NUMBER);
+            }else if is_ident_start(c) {
+                let end = scan_ident(text, self.idx);
+                let word = &text[self.idx..end];
+                let hlk = match self.keyword_at(self.idx, end) {
+                    Some(hlk) => hlk,
+                    None if word == b"inf" || word == b"nan" => Highlight::NUMBER,
+                    None => Highlight::NORMAL,
+                };
+                self.mark(end, hlk);
+            }else{
+                self.structure(c);
+                self.operator();
+            }
+        }
+    }
+
+    // `[table]` or `[[array.of.tables]]`.
+    fn toml_header(&mut self) {
+        let text = self.text;
+        let mut end = self.idx;
+        let mut quote = 0;
+        while end < text.len() {
+            let c = text[end];
+            end += 1;
+            if quote != 0 {
+                if c == quote { quote = 0; }
+            }else if c == b'"' || c == b'\'' {
+                quote = c;
+            }else if c == b']' {
+                if self.at(end) == b']' { end += 1; }
+                break;
+            }
+        }
+        self.mark(end, Highlight::TYPE);
+    }
+
+    // A bare, quoted or dotted key up to its `=`.
+    fn toml_key(&mut self) {
+        let text = self.text;
+        let mut end = self.idx;
+        let mut quote = 0;
+        while end < text.len() {
+            let c = text[end];
+            if quote != 0 {
+                if c == quote { quote = 0; }
+            }else if c == b'"' || c == b'\'' {
+                quote = c;
+            }else if c == b'=' || c == b'#' || c == b'}' {
+                break;
+            }
+            end += 1;
+        }
+        while end > self.idx && text[end - 1].is_ascii_whitespace() {
+            end -= 1;
+        }
+        self.mark(end.max(self.idx + 1), Highlight::KEYWORD2);
+        if let Some(Scope::Object(key)) = self.stack.last_mut() {
+            *key = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUST: &str = include_str!("../../syntax/rust.syntax");
+    const C: &str = include_str!("../../syntax/c.syntax");
+    const JSON: &str = include_str!("../../syntax/json.syntax");
+    const TOML: &str = include_str!("../../syntax/toml.syntax");
+
+    // Highlights the rows in order, each starting in the state the one above
+    // ended in.
+    fn parse(definition: &str, text: &[&str]) -> Vec<Erow> {
+        let mut errors = Vec::new();
+        let syntax = EditorSyntax::from_definition(definition, "test", &mut errors).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        let language = syntax.parser.unwrap();
+        let highlighter = StructuralHighlighter::new(syntax, language);
+        let mut state = RowState::new();
+        text.iter().map(|line| {
+            let mut row = Erow {
+                size: line.len() as u16,
+                chars: line.as_bytes().to_vec(),
+                _rsize: line.len() as u16,
+                render: line.as_bytes().to_vec(),
+                hl: Vec::new(),
+                hl_state: RowState::new(),
+                hl_stale: true,
+                tree: Vec::new(),
+                symbol: None,
+            };
+            highlighter.highlight_row(&mut row, &state);
+            state = row.hl_state.clone();
+            row
+        }).collect()
+    }
+
+    // The group of each column as a letter: `.` code, `k` keyword, `K` second
+    // keyword, `c` comment, `m` block comment, `d` doc comment, `s` string, `h`
+    // char, `e` escape, `n` number, `t` type, `f` function, `M` macro, `l`
+    // lifetime, `a` attribute and `o` operator.
+    fn groups(definition: &str, text: &[&str]) -> Vec<String> {
+        parse(definition, text).iter().map(|row| row.hl.iter().map(|hl| match hl {
+            Highlight::KEYWORD1 => 'k',
+            Highlight::KEYWORD2 => 'K',
+            Highlight::COMMENT => 'c',
+            Highlight::MLCOMMENT => 'm',
+            Highlight::DOCCOMMENT => 'd',
+            Highlight::STRING => 's',
+            Highlight::CHAR => 'h',
+            Highlight::ESCAPE => 'e',
+            Highlight::NUMBER => 'n',
+            Highlight::TYPE => 't',
+            Highlight::FUNCTION => 'f',
+            Highlight::MACRO => 'M',
+            Highlight::LIFETIME => 'l',
+            Highlight::ATTRIBUTE => 'a',
+            Highlight::OPERATOR => 'o',
+            _ => '.',
+        }).collect()).collect()
+    }
+
+    fn scopes(definition: &str, text: &[&str]) -> Vec<&'static str> {
+        parse(definition, text).last().unwrap().hl_state.scopes.iter().map(|scope| scope.name()).collect()
+    }
+
+    #[test]
+    fn highlights_rust_generics_lifetimes_and_chars() {
+        assert_eq!(groups(RUST, &[
+            "fn f<'a>(x: &'a str) -> Vec<HashMap<K, V>> {",
+            "    'outer: loop { x::<u8>(b'x', 'a'); }",
+            "    #[cfg(test)] m!(1.5e3);",
+        ]), vec![
+            "kk.follo....oll.KKK..oo.KKKotttttttot..too..",
+            "....llllll..kkkk......oKKo.hhhh..hhh....",
+            "....aaaaaaaaaaaa.MM.nnnnn..",
+        ]);
+    }
+
+    #[test]
+    fn continues_rust_comments_and_strings_on_later_rows() {
+        assert_eq!(groups(RUST, &[
+            "let c = 'a'; /* one /* two */",
+            "still */ let s = \"a\\n",
+            "b\"; /// doc",
+        ]), vec![
+            "kkk...o.hhh..mmmmmmmmmmmmmmmm",
+            "mmmmmmmm.kkk...o.ssee",
+            "ss..ddddddd",
+        ]);
+        assert_eq!(scopes(RUST, &["/* /* */"]), vec!["comment"]);
+        assert_eq!(scopes(RUST, &["/* /* */ */"]), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn closes_raw_strings_on_their_own_hashes() {
+        assert_eq!(groups(RUST, &[
+            "let r = r##\"x\"# y",
+            "z\"#\"##; br\"a\"",
+        ]), vec![
+            "kkk...o.sssssssss",
+            "ssssss..sssss",
+        ]);
+        assert_eq!(groups(RUST, &["r#\"x\"##;"]), vec!["ssssss.."]);
+        assert_eq!(scopes(RUST, &["r#\"x\"##;"]), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn recovers_from_unclosed_scopes() {
+        // A closing brace ends what was left open inside its block, and `;`
+        // ends a declaration cut short.
+        assert_eq!(scopes(RUST, &["fn f() { let x = g(a, [1;", "}"]), Vec::<&str>::new());
+        assert_eq!(scopes(RUST, &["fn f() {", "    let x: Vec<u8;"]), vec!["block"]);
+        assert_eq!(groups(RUST, &["let x: Vec<u8; fn g() {}"]), vec!["kkk....KKKoKK..kk.f....."]);
+        // A stray closer without an opener leaves the scopes alone.
+        assert_eq!(scopes(RUST, &["fn f() {", ") ]"]), vec!["block"]);
+    }
+
+    #[test]
+    fn highlights_c_declarations_and_directives() {
+        assert_eq!(groups(C, &[
+            "#include <stdio.h>",
+            "static struct point *p; /* a",
+            "b */ size_t n = f(x); Foo **bar;",
+            "typedef int myint;",
+            "#define MAX(a) \\",
+            "  (a + 1)",
+            "char c = '\\n'; x = y * z;",
+        ]), vec![
+            "kkkkkkkk.sssssssss",
+            "kkkkkk.kkkkkk.ttttt.....mmmm",
+            "mmmm.KKKKKK.....f.....ttt.......",
+            "kkkkkkk.KKK.ttttt.",
+            "kkkkkkk.MMM.....",
+            ".......n.",
+            "KKKK.....heeh............",
+        ]);
+    }
+
+    #[test]
+    fn highlights_json_keys_and_values() {
+        assert_eq!(groups(JSON, &[
+            "{\"a\": [1, -2.5, true], \"b\": {\"c\": null}, \"d\": \"x\\ty\"}",
+        ]), vec![
+            ".KKK...n..nnnn..kkkk...KKK...KKK..kkkk...KKK..sseess.",
+        ]);
+        assert_eq!(scopes(JSON, &["{\"a\": [1,"]), vec!["object", "array"]);
+    }
+
+    #[test]
+    fn highlights_toml_headers_keys_and_strings() {
+        assert_eq!(groups(TOML, &[
+            "[[bin]]",
+            "name = \"rilo\" # c",
+            "a.\"b c\" = { x = 1, y = 'z' }",
+            "s = \"\"\"one",
+            "two\"\"\"",
+            "d = 1979-05-27T07:32:00Z",
+        ]), vec![
+            "ttttttt",
+            "KKKK...ssssss.ccc",
+            "KKKKKKK.....K...n..K...sss..",
+            "K...ssssss",
+            "ssssss",
+            "K...nnnnnnnnnnnnnnnnnnnn",
+        ]);
+    }
+}
@@ -0,0 +1,239 @@
+use super::glob::{glob_match, glob_match_path};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Files and directories marking the root of a project, after `.git`.
+const ROOT_MARKERS: [&str; 6] = ["Cargo.toml", "package.json", "go.mod", "pyproject.toml", "setup.py", "Makefile"];
+
+// Files listed at most, so a huge tree does not stall the editor.
+pub const FILE_LIMIT: usize = 50000;
+
+// Matches shown at most by the file picker.
+const PICKER_RESULTS: usize = 200;
+
+// A line of a `.gitignore` file, applying to the paths under `base`.
+struct IgnoreRule {
+    base: String,
+    pattern: String,
+    negate: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+// The nearest directory above `dir` with a `.git`, or else with one of the
+// other root markers; `dir` itself without either.
+pub fn project_root(dir: &Path) -> PathBuf {
+    if let Some(root) = dir.ancestors().find(|d| d.join(".git").exists()) {
+        return root.to_path_buf();
+    }
+    dir.ancestors()
+        .find(|d| ROOT_MARKERS.iter().any(|marker| d.join(marker).exists()))
+        .unwrap_or(dir)
+        .to_path_buf()
+}
+
+// A directory being walked, with its entries still to visit, last first,
+// and the number of ignore rules that applied above it.
+struct WalkDir {
+    dir: PathBuf,
+    rel: String,
+    entries: Vec<(String, bool)>,
+    kept: usize,
+}
+
+// A walk through the files under a root, one entry at a time, leaving out
+// `.git` and what the `.gitignore` files of the tree ignore. The directories
+// being walked are kept on a stack so the walk can stop between any two
+// entries.
+pub struct ProjectWalk {
+    stack: Vec<WalkDir>,
+    rules: Vec<IgnoreRule>,
+    found: usize,
+}
+
+impl ProjectWalk {
+    pub fn new(root: &Path) -> ProjectWalk {
+        let mut walk = ProjectWalk { stack: Vec::new(), rules: Vec::new(), found: 0 };
+        walk.enter(root.to_path_buf(), String::new());
+        walk
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.stack.is_empty() || self.found >= FILE_LIMIT
+    }
+
+    // Visits the next entry, returning its path relative to the root when it
+    // is a file to list.
+    pub fn advance(&mut self) -> Option<String> {
+        if self.found >= FILE_LIMIT {
+            return None;
+        }
+        let top = self.stack.last_mut()?;
+        let (name, is_dir) = match top.entries.pop() {
+            Some(entry) => entry,
+            None => {
+                let kept = top.kept;
+                self.stack.pop();
+                self.rules.truncate(kept);
+                return None;
+            },
+        };
+        let path = if top.rel.is_empty() { name.clone() } else { format!("{}/{}", top.rel, name) };
+        if name == ".git" || is_ignored(&self.rules, &path, is_dir) {
+            return None;
+        }
+        if is_dir {
+            let dir = top.dir.join(&name);
+            self.enter(dir, path);
+            None
+        }else{
+            self.found += 1;
+            Some(path)
+        }
+    }
+
+    fn enter(&mut self, dir: PathBuf, rel: String) {
+        let kept = self.rules.len();
+        if let Ok(text) = fs::read_to_string(dir.join(".gitignore")) {
+            self.rules.extend(text.lines().filter_map(|line| parse_ignore_rule(line, &rel)));
+        }
+        let mut entries: Vec<(String, bool)> = match fs::read_dir(&dir) {
+            Ok(entries) => entries.filter_map(|e| e.ok())
+                .filter_map(|e| {
+                    let kind = e.file_type().ok()?;
+                    Some((e.file_name().to_string_lossy().into_owned(), kind.is_dir()))
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        entries.sort();
+        entries.reverse();
+        self.stack.push(WalkDir { dir, rel, entries, kept });
+    }
+}
+
+// The files under `root`, relative to it, as `ProjectWalk` lists them.
+pub fn project_files(root: &Path) -> Vec<String> {
+    let mut walk = ProjectWalk::new(root);
+    let mut files = Vec::new();
+    while !walk.is_done() {
+        files.extend(walk.advance());
+    }
+    files
+}
+
+fn parse_ignore_rule(line: &str, base: &str) -> Option<IgnoreRule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negate, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line.strip_prefix('\\').unwrap_or(line)),
+    };
+    let (dir_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    // A slash anywhere but at the end ties the pattern to the directory of
+    // the `.gitignore`.
+    let anchored = line.contains('/');
+    let pattern = String::from(line.strip_prefix('/').unwrap_or(line));
+    Some(IgnoreRule { base: String::from(base), pattern, negate, dir_only, anchored })
+}
+
+// The last rule matching `path` decides, and `!` rules take a path back.
+fn is_ignored(rules: &[IgnoreRule], path: &str, is_dir: bool) -> bool {
+    for rule in rules.iter().rev() {
+        if rule.dir_only && !is_dir {
+            continue;
+        }
+        let rel = if rule.base.is_empty() {
+            path
+        }else{
+            match path.strip_prefix(&rule.base).and_then(|p| p.strip_prefix('/')) {
+                Some(rel) => rel,
+                None => continue,
+            }
+        };
+        let matched = if rule.anchored {
+            glob_match_path(&rule.pattern, rel)
+        }else{
+            glob_match(&rule.pattern, rel.rsplit('/').next().unwrap_or(rel))
+        };
+        if matched {
+            return !rule.negate;
+        }
+    }
+    false
+}
+
+// The indexes of the files matching `query`, best first.
+pub fn rank_files(query: &str, files: &[String]) -> Vec<usize> {
+    let query = query.as_bytes();
+    let mut ranked: Vec<(i64, usize, usize)> = files.iter().enumerate()
+        .filter_map(|(idx, file)| Some((-fuzzy_score(query, file.as_bytes())?, file.len(), idx)))
+        .collect();
+    ranked.sort();
+    ranked.into_iter().take(PICKER_RESULTS).map(|(_, _, idx)| idx).collect()
+}
+
+// How well `path` matches `query`, whose characters it must have in order,
+// ignoring case. Characters in a row, at the start of words and in the file
+// name count more; a match within the file name alone is best.
+fn fuzzy_score(query: &[u8], path: &[u8]) -> Option<i64> {
+    let name = path.iter().rposition(|c| *c == b'/').map(|idx| idx + 1).unwrap_or(0);
+    let whole = match_score(query, path, name)?;
+    Some(match match_score(query, &path[name..], 0) {
+        Some(score) => whole.max(score + 10),
+        None => whole,
+    })
+}
+
+fn match_score(query: &[u8], text: &[u8], name: usize) -> Option<i64> {
+    let mut score: i64 = 0;
+    let mut from = 0;
+    let mut last: Option<usize> = None;
+    for q in query {
+        let pos = from + text[from..].iter().position(|c| c.eq_ignore_ascii_case(q))?;
+        score += 1;
+        if last.map(|last| last + 1 == pos).unwrap_or(false) {
+            score += 8;
+        }else if let Some(last) = last {
+            score -= (pos - last - 1).min(5) as i64;
+        }
+        let word_start = pos == 0 || b"/_-. ".contains(&text[pos - 1]) ||
+            (text[pos].is_ascii_uppercase() && text[pos - 1].is_ascii_lowercase());
+        if word_start {
+            score += 6;
+        }
+        if pos >= name {
+            score += 2;
+        }
+        last = Some(pos);
+        from = pos + 1;
+    }
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walks_the_tree_in_order_with_its_ignore_rules() {
+        let root = std::env::temp_dir().join(format!("rilo-walk-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["a/b", "a/target", "c", ".git"].iter() {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in ["a/b/x.rs", "a/b/x.log", "a/target/y", "a/keep.log", "c/x.log", "z", ".git/HEAD"].iter() {
+            fs::write(root.join(file), "").unwrap();
+        }
+        fs::write(root.join("a/.gitignore"), "*.log\n!keep.log\ntarget/\n").unwrap();
+        let files = project_files(&root);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(files, vec!["a/.gitignore", "a/b/x.rs", "a/keep.log", "c/x.log", "z"]);
+    }
+}
@@ -0,0 +1,403 @@
+use std::mem;
+
+// A small regular expression matcher for syntax definitions: literals, `.`,
+// classes like `[a-z_]` and `[^)]`, `\d \w \s` and their upper case negations,
+// `^` and `$`, `* + ?` (lazy with a `?` after them), groups with `|` and
+// `(?:...)` groups that do not capture.
+//
+// The pattern is compiled to a program that is run on all its threads at
+// once, one byte of the text at a time, so matching takes time and memory in
+// proportion to the text and the pattern whatever they hold. Threads keep the
+// order a backtracking matcher would try them in, so the match and its groups
+// are the ones it would find.
+#[derive(Clone)]
+pub struct Regex {
+    program: Vec<Inst>,
+    groups: usize,
+}
+
+#[derive(Clone)]
+enum Node {
+    Byte(u8),
+    Any,
+    Class(Vec<(u8, u8)>, bool),
+    Start,
+    End,
+    Group(Option<usize>, Vec<Vec<Node>>),
+    Repeat(Box<Node>, usize, Option<usize>, bool),
+}
+
+#[derive(Clone)]
+enum Inst {
+    Byte(u8),
+    Any,
+    Class(Vec<(u8, u8)>, bool),
+    Start,
+    End,
+    // Goes on at both, the first one first.
+    Split(usize, usize),
+    Jump(usize),
+    // Notes the position in a slot, two for each group.
+    Save(usize),
+    Match,
+}
+
+// The start and end of each group, the whole match being group 0.
+type Captures = Vec<Option<(usize, usize)>>;
+
+// A thread: where it is in the program and the positions it saved.
+type Thread = (usize, Vec<Option<usize>>);
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let p = pattern.as_bytes();
+        let mut idx = 0;
+        let mut groups = 0;
+        let alternatives = parse_alternatives(p, &mut idx, &mut groups)?;
+        if idx < p.len() {
+            return Err(format!("unmatched ')' in '{}'", pattern));
+        }
+        let mut program = Vec::new();
+        compile(&Node::Group(Some(0), alternatives), &mut program);
+        program.push(Inst::Match);
+        Ok(Regex { program, groups })
+    }
+
+    // The groups of the leftmost match in `text`, if any.
+    pub fn captures(&self, text: &[u8]) -> Option<Captures> {
+        let mut threads: Vec<Thread> = Vec::new();
+        let mut next: Vec<Thread> = Vec::new();
+        // The step each instruction was last added in, so a thread is only
+        // added once per step, by the first way to get to it.
+        let mut added = vec![usize::MAX; self.program.len()];
+        let mut found: Option<Vec<Option<usize>>> = None;
+        for pos in 0..=text.len() {
+            // A match starting here comes after those that started earlier.
+            if found.is_none() {
+                self.add_thread(&mut threads, &mut added, (0, vec![None; self.groups * 2 + 2]), text, pos);
+            }
+            if threads.is_empty() {
+                break;
+            }
+            for (pc, saved) in threads.drain(..) {
+                let step = match (&self.program[pc], text.get(pos)) {
+                    (Inst::Match, _) => {
+                        // The threads after this one would only give later choices.
+                        found = Some(saved);
+                        break;
+                    },
+                    (Inst::Byte(b), Some(c)) => b == c,
+                    (Inst::Any, Some(_)) => true,
+                    (Inst::Class(ranges, negate), Some(c)) => ranges.iter().any(|(lo, hi)| lo <= c && c <= hi) != *negate,
+                    _ => false,
+                };
+                if step {
+                    self.add_thread(&mut next, &mut added, (pc + 1, saved), text, pos + 1);
+                }
+            }
+            mem::swap(&mut threads, &mut next);
+        }
+        let saved = found?;
+        Some((0..=self.groups).map(|group| match (saved[group * 2], saved[group * 2 + 1]) {
+            (Some(start), Some(end)) => Some((start, end)),
+            _ => None,
+        }).collect())
+    }
+
+    // Follows the jumps, splits and assertions from `thread` at `pos` and adds
+    // the threads waiting for a byte or matching to `threads`, in order.
+    fn add_thread(&self, threads: &mut Vec<Thread>, added: &mut [usize], thread: Thread, text: &[u8], pos: usize) {
+        let mut stack = vec![thread];
+        while let Some((pc, mut saved)) = stack.pop() {
+            if added[pc] == pos {
+                continue;
+            }
+            added[pc] = pos;
+            match &self.program[pc] {
+                Inst::Jump(to) => stack.push((*to, saved)),
+                Inst::Split(first, second) => {
+                    stack.push((*second, saved.clone()));
+                    stack.push((*first, saved));
+                },
+                Inst::Save(slot) => {
+                    saved[*slot] = Some(pos);
+                    stack.push((pc + 1, saved));
+                },
+                Inst::Start => if pos == 0 {
+                    stack.push((pc + 1, saved));
+                },
+                Inst::End => if pos == text.len() {
+                    stack.push((pc + 1, saved));
+                },
+                _ => threads.push((pc, saved)),
+            }
+        }
+    }
+}
+
+fn parse_alternatives(p: &[u8], idx: &mut usize, groups: &mut usize) -> Result<Vec<Vec<Node>>, String> {
+    let mut alternatives = vec![Vec::new()];
+    while *idx < p.len() {
+        match p[*idx] {
+            b')' => break,
+            b'|' => {
+                alternatives.push(Vec::new());
+                *idx += 1;
+            },
+            _ => {
+                let atom = parse_atom(p, idx, groups)?;
+                let node = parse_repeat(p, idx, atom);
+                alternatives.last_mut().unwrap().push(node);
+            },
+        }
+    }
+    Ok(alternatives)
+}
+
+fn parse_atom(p: &[u8], idx: &mut usize, groups: &mut usize) -> Result<Node, String> {
+    let c = p[*idx];
+    *idx += 1;
+    Ok(match c {
+        b'.' => Node::Any,
+        b'^' => Node::Start,
+        b'$' => Node::End,
+        b'*' | b'+' | b'?' => return Err(format!("nothing to repeat before '{}'", c as char)),
+        b'(' => {
+            let number = if p[*idx..].starts_with(b"?:") {
+                *idx += 2;
+                None
+            }else{
+                *groups += 1;
+                Some(*groups)
+            };
+            let alternatives = parse_alternatives(p, idx, groups)?;
+            if *idx >= p.len() {
+                return Err(String::from("unclosed '('"));
+            }
+            *idx += 1;
+            Node::Group(number, alternatives)
+        },
+        b'[' => parse_class(p, idx)?,
+        b'\\' => {
+            let c = *p.get(*idx).ok_or("trailing '\\'")?;
+            *idx += 1;
+            match escape_class(c) {
+                Some(ranges) => Node::Class(ranges, c.is_ascii_uppercase()),
+                None => Node::Byte(escape_byte(c)),
+            }
+        },
+        c => Node::Byte(c),
+    })
+}
+
+fn parse_repeat(p: &[u8], idx: &mut usize, atom: Node) -> Node {
+    let (min, max) = match p.get(*idx) {
+        Some(b'*') => (0, None),
+        Some(b'+') => (1, None),
+        Some(b'?') => (0, Some(1)),
+        _ => return atom,
+    };
+    *idx += 1;
+    let greedy = p.get(*idx) != Some(&b'?');
+    if !greedy {
+        *idx += 1;
+    }
+    Node::Repeat(Box::new(atom), min, max, greedy)
+}
+
+fn parse_class(p: &[u8], idx: &mut usize) -> Result<Node, String> {
+    let negate = p.get(*idx) == Some(&b'^');
+    if negate {
+        *idx += 1;
+    }
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let c = *p.get(*idx).ok_or("unclosed '['")?;
+        *idx += 1;
+        if c == b']' && !first {
+            break;
+        }
+        first = false;
+        let lo = if c == b'\\' {
+            let e = *p.get(*idx).ok_or("unclosed '['")?;
+            *idx += 1;
+            if let Some(class) = escape_class(e) {
+                if e.is_ascii_uppercase() {
+                    return Err(format!("'\\{}' inside '[]'", e as char));
+                }
+                ranges.extend(class);
+                continue;
+            }
+            escape_byte(e)
+        }else{
+            c
+        };
+        if p.get(*idx) == Some(&b'-') && p.get(*idx + 1).map(|c| *c != b']').unwrap_or(false) {
+            ranges.push((lo, p[*idx + 1]));
+            *idx += 2;
+        }else{
+            ranges.push((lo, lo));
+        }
+    }
+    Ok(Node::Class(ranges, negate))
+}
+
+fn escape_class(c: u8) -> Option<Vec<(u8, u8)>> {
+    match c.to_ascii_lowercase() {
+        b'd' => Some(vec![(b'0', b'9')]),
+        b'w' => Some(vec![(b'a', b'z'), (b'A', b'Z'), (b'0', b'9'), (b'_', b'_')]),
+        b's' => Some(vec![(b' ', b' '), (b'\t', b'\r')]),
+        _ => None,
+    }
+}
+
+fn escape_byte(c: u8) -> u8 {
+    match c {
+        b't' => b'\t',
+        b'n' => b'\n',
+        c => c,
+    }
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>) {
+    match node {
+        Node::Byte(b) => program.push(Inst::Byte(*b)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class(ranges, negate) => program.push(Inst::Class(ranges.clone(), *negate)),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Group(number, alternatives) => {
+            if let Some(number) = number {
+                program.push(Inst::Save(number * 2));
+            }
+            let mut jumps = Vec::new();
+            for (idx, alternative) in alternatives.iter().enumerate() {
+                let split = program.len();
+                if idx + 1 < alternatives.len() {
+                    program.push(Inst::Split(split + 1, 0));
+                }
+                for node in alternative {
+                    compile(node, program);
+                }
+                if idx + 1 < alternatives.len() {
+                    jumps.push(program.len());
+                    program.push(Inst::Jump(0));
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+            if let Some(number) = number {
+                program.push(Inst::Save(number * 2 + 1));
+            }
+        },
+        Node::Repeat(atom, min, max, greedy) => {
+            for _ in 0..*min {
+                compile(atom, program);
+            }
+            let order = |body: usize, out: usize| if *greedy { Inst::Split(body, out) } else { Inst::Split(out, body) };
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Jump(0));
+                    compile(atom, program);
+                    program.push(Inst::Jump(split));
+                    program[split] = order(split + 1, program.len());
+                },
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Jump(0));
+                        compile(atom, program);
+                    }
+                    let out = program.len();
+                    for split in splits {
+                        program[split] = order(split + 1, out);
+                    }
+                },
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn groups(pattern: &str, text: &str) -> Option<Vec<Option<String>>> {
+        let caps = Regex::new(pattern).unwrap().captures(text.as_bytes())?;
+        Some(caps.iter().map(|group| group.map(|(start, end)| String::from(&text[start..end]))).collect())
+    }
+
+    fn found(pattern: &str, text: &str) -> Option<String> {
+        groups(pattern, text).and_then(|caps| caps[0].clone())
+    }
+
+    #[test]
+    fn matches_the_leftmost_match() {
+        assert_eq!(found("b+", "abbbc"), Some(String::from("bbb")));
+        assert_eq!(found("a.c", "xxabcx"), Some(String::from("abc")));
+        assert_eq!(found("x", "abc"), None);
+        assert_eq!(found("", "abc"), Some(String::new()));
+        assert_eq!(found("^a", "ba"), None);
+        assert_eq!(found("a$", "aba"), Some(String::from("a")));
+        assert_eq!(found("^$", ""), Some(String::new()));
+    }
+
+    #[test]
+    fn matches_classes() {
+        assert_eq!(found("[a-c_]+", "xx_ab_cd"), Some(String::from("_ab_c")));
+        assert_eq!(found("[^)]*", "ab)c"), Some(String::from("ab")));
+        assert_eq!(found(r"\d+", "ab12c"), Some(String::from("12")));
+        assert_eq!(found(r"\w+", "  foo_1 "), Some(String::from("foo_1")));
+        assert_eq!(found(r"\s+", "a \tb"), Some(String::from(" \t")));
+        assert_eq!(found(r"\S+", "  ab "), Some(String::from("ab")));
+        assert_eq!(found(r"[\w.\-]+", "a-b.c d"), Some(String::from("a-b.c")));
+        assert_eq!(found(r"\(", "f(x)"), Some(String::from("(")));
+    }
+
+    #[test]
+    fn repeats_greedily_unless_lazy() {
+        assert_eq!(found("<.*>", "<a><b>"), Some(String::from("<a><b>")));
+        assert_eq!(found("<.*?>", "<a><b>"), Some(String::from("<a>")));
+        assert_eq!(found("ab?", "ab"), Some(String::from("ab")));
+        assert_eq!(found("ab??", "ab"), Some(String::from("a")));
+        assert_eq!(found("(a*)*b", "aab"), Some(String::from("aab")));
+    }
+
+    #[test]
+    fn captures_groups() {
+        let caps = groups(r"^(\w+)\s*=\s*(?:(\d+)|(\w+))$", "size = 12").unwrap();
+        assert_eq!(caps, vec![Some(String::from("size = 12")), Some(String::from("size")), Some(String::from("12")), None]);
+        let caps = groups("(a|ab)(c|bcd)", "abcd").unwrap();
+        assert_eq!(caps, vec![Some(String::from("abcd")), Some(String::from("a")), Some(String::from("bcd"))]);
+        let caps = groups("(?:(a)|b)+", "ab").unwrap();
+        assert_eq!(caps[1], Some(String::from("a")));
+        assert_eq!(groups("(x)?y", "y").unwrap()[1], None);
+    }
+
+    #[test]
+    fn refuses_bad_patterns() {
+        assert!(Regex::new("(a").is_err());
+        assert!(Regex::new("a)").is_err());
+        assert!(Regex::new("[a").is_err());
+        assert!(Regex::new("*a").is_err());
+        assert!(Regex::new("a\\").is_err());
+        assert!(Regex::new(r"[\S]").is_err());
+    }
+
+    #[test]
+    fn matches_long_lines() {
+        let regex = Regex::new(r"^[A-Za-z_][\w \t*]*[\s*](\w+)\s*\([^;]*$").unwrap();
+        let line = format!("int x{}", " a".repeat(10000));
+        assert!(regex.captures(line.as_bytes()).is_none());
+        let line = format!("int {}f(void) {{", "a ".repeat(10000));
+        let caps = regex.captures(line.as_bytes()).unwrap();
+        assert_eq!(caps[1].map(|(start, end)| &line[start..end]), Some("f"));
+        assert!(Regex::new("(a|aa)*$").unwrap().captures(&[b'a'; 20000]).is_some());
+    }
+}
@@ -0,0 +1,153 @@
+use super::panel::ListItem;
+use super::project::ProjectWalk;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+// Bytes looked at for a NUL to tell a binary file.
+const BINARY_PROBE: usize = 8000;
+
+// Files larger than this are not searched.
+const FILE_SIZE_LIMIT: u64 = 8 << 20;
+
+// Matches listed at most.
+pub const SEARCH_RESULTS: usize = 10000;
+
+// Text shown of a matching line at most; longer lines are cut around the match.
+const LINE_TEXT_LIMIT: usize = 200;
+
+// A search for `pattern` through the files of a project, walking it and
+// searching a few files at a time so the editor keeps reading keys.
+pub struct ProjectSearch {
+    pub pattern: String,
+    root: PathBuf,
+    walk: ProjectWalk,
+    pub matches: usize,
+    pub matched_files: usize,
+}
+
+impl ProjectSearch {
+    // Searches the files under `root`; the matches are given relative to `cwd`.
+    pub fn new(pattern: String, root: PathBuf) -> ProjectSearch {
+        let walk = ProjectWalk::new(&root);
+        ProjectSearch { pattern, root, walk, matches: 0, matched_files: 0 }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.walk.is_done() || self.matches >= SEARCH_RESULTS
+    }
+
+    // Walks and searches files until `deadline` and returns the matches found
+    // in them, one for each matching line.
+    pub fn step(&mut self, deadline: Instant) -> Vec<ListItem> {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let mut items = Vec::new();
+        while !self.is_done() && Instant::now() < deadline {
+            let path = match self.walk.advance() {
+                Some(file) => self.root.join(file),
+                None => continue,
+            };
+            let file = path.strip_prefix(&cwd).unwrap_or(&path).to_string_lossy().into_owned();
+            let found = search_file(&path, self.pattern.as_bytes(), &file, SEARCH_RESULTS - self.matches);
+            if !found.is_empty() {
+                self.matches += found.len();
+                self.matched_files += 1;
+                items.extend(found);
+            }
+        }
+        items
+    }
+}
+
+fn search_file(path: &Path, pattern: &[u8], file: &str, limit: usize) -> Vec<ListItem> {
+    let too_large = fs::metadata(path).map(|m| m.len() > FILE_SIZE_LIMIT).unwrap_or(true);
+    if too_large || pattern.is_empty() {
+        return Vec::new();
+    }
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(_) => return Vec::new(),
+    };
+    if bytes[..bytes.len().min(BINARY_PROBE)].contains(&0) {
+        return Vec::new();
+    }
+    let mut items = Vec::new();
+    for (idx, line) in bytes.split(|c| *c == b'\n').enumerate() {
+        if items.len() >= limit {
+            break;
+        }
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let col = match line.windows(pattern.len()).position(|w| w == pattern) {
+            Some(col) => col,
+            None => continue,
+        };
+        let (text, span) = line_text(line, col, col + pattern.len());
+        items.push(ListItem { file: String::from(file), line: idx as u32 + 1, col: col as u32 + 1, text, span });
+    }
+    items
+}
+
+// The line without its indentation, or the part of it around the match when
+// it is long, with where the match is in it.
+fn line_text(line: &[u8], start: usize, end: usize) -> (String, Option<(usize, usize)>) {
+    let indent = line.iter().take_while(|c| c.is_ascii_whitespace()).count().min(start);
+    let from = if line.len() - indent > LINE_TEXT_LIMIT { start.saturating_sub(40).max(indent) } else { indent };
+    let to = line.len().min(from + LINE_TEXT_LIMIT).max(end);
+    let before = String::from_utf8_lossy(&line[from..start]).into_owned();
+    let matched = String::from_utf8_lossy(&line[start..end]).into_owned();
+    let after = String::from_utf8_lossy(&line[end..to]).into_owned();
+    let span = (before.len(), before.len() + matched.len());
+    (format!("{}{}{}", before.replace('\t', " "), matched, after.trim_end().replace('\t', " ")), Some(span))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The text shown for the match of `pattern` in `line`, with the match
+    // marked by brackets.
+    fn shown(line: &str, pattern: &str) -> String {
+        let start = line.find(pattern).unwrap();
+        let (text, span) = line_text(line.as_bytes(), start, start + pattern.len());
+        let (from, to) = span.unwrap();
+        assert_eq!(&text[from..to], pattern);
+        format!("{}[{}]{}", &text[..from], &text[from..to], &text[to..])
+    }
+
+    #[test]
+    fn shows_the_line_around_the_match() {
+        assert_eq!(shown("    let x = foo;  ", "foo"), "let x = [foo];");
+        assert_eq!(shown("\tif\ta == foo {", "foo"), "if a == [foo] {");
+        assert_eq!(shown("  foo", "  foo"), "[  foo]");
+        let long = format!("{}needle{}", "x".repeat(300), "y".repeat(300));
+        let text = shown(&long, "needle");
+        assert_eq!(text, format!("{}[needle]{}", "x".repeat(40), "y".repeat(154)));
+        let tail = format!("  {}needle", "x".repeat(300));
+        assert_eq!(shown(&tail, "needle"), format!("{}[needle]", "x".repeat(40)));
+        // A cut through a character leaves the span on the match.
+        let wide = format!("{}needle", "\u{e9}".repeat(100));
+        assert!(shown(&wide, "needle").ends_with("[needle]"));
+    }
+
+    #[test]
+    fn searches_the_project_files() {
+        let root = std::env::temp_dir().join(format!("rilo-search-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/a.rs"), "fn needle() {}\r\nlet x = 1;\n  needle(); needle();\n").unwrap();
+        fs::write(root.join("bin.dat"), b"needle\0").unwrap();
+        fs::write(root.join("b.txt"), "no match\n").unwrap();
+        let mut search = ProjectSearch::new(String::from("needle"), root.clone());
+        let mut items = Vec::new();
+        while !search.is_done() {
+            items.extend(search.step(Instant::now() + std::time::Duration::from_secs(1)));
+        }
+        fs::remove_dir_all(&root).unwrap();
+        let found: Vec<(u32, u32, String)> = items.into_iter()
+            .map(|item| (item.line, item.col, item.text))
+            .collect();
+        assert_eq!(found, vec![(1, 4, String::from("fn needle() {}")), (3, 3, String::from("needle(); needle();"))]);
+        assert_eq!((search.matches, search.matched_files), (2, 1));
+    }
+}
@@ -0,0 +1,204 @@
+use super::{RILO_TAB_STOP, RILO_QUIT_TIMES, RILO_MESSAGE_TIMEOUT, RILO_HELP};
+use super::fold::{FoldMethod, parse_fold_method};
+use super::theme::{Theme, ColorDepth, RILO_DEFAULT_THEME, parse_color_depth};
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const RILO_CONFIG_FILE: &str = "config";
+pub const RILO_PROJECT_DIR: &str = ".rilo";
+
+#[derive(Clone)]
+pub struct EditorSettings {
+    pub tab_stop: u16,
+    pub expand_tabs: bool,
+    pub auto_indent: bool,
+    pub auto_pairs: bool,
+    pub detect_indent: bool,
+    pub quit_times: u16,
+    pub message_timeout: u64,
+    pub line_numbers: bool,
+    pub wrap: bool,
+    pub fold_method: FoldMethod,
+    pub structural_highlighting: bool,
+    pub trim_trailing_whitespace: bool,
+    pub ensure_final_newline: bool,
+    pub format_on_save: bool,
+    pub formatters: Vec<(String, String)>,
+    pub build_command: String,
+    pub lsp_servers: Vec<(String, String)>,
+    pub help: String,
+    pub theme: String,
+    pub color_depth: Option<ColorDepth>,
+    pub colors: Theme,
+}
+
+impl EditorSettings {
+    pub fn new() -> EditorSettings {
+        EditorSettings {
+            tab_stop: RILO_TAB_STOP,
+            expand_tabs: false,
+            auto_indent: true,
+            auto_pairs: true,
+            detect_indent: true,
+            quit_times: RILO_QUIT_TIMES,
+            message_timeout: RILO_MESSAGE_TIMEOUT,
+            line_numbers: false,
+            wrap: false,
+            fold_method: FoldMethod::Auto,
+            structural_highlighting: true,
+            trim_trailing_whitespace: false,
+            ensure_final_newline: false,
+            format_on_save: true,
+            formatters: Vec::new(),
+            build_command: String::new(),
+            lsp_servers: Vec::new(),
+            help: String::from(RILO_HELP),
+            theme: String::from(RILO_DEFAULT_THEME),
+            color_depth: None,
+            colors: Theme::new(),
+        }
+    }
+
+    // Loads the user configuration and then every project configuration found
+    // between the filesystem root and `dir`, so the nearest one wins.
+    pub fn load(dir: Option<&Path>) -> (EditorSettings, Vec<String>) {
+        let mut settings = EditorSettings::new();
+        let mut errors: Vec<String> = Vec::new();
+        if let Some(path) = user_config_dir() {
+            errors.append(&mut settings.load_file(&path.join(RILO_CONFIG_FILE)));
+        }
+        if let Some(dir) = dir {
+            for path in project_config_files(dir) {
+                errors.append(&mut settings.load_file(&path));
+            }
+        }
+        (settings, errors)
+    }
+
+    pub fn load_file(&mut self, path: &Path) -> Vec<String> {
+        let mut errors: Vec<String> = Vec::new();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    errors.push(format!("{}: {}", path.display(), e));
+                }
+                return errors;
+            },
+        };
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let result = match line.find('=') {
+                Some(pos) => self.set(line[..pos].trim(), unquote(line[pos + 1..].trim())),
+                None => Err(format!("expected 'key = value', found '{}'", line)),
+            };
+            if let Err(msg) = result {
+                errors.push(format!("{}:{}: {}", path.display(), idx + 1, msg));
+            }
+        }
+        errors
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "tab_width" => self.tab_stop = parse_number(key, value, 1, 32)? as u16,
+            "expand_tabs" => self.expand_tabs = parse_bool(key, value)?,
+            "auto_indent" => self.auto_indent = parse_bool(key, value)?,
+            "auto_pairs" => self.auto_pairs = parse_bool(key, value)?,
+            "detect_indent" => self.detect_indent = parse_bool(key, value)?,
+            "quit_times" => self.quit_times = parse_number(key, value, 0, 100)? as u16,
+            "message_timeout" => self.message_timeout = parse_number(key, value, 0, 3600)?,
+            "line_numbers" => self.line_numbers = parse_bool(key, value)?,
+            "wrap" => self.wrap = parse_bool(key, value)?,
+            "fold_method" => self.fold_method = parse_fold_method(value)?,
+            "structural_highlighting" => self.structural_highlighting = parse_bool(key, value)?,
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = parse_bool(key, value)?,
+            "ensure_final_newline" => self.ensure_final_newline = parse_bool(key, value)?,
+            "format_on_save" => self.format_on_save = parse_bool(key, value)?,
+            "build_command" => self.build_command = String::from(value),
+            "help" => self.help = String::from(value),
+            "theme" => self.theme = String::from(value),
+            "color_depth" => self.color_depth = parse_color_depth(value)?,
+            _ => {
+                if let Some(group) = key.strip_prefix("color.") {
+                    self.colors.set(group, value)?;
+                }else if let Some(file_type) = key.strip_prefix("formatter.") {
+                    self.formatters.retain(|(ft, _)| ft != file_type);
+                    if !value.is_empty() {
+                        self.formatters.push((String::from(file_type), String::from(value)));
+                    }
+                }else if let Some(file_type) = key.strip_prefix("lsp.") {
+                    self.lsp_servers.retain(|(ft, _)| ft != file_type);
+                    if !value.is_empty() {
+                        self.lsp_servers.push((String::from(file_type), String::from(value)));
+                    }
+                }else{
+                    return Err(format!("unknown option '{}'", key));
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+pub fn user_config_dir() -> Option<PathBuf> {
+    if let Ok(dir) = env::var("XDG_CONFIG_HOME") {
+        if !dir.is_empty() {
+            return Some(Path::new(&dir).join("rilo"));
+        }
+    }
+    match env::var("HOME") {
+        Ok(home) if !home.is_empty() => Some(Path::new(&home).join(".config").join("rilo")),
+        _ => None,
+    }
+}
+
+// Project `.rilo` directories from the outermost directory down to `dir`.
+pub fn project_dirs(dir: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    let mut current = Some(dir);
+    while let Some(d) = current {
+        let path = d.join(RILO_PROJECT_DIR);
+        if path.is_dir() {
+            dirs.insert(0, path);
+        }
+        current = d.parent();
+    }
+    dirs
+}
+
+pub fn project_config_files(dir: &Path) -> Vec<PathBuf> {
+    project_dirs(dir).into_iter()
+        .map(|d| d.join(RILO_CONFIG_FILE))
+        .filter(|p| p.is_file())
+        .collect()
+}
+
+fn unquote(value: &str) -> &str {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        &value[1..value.len() - 1]
+    }else{
+        value
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(format!("invalid value '{}' for '{}' (expected true or false)", value, key)),
+    }
+}
+
+fn parse_number(key: &str, value: &str, min: u64, max: u64) -> Result<u64, String> {
+    match value.parse::<u64>() {
+        Ok(n) if n >= min && n <= max => Ok(n),
+        _ => Err(format!("invalid value '{}' for '{}' (expected a number from {} to {})",
+                value, key, min, max)),
+    }
+}
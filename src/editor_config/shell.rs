@@ -0,0 +1,47 @@
+use std::io::{Write};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+
+// Runs `command` with `sh -c`, feeding `input` on stdin, and returns what it
+// printed. A failing command returns the first line of its error output.
+pub fn run_filter(command: &str, input: &[u8]) -> Result<Vec<u8>, String> {
+    let output = run_shell(command, input)?;
+    if output.status.success() {
+        return Ok(output.stdout);
+    }
+    let errors = String::from_utf8_lossy(&output.stderr);
+    match errors.lines().map(str::trim).find(|line| !line.is_empty()) {
+        Some(line) => Err(String::from(line)),
+        None => Err(format!("{} exited with {}", command, output.status)),
+    }
+}
+
+// Returns everything `command` printed, its error output after its output,
+// and whether it succeeded.
+pub fn run_capture(command: &str) -> Result<(Vec<u8>, bool), String> {
+    let output = run_shell(command, b"")?;
+    let mut text = output.stdout;
+    text.extend_from_slice(&output.stderr);
+    Ok((text, output.status.success()))
+}
+
+fn run_shell(command: &str, input: &[u8]) -> Result<Output, String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("{}: {}", command, e))?;
+    // Written from another thread so a command that prints before reading all
+    // its input cannot block on a full pipe.
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = thread::spawn(move || {
+        let _ = stdin.write_all(&input);
+    });
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    let _ = writer.join();
+    Ok(output)
+}
@@ -0,0 +1,299 @@
+use super::settings::{user_config_dir, project_dirs};
+
+use std::fs;
+use std::path::Path;
+
+pub const RILO_SNIPPET_DIR: &str = "snippets";
+pub const RILO_SNIPPET_SUFFIX: &str = ".snippets";
+
+const BUILTIN_SNIPPETS: [(&str, &str); 3] = [
+    ("rust.snippets", include_str!("../../snippets/rust.snippets")),
+    ("python.snippets", include_str!("../../snippets/python.snippets")),
+    ("c.snippets", include_str!("../../snippets/c.snippets")),
+];
+
+// A snippet of the file type its file is named after.
+#[derive(Clone)]
+pub struct Snippet {
+    pub file_type: String,
+    pub trigger: String,
+    pub body: Vec<String>,
+}
+
+// A placeholder of an expanded snippet, at `row` between columns `start` and
+// `end`. Placeholders with the same number mirror the first one.
+#[derive(Clone, Copy)]
+pub struct Field {
+    pub number: u32,
+    pub row: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+// The placeholders of the snippet being filled in, visited in `stops` order.
+pub struct SnippetSession {
+    pub fields: Vec<Field>,
+    pub stops: Vec<usize>,
+    pub current: usize,
+    // The placeholder was just entered: typing replaces its default text.
+    pub fresh: bool,
+}
+
+impl SnippetSession {
+    pub fn new(fields: Vec<Field>) -> SnippetSession {
+        let mut numbers: Vec<u32> = fields.iter().map(|f| f.number).collect();
+        numbers.sort_by_key(|n| if *n == 0 { u32::MAX } else { *n });
+        numbers.dedup();
+        let stops = numbers.iter()
+            .filter_map(|n| fields.iter().position(|f| f.number == *n))
+            .collect();
+        SnippetSession { fields, stops, current: 0, fresh: true }
+    }
+
+    pub fn active(&self) -> Field {
+        self.fields[self.stops[self.current]]
+    }
+
+    // Whether the last stop, `$0`, is reached; the snippet is done there.
+    pub fn at_end(&self) -> bool {
+        self.current + 1 == self.stops.len()
+    }
+
+    // Moves the placeholders after column `at` of `row` by `delta` columns;
+    // the one being edited grows or shrinks instead.
+    pub fn shift(&mut self, row: usize, at: usize, delta: isize, edited: Option<usize>) {
+        for (idx, field) in self.fields.iter_mut().enumerate() {
+            if field.row != row {
+                continue;
+            }
+            if Some(idx) == edited {
+                field.end = (field.end as isize + delta) as usize;
+            }else if field.start >= at {
+                field.start = (field.start as isize + delta) as usize;
+                field.end = (field.end as isize + delta) as usize;
+            }
+        }
+    }
+}
+
+// Loads the built-in snippets, then `~/.config/rilo/snippets/` and the project
+// `.rilo/snippets/` directories; a trigger defined again replaces the earlier one.
+pub fn load_snippets(dir: Option<&Path>, errors: &mut Vec<String>) -> Vec<Snippet> {
+    let mut snippets: Vec<Snippet> = Vec::new();
+    for (origin, text) in BUILTIN_SNIPPETS.iter() {
+        parse_snippets(text, origin, &mut snippets, errors);
+    }
+    let mut dirs = Vec::new();
+    if let Some(path) = user_config_dir() {
+        dirs.push(path.join(RILO_SNIPPET_DIR));
+    }
+    if let Some(dir) = dir {
+        dirs.extend(project_dirs(dir).into_iter().map(|path| path.join(RILO_SNIPPET_DIR)));
+    }
+    for dir in dirs {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let mut paths: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path())
+            .filter(|p| p.to_string_lossy().ends_with(RILO_SNIPPET_SUFFIX))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let origin = path.display().to_string();
+            match fs::read_to_string(&path) {
+                Ok(text) => parse_snippets(&text, &origin, &mut snippets, errors),
+                Err(e) => errors.push(format!("{}: {}", origin, e)),
+            }
+        }
+    }
+    snippets
+}
+
+// `snippet <trigger>` starts a snippet and the lines after it starting with
+// a tab are its body.
+fn parse_snippets(text: &str, origin: &str, snippets: &mut Vec<Snippet>, errors: &mut Vec<String>) {
+    let name = Path::new(origin).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let file_type = String::from(name.strip_suffix(RILO_SNIPPET_SUFFIX).unwrap_or(&name));
+    let mut current: Option<Snippet> = None;
+    for (idx, line) in text.lines().enumerate() {
+        if let Some(body) = line.strip_prefix('\t') {
+            match current.as_mut() {
+                Some(snippet) => snippet.body.push(String::from(body)),
+                None => errors.push(format!("{}:{}: body line outside a snippet", origin, idx + 1)),
+            }
+            continue;
+        }
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.strip_prefix("snippet ").map(str::trim) {
+            Some(trigger) if !trigger.is_empty() => {
+                add_snippet(snippets, current.take());
+                current = Some(Snippet {
+                    file_type: file_type.clone(),
+                    trigger: String::from(trigger),
+                    body: Vec::new(),
+                });
+            },
+            _ => errors.push(format!("{}:{}: expected 'snippet <trigger>'", origin, idx + 1)),
+        }
+    }
+    add_snippet(snippets, current);
+}
+
+fn add_snippet(snippets: &mut Vec<Snippet>, snippet: Option<Snippet>) {
+    if let Some(snippet) = snippet {
+        snippets.retain(|s| s.file_type != snippet.file_type || s.trigger != snippet.trigger);
+        snippets.push(snippet);
+    }
+}
+
+// The text of a snippet with its placeholders: `$1`, `${2:default}` and `$0`
+// where the cursor ends up, `\$` for a dollar sign. Lines after the first get
+// `indent`, and their leading tabs become `unit`. Without a `$0` the snippet
+// ends after its text.
+pub fn expand_snippet(body: &[String], indent: &[u8], unit: &[u8]) -> (Vec<Vec<u8>>, Vec<Field>) {
+    let mut lines: Vec<Vec<u8>> = Vec::new();
+    let mut fields: Vec<Field> = Vec::new();
+    for (row, text) in body.iter().enumerate() {
+        let bytes = text.as_bytes();
+        let mut line: Vec<u8> = Vec::new();
+        let mut idx = 0;
+        if row > 0 {
+            line.extend_from_slice(indent);
+            while idx < bytes.len() && bytes[idx] == b'\t' {
+                line.extend_from_slice(unit);
+                idx += 1;
+            }
+        }
+        while idx < bytes.len() {
+            match (bytes[idx], bytes.get(idx + 1)) {
+                (b'\\', Some(b'$')) => {
+                    line.push(b'$');
+                    idx += 2;
+                },
+                (b'$', Some(c)) if c.is_ascii_digit() => {
+                    let end = idx + 1 + bytes[idx + 1..].iter().take_while(|c| c.is_ascii_digit()).count();
+                    let number = text[idx + 1..end].parse().unwrap_or(0);
+                    fields.push(Field { number, row, start: line.len(), end: line.len() });
+                    idx = end;
+                },
+                (b'$', Some(b'{')) => {
+                    let close = match bytes[idx..].iter().position(|c| *c == b'}') {
+                        Some(pos) => idx + pos,
+                        None => {
+                            line.push(b'$');
+                            idx += 1;
+                            continue;
+                        },
+                    };
+                    let inner = &text[idx + 2..close];
+                    let (number, default) = match inner.find(':') {
+                        Some(pos) => (&inner[..pos], &inner[pos + 1..]),
+                        None => (inner, ""),
+                    };
+                    let start = line.len();
+                    line.extend_from_slice(default.as_bytes());
+                    fields.push(Field { number: number.parse().unwrap_or(0), row, start, end: line.len() });
+                    idx = close + 1;
+                },
+                (c, _) => {
+                    line.push(c);
+                    idx += 1;
+                },
+            }
+        }
+        lines.push(line);
+    }
+    if lines.is_empty() {
+        lines.push(Vec::new());
+    }
+    // Mirrors start with the text of the first placeholder of their number.
+    for idx in 0..fields.len() {
+        let first = fields[..idx].iter().find(|f| f.number == fields[idx].number).copied();
+        if let Some(first) = first {
+            let text = lines[first.row][first.start..first.end].to_vec();
+            let field = fields[idx];
+            let delta = text.len() as isize - (field.end - field.start) as isize;
+            lines[field.row].splice(field.start..field.end, text);
+            for other in fields.iter_mut().skip(idx + 1) {
+                if other.row == field.row && other.start >= field.end {
+                    other.start = (other.start as isize + delta) as usize;
+                    other.end = (other.end as isize + delta) as usize;
+                }
+            }
+            fields[idx].end = (field.end as isize + delta) as usize;
+        }
+    }
+    if !fields.iter().any(|f| f.number == 0) {
+        let row = lines.len() - 1;
+        let end = lines[row].len();
+        fields.push(Field { number: 0, row, start: end, end });
+    }
+    (lines, fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each placeholder as its number, row, start and end.
+    type Placed = (u32, usize, usize, usize);
+
+    fn expand(body: &[&str]) -> (Vec<String>, Vec<Placed>) {
+        let body: Vec<String> = body.iter().map(|line| String::from(*line)).collect();
+        let (lines, fields) = expand_snippet(&body, b"    ", b"  ");
+        let lines = lines.iter().map(|line| String::from_utf8_lossy(line).into_owned()).collect();
+        (lines, fields.iter().map(|f| (f.number, f.row, f.start, f.end)).collect())
+    }
+
+    #[test]
+    fn expands_placeholders_with_their_defaults() {
+        let (lines, fields) = expand(&["fn ${1:name}(${2}) -> ${3:()} {", "\t$0", "}"]);
+        assert_eq!(lines, vec!["fn name() -> () {", "      ", "    }"]);
+        assert_eq!(fields, vec![(1, 0, 3, 7), (2, 0, 8, 8), (3, 0, 13, 15), (0, 1, 6, 6)]);
+    }
+
+    #[test]
+    fn fills_mirrors_from_the_first_placeholder() {
+        let (lines, fields) = expand(&["${1:T} x = new $1(); $1 y; ${2:a}$2"]);
+        assert_eq!(lines, vec!["T x = new T(); T y; aa"]);
+        assert_eq!(fields, vec![(1, 0, 0, 1), (1, 0, 10, 11), (1, 0, 15, 16), (2, 0, 20, 21), (2, 0, 21, 22),
+            (0, 0, 22, 22)]);
+    }
+
+    #[test]
+    fn keeps_escaped_and_unclosed_dollars() {
+        let (lines, fields) = expand(&["cost: \\$5 ${1", "$x"]);
+        assert_eq!(lines, vec!["cost: $5 ${1", "    $x"]);
+        assert_eq!(fields, vec![(0, 1, 6, 6)]);
+        assert_eq!(expand(&[]).0, vec![""]);
+    }
+
+    #[test]
+    fn visits_placeholders_in_number_order_and_zero_last() {
+        let (_, fields) = expand_snippet(&[String::from("$0 $2 $1 $2")], b"", b"");
+        let mut session = SnippetSession::new(fields);
+        let stops: Vec<u32> = session.stops.iter().map(|idx| session.fields[*idx].number).collect();
+        assert_eq!(stops, vec![1, 2, 0]);
+        assert_eq!(session.active().start, 2);
+        session.shift(0, 2, 3, Some(session.stops[0]));
+        assert_eq!(session.fields.iter().map(|f| (f.start, f.end)).collect::<Vec<_>>(),
+            vec![(0, 0), (1, 1), (2, 5), (6, 6)]);
+        session.current = 2;
+        assert!(session.at_end());
+    }
+
+    #[test]
+    fn reads_snippet_files() {
+        let mut snippets = Vec::new();
+        let mut errors = Vec::new();
+        let text = "# comment\nsnippet fn\n\tfn $1() {\n\t}\n\tstray\nsnippet fn\n\tfn again\nbad line\n";
+        parse_snippets("\tbody first\n", "x/rust.snippets", &mut snippets, &mut errors);
+        parse_snippets(text, "x/rust.snippets", &mut snippets, &mut errors);
+        assert_eq!(snippets.len(), 1);
+        assert_eq!((snippets[0].file_type.as_str(), snippets[0].body.clone()), ("rust", vec![String::from("fn again")]));
+        assert_eq!(errors, vec!["x/rust.snippets:1: body line outside a snippet", "x/rust.snippets:8: expected 'snippet <trigger>'"]);
+    }
+}
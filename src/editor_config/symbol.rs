@@ -0,0 +1,120 @@
+use super::{EditorSyntax, Erow, Highlight, Language};
+use super::brackets::is_text;
+use super::completion::fuzzy_match;
+use super::erow::{is_ident_start, is_ident_char};
+use super::regex::Regex;
+
+// Rust items found by their keyword; the name is the identifier after it.
+const RUST_ITEMS: [&str; 7] = ["fn", "struct", "enum", "union", "trait", "mod", "impl"];
+
+// An item of a buffer, from its first row to its last one, inside `depth`
+// other items.
+#[derive(Clone)]
+pub struct Symbol {
+    pub kind: String,
+    pub name: String,
+    pub row: usize,
+    pub end: usize,
+    pub depth: usize,
+}
+
+impl Symbol {
+    pub fn label(&self) -> String {
+        format!("{} {}", self.kind, self.name)
+    }
+}
+
+// The kind and name of the Rust item starting on a row, looked for in the
+// code outside strings and comments.
+fn rust_symbol(render: &[u8], hl: &[Highlight]) -> Option<(String, String)> {
+    let mut idx = 0;
+    while idx < render.len() {
+        if !is_ident_start(render[idx]) || (idx > 0 && is_ident_char(render[idx - 1])) || is_text(hl.get(idx)) {
+            idx += 1;
+            continue;
+        }
+        let start = idx;
+        while idx < render.len() && is_ident_char(render[idx]) {
+            idx += 1;
+        }
+        let kind = match RUST_ITEMS.iter().find(|k| k.as_bytes() == &render[start..idx]) {
+            Some(kind) => *kind,
+            None => continue,
+        };
+        let name = if kind == "impl" { impl_name(&render[idx..]) } else { ident_after(&render[idx..]) };
+        if let Some(name) = name {
+            return Some((String::from(kind), name));
+        }
+    }
+    None
+}
+
+fn ident_after(text: &[u8]) -> Option<String> {
+    let start = text.iter().take_while(|c| c.is_ascii_whitespace()).count();
+    if start == 0 || !text.get(start).map(|c| is_ident_start(*c)).unwrap_or(false) {
+        return None;
+    }
+    let len = text[start..].iter().take_while(|c| is_ident_char(**c)).count();
+    Some(String::from_utf8_lossy(&text[start..start + len]).into_owned())
+}
+
+// `impl<T> Display for Wrapper<T> {` is named `Display for Wrapper<T>`.
+fn impl_name(text: &[u8]) -> Option<String> {
+    let mut idx = 0;
+    if text.first() == Some(&b'<') {
+        let mut depth = 0;
+        while idx < text.len() {
+            match text[idx] {
+                b'<' => depth += 1,
+                b'>' => depth -= 1,
+                _ => (),
+            }
+            idx += 1;
+            if depth == 0 {
+                break;
+            }
+        }
+    }
+    let rest = String::from_utf8_lossy(&text[idx..]).into_owned();
+    let end = ["{", " where", "//"].iter().filter_map(|stop| rest.find(stop)).min().unwrap_or(rest.len());
+    let name = rest[..end].split_whitespace().collect::<Vec<&str>>().join(" ");
+    if name.is_empty() { None } else { Some(name) }
+}
+
+// The kind and name of the item starting on a highlighted row.
+pub fn row_symbol(syntax: &EditorSyntax, erow: &Erow) -> Option<(String, String)> {
+    if !syntax.symbol_rules.is_empty() {
+        rule_symbol(&syntax.symbol_rules, &erow.chars[..erow.size as usize])
+    }else if syntax.parser == Some(Language::Rust) {
+        rust_symbol(&erow.render[..erow._rsize as usize], &erow.hl)
+    }else{
+        None
+    }
+}
+
+// The first rule matching a row gives its kind; the name is the first group
+// of the match, or the whole match without one.
+pub fn rule_symbol(rules: &[(String, Regex)], text: &[u8]) -> Option<(String, String)> {
+    rules.iter().find_map(|(kind, regex)| {
+        let caps = regex.captures(text)?;
+        let (start, end) = caps.get(1).copied().flatten().or(caps[0])?;
+        let name = String::from_utf8_lossy(&text[start..end]).trim().to_string();
+        if name.is_empty() { None } else { Some((kind.clone(), name)) }
+    })
+}
+
+// The symbols whose rows hold `row`, outermost first.
+pub fn enclosing_symbols(symbols: &[Symbol], row: usize) -> Vec<&Symbol> {
+    symbols.iter().filter(|s| s.row <= row && row <= s.end).collect()
+}
+
+// The indexes of the symbols whose names hold `query`, then of those holding
+// its characters in order, ignoring case.
+pub fn filter_symbols(symbols: &[Symbol], query: &str) -> Vec<usize> {
+    let query = query.to_ascii_lowercase();
+    let (mut found, fuzzy): (Vec<usize>, Vec<usize>) = (0..symbols.len())
+        .filter(|idx| fuzzy_match(query.as_bytes(), symbols[*idx].name.as_bytes()))
+        .partition(|idx| symbols[*idx].name.to_ascii_lowercase().contains(&query));
+    found.extend(fuzzy);
+    found
+}
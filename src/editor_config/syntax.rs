@@ -0,0 +1,542 @@
+use super::Highlight;
+use super::settings::{user_config_dir, project_dirs};
+use super::glob::glob_match;
+use super::erow::{is_ident_start, is_ident_char};
+use super::parser::{Language, parse_language};
+use super::regex::Regex;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use bitflags::bitflags;
+
+pub const RILO_SYNTAX_DIR: &str = "syntax";
+pub const RILO_SYNTAX_SUFFIX: &str = ".syntax";
+
+const BUILTIN_SYNTAX: [(&str, &str); 11] = [
+    ("rust.syntax", include_str!("../../syntax/rust.syntax")),
+    ("toml.syntax", include_str!("../../syntax/toml.syntax")),
+    ("c.syntax", include_str!("../../syntax/c.syntax")),
+    ("python.syntax", include_str!("../../syntax/python.syntax")),
+    ("go.syntax", include_str!("../../syntax/go.syntax")),
+    ("sql.syntax", include_str!("../../syntax/sql.syntax")),
+    ("yaml.syntax", include_str!("../../syntax/yaml.syntax")),
+    ("shell.syntax", include_str!("../../syntax/shell.syntax")),
+    ("make.syntax", include_str!("../../syntax/make.syntax")),
+    ("dockerfile.syntax", include_str!("../../syntax/dockerfile.syntax")),
+    ("json.syntax", include_str!("../../syntax/json.syntax")),
+];
+
+// How many lines at the top and the bottom of a file are searched for a modeline.
+const MODELINE_LINES: usize = 5;
+
+bitflags! {
+    pub struct HLFlags: u32 {
+        const HLF_NUMBERS = 0b00000001;
+        const HLF_STRINGS = 0b00000010;
+        const HLF_IGNORE_CASE = 0b00000100;
+        const HLF_MULTILINE_STRINGS = 0b00001000;
+        const HLF_ESCAPES = 0b00010000;
+        const HLF_LIFETIMES = 0b00100000;
+        const HLF_FUNCTIONS = 0b01000000;
+        const HLF_MACROS = 0b10000000;
+        const HLF_CAPITALIZED_TYPES = 0b100000000;
+    }
+}
+
+bitflags! {
+    pub struct NumFlags: u32 {
+        const NUM_HEX = 0b00000001;
+        const NUM_OCTAL = 0b00000010;
+        const NUM_BINARY = 0b00000100;
+        const NUM_FLOAT = 0b00001000;
+        const NUM_UNDERSCORE = 0b00010000;
+        const NUM_SUFFIX = 0b00100000;
+    }
+}
+
+#[derive(Clone)]
+pub struct EditorSyntaxInf {
+    pub syntax: Option<EditorSyntax>,
+}
+
+// Keywords made of identifier characters are looked up by whole word; the few
+// others (`#include`, `.PHONY`) are tried in order at each word start.
+#[derive(Clone)]
+pub struct KeywordMatcher {
+    words: HashMap<Vec<u8>, Highlight>,
+    others: Vec<(Vec<u8>, Highlight)>,
+    ignore_case: bool,
+}
+
+impl KeywordMatcher {
+    fn new() -> KeywordMatcher {
+        KeywordMatcher { words: HashMap::new(), others: Vec::new(), ignore_case: false }
+    }
+
+    fn build(keywords: &[String], ignore_case: bool) -> KeywordMatcher {
+        let mut matcher = KeywordMatcher::new();
+        matcher.ignore_case = ignore_case;
+        for kwd in keywords.iter() {
+            let (word, hlk) = match kwd.strip_suffix('|') {
+                Some(stripped) => (stripped.as_bytes(), Highlight::KEYWORD2),
+                None => (kwd.as_bytes(), Highlight::KEYWORD1),
+            };
+            if word.is_empty() {
+                continue;
+            }
+            if is_ident_start(word[0]) && word.iter().all(|c| is_ident_char(*c)) {
+                let key = if ignore_case { word.to_ascii_lowercase() } else { word.to_vec() };
+                matcher.words.entry(key).or_insert(hlk);
+            }else{
+                matcher.others.push((word.to_vec(), hlk));
+            }
+        }
+        matcher
+    }
+
+    // Length and group of the keyword at the start of `text`, if any.
+    pub fn find(&self, text: &[u8]) -> Option<(usize, Highlight)> {
+        for (word, hlk) in self.others.iter() {
+            let end = word.len();
+            if end <= text.len() && self.same(&text[..end], word) &&
+                    (end == text.len() || !is_ident_char(text[end]) || !is_ident_char(text[end - 1])) {
+                return Some((end, hlk.clone()));
+            }
+        }
+        if text.is_empty() || !is_ident_start(text[0]) || self.words.is_empty() {
+            return None;
+        }
+        let end = text.iter().position(|c| !is_ident_char(*c)).unwrap_or(text.len());
+        let hlk = if self.ignore_case {
+            self.words.get(&text[..end].to_ascii_lowercase())
+        }else{
+            self.words.get(&text[..end])
+        };
+        hlk.map(|h| (end, h.clone()))
+    }
+
+    fn same(&self, text: &[u8], word: &[u8]) -> bool {
+        if self.ignore_case {
+            text.eq_ignore_ascii_case(word)
+        }else{
+            text == word
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EditorSyntax {
+    pub file_type: String,
+    aliases: Vec<String>,
+    file_extensions: Vec<String>,
+    filenames: Vec<String>,
+    shebangs: Vec<String>,
+    keywords: Vec<String>,
+    pub keyword_matcher: KeywordMatcher,
+    pub singleline_comment_start: String,
+    pub multiline_comment_start: String,
+    pub multiline_comment_end: String,
+    pub doc_comment_start: Vec<String>,
+    pub string_delimiters: Vec<u8>,
+    pub char_delimiter: u8,
+    pub raw_string_prefixes: Vec<String>,
+    pub attribute_starts: Vec<String>,
+    pub function_keywords: Vec<String>,
+    pub operators: Vec<u8>,
+    pub indent_after: Vec<u8>,
+    pub dedent_on: Vec<u8>,
+    pub flags: HLFlags,
+    pub number_flags: NumFlags,
+    pub parser: Option<Language>,
+    pub symbol_rules: Vec<(String, Regex)>,
+}
+
+impl EditorSyntax {
+    fn new() -> EditorSyntax {
+        EditorSyntax{
+            file_type: String::new(),
+            aliases: Vec::new(),
+            file_extensions: Vec::new(),
+            filenames: Vec::new(),
+            shebangs: Vec::new(),
+            keywords: Vec::new(),
+            keyword_matcher: KeywordMatcher::new(),
+            singleline_comment_start: String::new(),
+            multiline_comment_start: String::new(),
+            multiline_comment_end: String::new(),
+            doc_comment_start: Vec::new(),
+            string_delimiters: Vec::new(),
+            char_delimiter: 0,
+            raw_string_prefixes: Vec::new(),
+            attribute_starts: Vec::new(),
+            function_keywords: Vec::new(),
+            operators: Vec::new(),
+            indent_after: Vec::new(),
+            dedent_on: Vec::new(),
+            flags: HLFlags::empty(),
+            number_flags: NumFlags::empty(),
+            parser: None,
+            symbol_rules: Vec::new(),
+        }
+    }
+
+    // Parses a syntax definition made of `key = value` lines. Bad entries are
+    // reported to `errors` and skipped; a definition without a name is dropped.
+    pub fn from_definition(text: &str, origin: &str, errors: &mut Vec<String>) -> Option<EditorSyntax> {
+        let mut syntax = EditorSyntax::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let result = match line.find('=') {
+                Some(pos) => syntax.set(line[..pos].trim(), line[pos + 1..].trim()),
+                None => Err(format!("expected 'key = value', found '{}'", line)),
+            };
+            if let Err(msg) = result {
+                errors.push(format!("{}:{}: {}", origin, idx + 1, msg));
+            }
+        }
+        if syntax.file_type.is_empty() {
+            errors.push(format!("{}: missing 'name'", origin));
+            return None;
+        }
+        syntax.keyword_matcher = KeywordMatcher::build(&syntax.keywords,
+            syntax.flags.contains(HLFlags::HLF_IGNORE_CASE));
+        Some(syntax)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let words = value.split_whitespace();
+        match key {
+            "name" => self.file_type = String::from(value),
+            "parser" => self.parser = Some(parse_language(value)?),
+            "aliases" => self.aliases.extend(words.map(String::from)),
+            "shebangs" => self.shebangs.extend(words.map(String::from)),
+            "extensions" => {
+                for w in words {
+                    self.file_extensions.push(String::from(w.trim_start_matches('.')));
+                }
+            },
+            "filenames" => self.filenames.extend(words.map(String::from)),
+            "keywords" => self.keywords.extend(words.map(String::from)),
+            "keywords2" => self.keywords.extend(words.map(|w| format!("{}|", w))),
+            "comment" => self.singleline_comment_start = String::from(value),
+            "block_comment" => {
+                let markers: Vec<&str> = words.collect();
+                if markers.len() != 2 {
+                    return Err(format!("expected 'block_comment = <start> <end>', found '{}'", value));
+                }
+                self.multiline_comment_start = String::from(markers[0]);
+                self.multiline_comment_end = String::from(markers[1]);
+            },
+            "symbol" => {
+                let (kind, pattern) = match value.find(char::is_whitespace) {
+                    Some(pos) => (&value[..pos], value[pos..].trim()),
+                    None => return Err(format!("expected 'symbol = <kind> <regex>', found '{}'", value)),
+                };
+                self.symbol_rules.push((String::from(kind), Regex::new(pattern)?));
+            },
+            "doc_comment" => self.doc_comment_start.extend(words.map(String::from)),
+            "chars" => {
+                if value.len() != 1 {
+                    return Err(format!("char delimiter '{}' must be a single character", value));
+                }
+                self.char_delimiter = value.as_bytes()[0];
+            },
+            "raw_strings" => self.raw_string_prefixes.extend(words.map(String::from)),
+            "attributes" => self.attribute_starts.extend(words.map(String::from)),
+            "function_keywords" => self.function_keywords.extend(words.map(String::from)),
+            "operators" => self.operators.extend(value.bytes().filter(|c| !c.is_ascii_whitespace())),
+            "indent_after" => self.indent_after.extend(value.bytes().filter(|c| !c.is_ascii_whitespace())),
+            "dedent_on" => self.dedent_on.extend(value.bytes().filter(|c| !c.is_ascii_whitespace())),
+            "strings" => {
+                for w in words {
+                    if w.len() != 1 {
+                        return Err(format!("string delimiter '{}' must be a single character", w));
+                    }
+                    self.string_delimiters.push(w.as_bytes()[0]);
+                }
+                self.flags |= HLFlags::HLF_STRINGS;
+            },
+            "flags" => {
+                for w in words {
+                    self.flags |= match w {
+                        "numbers" => HLFlags::HLF_NUMBERS,
+                        "strings" => HLFlags::HLF_STRINGS,
+                        "ignore_case" => HLFlags::HLF_IGNORE_CASE,
+                        "multiline_strings" => HLFlags::HLF_MULTILINE_STRINGS,
+                        "escapes" => HLFlags::HLF_ESCAPES,
+                        "lifetimes" => HLFlags::HLF_LIFETIMES,
+                        "functions" => HLFlags::HLF_FUNCTIONS,
+                        "macros" => HLFlags::HLF_MACROS,
+                        "capitalized_types" => HLFlags::HLF_CAPITALIZED_TYPES,
+                        _ => return Err(format!("unknown flag '{}' (expected {})", w,
+                            "numbers, strings, ignore_case, multiline_strings, escapes, lifetimes, \
+                            functions, macros or capitalized_types")),
+                    };
+                }
+            },
+            "number_rules" => {
+                for w in words {
+                    self.number_flags |= match w {
+                        "hex" => NumFlags::NUM_HEX,
+                        "octal" => NumFlags::NUM_OCTAL,
+                        "binary" => NumFlags::NUM_BINARY,
+                        "float" => NumFlags::NUM_FLOAT,
+                        "underscore" => NumFlags::NUM_UNDERSCORE,
+                        "suffix" => NumFlags::NUM_SUFFIX,
+                        _ => return Err(format!(
+                            "unknown number rule '{}' (expected hex, octal, binary, float, underscore or suffix)",
+                            w)),
+                    };
+                }
+                self.flags |= HLFlags::HLF_NUMBERS;
+            },
+            _ => return Err(format!("unknown key '{}'", key)),
+        }
+        Ok(())
+    }
+
+    pub fn much_type(&self, filename: &[u8]) -> bool {
+        let str_fname = String::from_utf8_lossy(filename);
+        let basename = match str_fname.rfind('/') {
+            Some(pos) => &str_fname[pos + 1..],
+            None => &str_fname[..],
+        };
+        for glob in self.filenames.iter() {
+            if glob_match(glob, basename) {
+                return true
+            }
+        }
+        let work_vec: Vec<&str> = basename.split('.').collect();
+        if work_vec.len() == 1 { return false };
+        let ext: &str = work_vec[work_vec.len() - 1];
+        self.file_extensions.iter().any(|syntax_ext| syntax_ext == ext)
+    }
+
+    pub fn much_name(&self, name: &str) -> bool {
+        self.file_type.eq_ignore_ascii_case(name) ||
+            self.aliases.iter().any(|a| a.eq_ignore_ascii_case(name))
+    }
+
+    // `python3.11` also matches a definition listing `python3` or `python`.
+    pub fn much_shebang(&self, interpreter: &str) -> bool {
+        let mut name = interpreter;
+        loop {
+            if self.shebangs.iter().any(|s| s == name) {
+                return true;
+            }
+            let trimmed = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+            if trimmed == name || trimmed.is_empty() {
+                return false;
+            }
+            name = trimmed;
+        }
+    }
+}
+
+// The interpreter named by a `#!` line, looking through `/usr/bin/env [-S]`.
+pub fn shebang_interpreter(line: &[u8]) -> Option<String> {
+    let line = String::from_utf8_lossy(line);
+    let rest = line.strip_prefix("#!")?;
+    let mut words = rest.split_whitespace();
+    let mut program = words.next()?;
+    if program.rsplit('/').next() == Some("env") {
+        program = words.find(|w| !w.starts_with('-') && !w.contains('='))?;
+    }
+    program.rsplit('/').next().map(String::from)
+}
+
+// File type set by a vim (`vim: set ft=python :`) or emacs (`-*- mode: python -*-`)
+// modeline near the top or the bottom of the file.
+pub fn modeline_file_type(rows: &[&[u8]]) -> Option<String> {
+    let count = rows.len();
+    for (idx, row) in rows.iter().enumerate() {
+        if idx >= MODELINE_LINES && idx + MODELINE_LINES < count {
+            continue;
+        }
+        let line = String::from_utf8_lossy(row);
+        if idx < 2 {
+            if let Some(ft) = emacs_modeline(&line) {
+                return Some(ft);
+            }
+        }
+        if let Some(ft) = vim_modeline(&line) {
+            return Some(ft);
+        }
+    }
+    None
+}
+
+fn vim_modeline(line: &str) -> Option<String> {
+    let mut rest: Option<&str> = None;
+    for marker in ["vim:", "vi:", "ex:"].iter() {
+        if let Some(pos) = line.find(marker) {
+            if pos == 0 || line.as_bytes()[pos - 1].is_ascii_whitespace() {
+                rest = Some(&line[pos + marker.len()..]);
+                break;
+            }
+        }
+    }
+    let rest = rest?;
+    let rest = rest.trim_start().strip_prefix("set ").unwrap_or(rest);
+    for option in rest.split(|c: char| c == ':' || c.is_ascii_whitespace()) {
+        for key in ["ft=", "filetype=", "syn=", "syntax="].iter() {
+            if let Some(value) = option.strip_prefix(key) {
+                if !value.is_empty() {
+                    return Some(String::from(value));
+                }
+            }
+        }
+    }
+    None
+}
+
+fn emacs_modeline(line: &str) -> Option<String> {
+    let start = line.find("-*-")? + 3;
+    let end = start + line[start..].find("-*-")?;
+    let vars = line[start..end].trim();
+    if !vars.contains(':') {
+        return if vars.is_empty() { None } else { Some(String::from(vars)) };
+    }
+    for var in vars.split(';') {
+        if let Some(pos) = var.find(':') {
+            if var[..pos].trim().eq_ignore_ascii_case("mode") {
+                return Some(String::from(var[pos + 1..].trim()));
+            }
+        }
+    }
+    None
+}
+
+// Built-in definitions first, then the user's and the projects' syntax directories.
+// A later definition replaces an earlier one with the same name.
+pub fn load_syntax_definitions(dir: Option<&Path>, errors: &mut Vec<String>) -> Vec<EditorSyntax> {
+    let mut syntaxes: Vec<EditorSyntax> = Vec::new();
+    for (origin, text) in BUILTIN_SYNTAX.iter() {
+        if let Some(syntax) = EditorSyntax::from_definition(text, origin, errors) {
+            add_syntax(&mut syntaxes, syntax);
+        }
+    }
+    if let Some(path) = user_config_dir() {
+        load_syntax_dir(&path.join(RILO_SYNTAX_DIR), &mut syntaxes, errors);
+    }
+    if let Some(dir) = dir {
+        for path in project_dirs(dir) {
+            load_syntax_dir(&path.join(RILO_SYNTAX_DIR), &mut syntaxes, errors);
+        }
+    }
+    syntaxes
+}
+
+fn load_syntax_dir(dir: &Path, syntaxes: &mut Vec<EditorSyntax>, errors: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let mut paths: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path())
+        .filter(|p| p.to_string_lossy().ends_with(RILO_SYNTAX_SUFFIX))
+        .collect();
+    paths.sort();
+    for path in paths {
+        let origin = path.display().to_string();
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                if let Some(syntax) = EditorSyntax::from_definition(&text, &origin, errors) {
+                    add_syntax(syntaxes, syntax);
+                }
+            },
+            Err(e) => errors.push(format!("{}: {}", origin, e)),
+        }
+    }
+}
+
+fn add_syntax(syntaxes: &mut Vec<EditorSyntax>, syntax: EditorSyntax) {
+    match syntaxes.iter().position(|s| s.file_type == syntax.file_type) {
+        Some(pos) => syntaxes[pos] = syntax,
+        None => syntaxes.push(syntax),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::symbol::rule_symbol;
+
+    fn builtin(file_type: &str) -> EditorSyntax {
+        let mut errors = Vec::new();
+        let syntax = BUILTIN_SYNTAX.iter()
+            .filter_map(|(origin, text)| EditorSyntax::from_definition(text, origin, &mut errors))
+            .find(|syntax| syntax.file_type == file_type)
+            .unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        syntax
+    }
+
+    fn symbol(file_type: &str, line: &str) -> Option<(String, String)> {
+        rule_symbol(&builtin(file_type).symbol_rules, line.as_bytes())
+    }
+
+    fn found(kind: &str, name: &str) -> Option<(String, String)> {
+        Some((String::from(kind), String::from(name)))
+    }
+
+    #[test]
+    fn finds_c_symbols() {
+        assert_eq!(symbol("c", "static int *parse_args(int argc, char **argv)"), found("function", "parse_args"));
+        assert_eq!(symbol("c", "void\tmain(void) {"), found("function", "main"));
+        assert_eq!(symbol("c", "int f(int x);"), None);
+        assert_eq!(symbol("c", "    return f(x)"), None);
+        assert_eq!(symbol("c", "typedef struct point {"), found("struct", "point"));
+        assert_eq!(symbol("c", "struct point p;"), None);
+        assert_eq!(symbol("c", "enum color {"), found("enum", "color"));
+        assert_eq!(symbol("c", "union value {"), found("union", "value"));
+        assert_eq!(symbol("c", "#define MAX(a, b) ((a) > (b) ? (a) : (b))"), found("define", "MAX"));
+        assert_eq!(symbol("c", "# define DEBUG"), found("define", "DEBUG"));
+    }
+
+    #[test]
+    fn finds_go_symbols() {
+        assert_eq!(symbol("go", "func main() {"), found("func", "main"));
+        assert_eq!(symbol("go", "func (s *Server) Serve(l net.Listener) error {"), found("func", "Serve"));
+        assert_eq!(symbol("go", "type Server struct {"), found("type", "Server"));
+        assert_eq!(symbol("go", "\tf := func() {}"), None);
+    }
+
+    #[test]
+    fn finds_python_symbols() {
+        assert_eq!(symbol("python", "class Parser(Base):"), found("class", "Parser"));
+        assert_eq!(symbol("python", "    def parse(self, text):"), found("def", "parse"));
+        assert_eq!(symbol("python", "async def fetch(url):"), found("def", "fetch"));
+        assert_eq!(symbol("python", "    undefined = 1"), None);
+    }
+
+    #[test]
+    fn finds_shell_symbols() {
+        assert_eq!(symbol("shell", "function build_all {"), found("function", "build_all"));
+        assert_eq!(symbol("shell", "clean-up() {"), found("function", "clean-up"));
+        assert_eq!(symbol("shell", "  run_tests () {"), found("function", "run_tests"));
+        assert_eq!(symbol("shell", "echo hello"), None);
+    }
+
+    #[test]
+    fn finds_make_targets() {
+        assert_eq!(symbol("make", "all: build test"), found("target", "all"));
+        assert_eq!(symbol("make", "$(BUILD)/%.o: %.c"), found("target", "$(BUILD)/%.o"));
+        assert_eq!(symbol("make", "install:"), found("target", "install"));
+        assert_eq!(symbol("make", "CC := gcc"), None);
+        assert_eq!(symbol("make", "\tcc -o $@ $^"), None);
+    }
+
+    #[test]
+    fn finds_symbols_in_long_lines() {
+        let line = format!("int x{}", " a".repeat(10000));
+        assert_eq!(symbol("c", &line), None);
+        let line = format!("static {}handler(int signal) {{", "volatile ".repeat(2000));
+        assert_eq!(symbol("c", &line), found("function", "handler"));
+        let line = format!("def f({}):", "a, ".repeat(10000));
+        assert_eq!(symbol("python", &line), found("def", "f"));
+    }
+}
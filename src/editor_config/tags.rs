@@ -0,0 +1,225 @@
+use super::erow::is_ident_char;
+use super::panel::ListItem;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Names of tags files looked for, ctags first, then etags.
+const TAGS_FILES: [&str; 2] = ["tags", "TAGS"];
+
+// A definition listed in a tags file: its file and line, or the text of the
+// line when the tags file gives a search pattern.
+pub struct Tag {
+    pub file: PathBuf,
+    pub line: u32,
+    pub pattern: Option<(String, bool)>,
+}
+
+// The nearest tags file in `dir` or a directory above it.
+pub fn find_tags_file(dir: &Path) -> Option<PathBuf> {
+    for dir in dir.ancestors() {
+        for name in TAGS_FILES.iter() {
+            let path = dir.join(name);
+            if path.is_file() {
+                return Some(path);
+            }
+        }
+    }
+    None
+}
+
+// The tags named `name` in a ctags or etags file. Their files are relative
+// to the directory of the tags file.
+pub fn read_tags(path: &Path, name: &str) -> Result<Vec<Tag>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let text = String::from_utf8_lossy(&bytes);
+    let base = path.parent().unwrap_or_else(|| Path::new("."));
+    if text.starts_with('\x0c') {
+        return Ok(parse_etags(&text, base, name));
+    }
+    let prefix = format!("{}\t", name);
+    Ok(text.lines()
+        .filter(|line| line.starts_with(&prefix))
+        .filter_map(|line| parse_ctags_line(&line[prefix.len()..], base))
+        .collect())
+}
+
+// `file<TAB>address;"<TAB>fields` where the address is a line number or a
+// `/^text$/` or `?^text$?` search pattern.
+fn parse_ctags_line(rest: &str, base: &Path) -> Option<Tag> {
+    let (file, address) = rest.split_at(rest.find('\t')?);
+    let address = &address[1..];
+    let mut tag = Tag { file: base.join(file), line: 0, pattern: None };
+    let fields = match address.chars().next()? {
+        delimiter @ '/' | delimiter @ '?' => {
+            let mut text = String::new();
+            let mut chars = address[1..].char_indices();
+            let mut end = address.len();
+            while let Some((idx, c)) = chars.next() {
+                if c == '\\' {
+                    if let Some((_, escaped)) = chars.next() {
+                        text.push(escaped);
+                    }
+                }else if c == delimiter {
+                    end = idx + 2;
+                    break;
+                }else{
+                    text.push(c);
+                }
+            }
+            let anchored = text.ends_with('$');
+            if anchored {
+                text.pop();
+            }
+            let text = text.strip_prefix('^').map(String::from).unwrap_or(text);
+            tag.pattern = Some((text, anchored));
+            &address[end.min(address.len())..]
+        },
+        _ => {
+            let digits = address.chars().take_while(|c| c.is_ascii_digit()).count();
+            tag.line = address[..digits].parse().ok()?;
+            &address[digits..]
+        },
+    };
+    for field in fields.split('\t') {
+        if let Some(line) = field.strip_prefix("line:") {
+            tag.line = line.parse().unwrap_or(tag.line);
+        }
+    }
+    Some(tag)
+}
+
+// Sections of a form feed line, a `file,size` line and `text<DEL>name<SOH>line,offset`
+// lines, the name being the last word of the text when it is left out.
+fn parse_etags(text: &str, base: &Path, name: &str) -> Vec<Tag> {
+    let mut tags = Vec::new();
+    let mut file: Option<PathBuf> = None;
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        if line.starts_with('\x0c') {
+            file = lines.next()
+                .and_then(|header| header.rsplit_once(','))
+                .map(|(path, _)| base.join(path));
+            continue;
+        }
+        let (file, (text, position)) = match (&file, line.split_once('\x7f')) {
+            (Some(file), Some(parts)) => (file, parts),
+            _ => continue,
+        };
+        let (tag_name, position) = match position.split_once('\x01') {
+            Some((tag_name, position)) => (tag_name, position),
+            None => {
+                let trimmed = text.trim_end_matches(|c: char| !is_ident_char(c as u8) || !c.is_ascii());
+                let start = trimmed.rfind(|c: char| !is_ident_char(c as u8) || !c.is_ascii()).map(|i| i + 1).unwrap_or(0);
+                (&trimmed[start..], position)
+            },
+        };
+        if tag_name != name {
+            continue;
+        }
+        let line = position.split(',').next().and_then(|n| n.parse().ok()).unwrap_or(0);
+        tags.push(Tag { file: file.clone(), line, pattern: Some((String::from(text), false)) });
+    }
+    tags
+}
+
+// Where the tags are, found by reading their files: the line matching the
+// pattern, or else the line number, and the column of `name` on it.
+pub fn tag_locations(tags: &[Tag], name: &str) -> Vec<ListItem> {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let mut items: Vec<ListItem> = Vec::new();
+    for tag in tags {
+        let bytes = match fs::read(&tag.file) {
+            Ok(bytes) => bytes,
+            Err(_) => continue,
+        };
+        let text = String::from_utf8_lossy(&bytes);
+        let rows: Vec<&str> = text.lines().collect();
+        let found = tag.pattern.as_ref().and_then(|(pattern, anchored)| {
+            let matches = |row: &&str| if *anchored { *row == pattern.as_str() } else { row.starts_with(pattern.as_str()) };
+            // The line number, when known, picks among equal lines.
+            let near = tag.line.saturating_sub(1) as usize;
+            rows.iter().skip(near).position(&matches).map(|idx| idx + near)
+                .or_else(|| rows.iter().position(matches))
+        });
+        let line = match found {
+            Some(idx) => idx,
+            None if tag.line > 0 => tag.line as usize - 1,
+            None => continue,
+        };
+        let row = rows.get(line).copied().unwrap_or("");
+        let col = row.find(name).unwrap_or_else(|| row.len() - row.trim_start().len());
+        let file = tag.file.strip_prefix(&cwd).unwrap_or(&tag.file).to_string_lossy().into_owned();
+        let item = ListItem { file, line: line as u32 + 1, col: col as u32 + 1, text: row.trim().to_string(), span: None };
+        if !items.iter().any(|i| i.file == item.file && i.line == item.line) {
+            items.push(item);
+        }
+    }
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(tag: &Tag) -> (String, u32, Option<(String, bool)>) {
+        (tag.file.to_string_lossy().into_owned(), tag.line, tag.pattern.clone())
+    }
+
+    fn pattern(text: &str, anchored: bool) -> Option<(String, bool)> {
+        Some((String::from(text), anchored))
+    }
+
+    #[test]
+    fn parses_ctags_addresses() {
+        let base = Path::new("/p");
+        for (rest, expected) in [
+            ("src/a.rs\t12;\"\tf", ("/p/src/a.rs", 12, None)),
+            ("a.c\t/^int main(void)$/;\"\tf\tline:40", ("/p/a.c", 40, pattern("int main(void)", true))),
+            ("a.c\t?^static int x?;\"\tv", ("/p/a.c", 0, pattern("static int x", false))),
+            ("a.c\t/^a\\/b \\$x$/", ("/p/a.c", 0, pattern("a/b $x", true))),
+        ].iter() {
+            let parsed = parse_ctags_line(rest, base).map(|t| tag(&t));
+            let (file, line, pattern) = expected.clone();
+            assert_eq!(parsed, Some((String::from(file), line, pattern)), "{}", rest);
+        }
+        assert!(parse_ctags_line("no tab here", base).is_none());
+        assert!(parse_ctags_line("a.c\tx;\"", base).is_none());
+    }
+
+    #[test]
+    fn parses_etags_sections() {
+        let text = "\x0c\nsrc/a.c,60\nint main(void) {\x7fmain\x013,20\nstatic int count;\x7f5,40\n\
+            \x0c\nb.c,30\nvoid helper(int x)\x7fhelper\x0110,99\n";
+        let tags: Vec<_> = parse_etags(text, Path::new("/p"), "main").iter().map(tag).collect();
+        assert_eq!(tags, vec![(String::from("/p/src/a.c"), 3, pattern("int main(void) {", false))]);
+        let tags: Vec<_> = parse_etags(text, Path::new("/p"), "count").iter().map(tag).collect();
+        assert_eq!(tags, vec![(String::from("/p/src/a.c"), 5, pattern("static int count;", false))]);
+        assert_eq!(parse_etags(text, Path::new("/p"), "helper").len(), 1);
+    }
+
+    #[test]
+    fn finds_tags_in_their_files() {
+        let dir = std::env::temp_dir().join(format!("rilo-tags-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.c"), "int x;\nint main(void)\nint main(void)\n  int y;\n").unwrap();
+        fs::write(dir.join("tags"), "main\ta.c\t/^int main(void)$/;\"\tline:3\n\
+            main\ta.c\t/^int main(void)$/;\"\n\
+            y\ta.c\t4;\"\n\
+            gone\tmissing.c\t1;\"\n").unwrap();
+        let tags_file = find_tags_file(&dir.join("sub"));
+        assert_eq!(tags_file, Some(dir.join("tags")));
+        let locations = |name: &str| -> Vec<(u32, u32, String)> {
+            let tags = read_tags(tags_file.as_ref().unwrap(), name).unwrap();
+            tag_locations(&tags, name).into_iter().map(|item| (item.line, item.col, item.text)).collect()
+        };
+        let main = locations("main");
+        let y = locations("y");
+        let gone = locations("gone");
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(main, vec![(3, 5, String::from("int main(void)")), (2, 5, String::from("int main(void)"))]);
+        assert_eq!(y, vec![(4, 7, String::from("int y;"))]);
+        assert!(gone.is_empty());
+    }
+}
@@ -0,0 +1,380 @@
+use super::Highlight;
+use super::settings::{user_config_dir, project_dirs};
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+pub const RILO_THEME_DIR: &str = "themes";
+pub const RILO_THEME_SUFFIX: &str = ".theme";
+pub const RILO_DEFAULT_THEME: &str = "default";
+
+const BUILTIN_THEMES: [(&str, &str); 4] = [
+    ("default", include_str!("../../themes/default.theme")),
+    ("monokai", include_str!("../../themes/monokai.theme")),
+    ("solarized-dark", include_str!("../../themes/solarized-dark.theme")),
+    ("solarized-light", include_str!("../../themes/solarized-light.theme")),
+];
+
+// Highlight groups a theme can style, each with the group it falls back to.
+pub const THEME_GROUPS: [(&str, &str); 27] = [
+    ("normal", ""),
+    ("comment", "normal"),
+    ("mlcomment", "comment"),
+    ("doc_comment", "comment"),
+    ("keyword1", "normal"),
+    ("keyword2", "normal"),
+    ("type", "keyword2"),
+    ("function", "normal"),
+    ("macro", "function"),
+    ("lifetime", "keyword2"),
+    ("attribute", "comment"),
+    ("operator", "normal"),
+    ("number", "normal"),
+    ("string", "normal"),
+    ("char", "string"),
+    ("escape", "string"),
+    ("match", "normal"),
+    ("bracket", "normal"),
+    ("unmatched_bracket", "normal"),
+    ("selection", "normal"),
+    ("long_line", "normal"),
+    ("popup", "normal"),
+    ("diagnostic_error", "normal"),
+    ("diagnostic_warning", "normal"),
+    ("fold", "comment"),
+    ("line_number", "normal"),
+    ("status_bar", ""),
+];
+
+// xterm's default palette for the 16 basic colors.
+const ANSI_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0),
+    (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+    (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0),
+    (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+];
+
+const COLOR_NAMES: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Color {
+    Default,
+    Ansi(u8),
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColorDepth {
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+#[derive(Clone)]
+pub struct Theme {
+    styles: Vec<(String, Style)>,
+}
+
+impl Style {
+    pub fn new() -> Style {
+        Style {
+            fg: Color::Default,
+            bg: Color::Default,
+            bold: false,
+            italic: false,
+            underline: false,
+            reverse: false,
+        }
+    }
+
+    // Parses `fg=<color> bg=<color> bold italic underline reverse`. A lone color
+    // is taken as the foreground, so `color.string = magenta` keeps working.
+    pub fn parse(value: &str) -> Result<Style, String> {
+        let mut style = Style::new();
+        for word in value.split_whitespace() {
+            match word {
+                "bold" => style.bold = true,
+                "italic" => style.italic = true,
+                "underline" => style.underline = true,
+                "reverse" => style.reverse = true,
+                _ => {
+                    if let Some(color) = word.strip_prefix("fg=") {
+                        style.fg = parse_color(color)?;
+                    }else if let Some(color) = word.strip_prefix("bg=") {
+                        style.bg = parse_color(color)?;
+                    }else{
+                        style.fg = parse_color(word)?;
+                    }
+                },
+            }
+        }
+        Ok(style)
+    }
+
+    // SGR sequence selecting this style from a reset state.
+    pub fn escape(&self, depth: ColorDepth) -> String {
+        let mut seq = String::from("\x1b[0");
+        if self.bold { seq.push_str(";1"); }
+        if self.italic { seq.push_str(";3"); }
+        if self.underline { seq.push_str(";4"); }
+        if self.reverse { seq.push_str(";7"); }
+        seq.push_str(&color_escape(self.fg, depth, false));
+        seq.push_str(&color_escape(self.bg, depth, true));
+        seq.push('m');
+        seq
+    }
+}
+
+impl Theme {
+    pub fn new() -> Theme {
+        Theme { styles: Vec::new() }
+    }
+
+    pub fn from_definition(text: &str, origin: &str, errors: &mut Vec<String>) -> Theme {
+        let mut theme = Theme::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let result = match line.find('=') {
+                Some(pos) => theme.set(line[..pos].trim(), line[pos + 1..].trim()),
+                None => Err(format!("expected 'group = style', found '{}'", line)),
+            };
+            if let Err(msg) = result {
+                errors.push(format!("{}:{}: {}", origin, idx + 1, msg));
+            }
+        }
+        theme
+    }
+
+    pub fn set(&mut self, group: &str, value: &str) -> Result<(), String> {
+        if !THEME_GROUPS.iter().any(|(g, _)| *g == group) {
+            return Err(format!("unknown highlight group '{}'", group));
+        }
+        let style = Style::parse(value).map_err(|e| format!("{} in '{}'", e, group))?;
+        self.set_style(group, style);
+        Ok(())
+    }
+
+    pub fn set_style(&mut self, group: &str, style: Style) {
+        match self.styles.iter().position(|(g, _)| g == group) {
+            Some(pos) => self.styles[pos].1 = style,
+            None => self.styles.push((String::from(group), style)),
+        }
+    }
+
+    pub fn merge(&mut self, other: &Theme) {
+        for (group, style) in other.styles.iter() {
+            self.set_style(group, *style);
+        }
+    }
+
+    // Colors a group leaves at `default` are inherited from the group it falls back to.
+    pub fn style(&self, group: &str) -> Style {
+        let parent = match THEME_GROUPS.iter().find(|(g, _)| *g == group) {
+            Some((_, parent)) => parent,
+            None => "normal",
+        };
+        let inherited = if parent.is_empty() { Style::new() } else { self.style(parent) };
+        match self.styles.iter().find(|(g, _)| g == group) {
+            Some((_, style)) => {
+                let mut style = *style;
+                if style.fg == Color::Default { style.fg = inherited.fg; }
+                if style.bg == Color::Default { style.bg = inherited.bg; }
+                style
+            },
+            None => inherited,
+        }
+    }
+
+    pub fn highlight_style(&self, hl: &Highlight) -> Style {
+        self.style(highlight_group(hl))
+    }
+}
+
+pub fn highlight_group(hl: &Highlight) -> &'static str {
+    match hl {
+        Highlight::COMMENT => "comment",
+        Highlight::MLCOMMENT => "mlcomment",
+        Highlight::KEYWORD1 => "keyword1",
+        Highlight::KEYWORD2 => "keyword2",
+        Highlight::NUMBER => "number",
+        Highlight::STRING => "string",
+        Highlight::MATCH => "match",
+        Highlight::TYPE => "type",
+        Highlight::FUNCTION => "function",
+        Highlight::MACRO => "macro",
+        Highlight::LIFETIME => "lifetime",
+        Highlight::ATTRIBUTE => "attribute",
+        Highlight::CHAR => "char",
+        Highlight::ESCAPE => "escape",
+        Highlight::DOCCOMMENT => "doc_comment",
+        Highlight::OPERATOR => "operator",
+        Highlight::BRACKET => "bracket",
+        Highlight::UNMATCHED => "unmatched_bracket",
+        _ => "normal",
+    }
+}
+
+// Looks for `<name>.theme` in the projects' and the user's theme directories
+// before the built-in themes. Unknown names fall back to the default theme.
+pub fn load_theme(name: &str, dir: Option<&Path>, errors: &mut Vec<String>) -> Theme {
+    let file = format!("{}{}", name, RILO_THEME_SUFFIX);
+    let mut candidates = Vec::new();
+    if let Some(dir) = dir {
+        for path in project_dirs(dir).iter().rev() {
+            candidates.push(path.join(RILO_THEME_DIR).join(&file));
+        }
+    }
+    if let Some(path) = user_config_dir() {
+        candidates.push(path.join(RILO_THEME_DIR).join(&file));
+    }
+    for path in candidates {
+        if path.is_file() {
+            let origin = path.display().to_string();
+            match fs::read_to_string(&path) {
+                Ok(text) => return Theme::from_definition(&text, &origin, errors),
+                Err(e) => errors.push(format!("{}: {}", origin, e)),
+            }
+        }
+    }
+    if let Some((_, text)) = BUILTIN_THEMES.iter().find(|(n, _)| *n == name) {
+        return Theme::from_definition(text, name, errors);
+    }
+    errors.push(format!("unknown theme '{}'", name));
+    let (_, text) = BUILTIN_THEMES[0];
+    Theme::from_definition(text, RILO_DEFAULT_THEME, errors)
+}
+
+pub fn detect_color_depth() -> ColorDepth {
+    let colorterm = env::var("COLORTERM").unwrap_or_default().to_lowercase();
+    if colorterm == "truecolor" || colorterm == "24bit" {
+        return ColorDepth::TrueColor;
+    }
+    let term = env::var("TERM").unwrap_or_default().to_lowercase();
+    if term.contains("truecolor") || term.contains("24bit") || term.contains("direct") {
+        ColorDepth::TrueColor
+    }else if term.contains("256color") {
+        ColorDepth::Ansi256
+    }else{
+        ColorDepth::Ansi16
+    }
+}
+
+pub fn parse_color_depth(value: &str) -> Result<Option<ColorDepth>, String> {
+    match value {
+        "auto" => Ok(None),
+        "16" => Ok(Some(ColorDepth::Ansi16)),
+        "256" => Ok(Some(ColorDepth::Ansi256)),
+        "truecolor" | "24bit" => Ok(Some(ColorDepth::TrueColor)),
+        _ => Err(format!("invalid color depth '{}' (expected auto, 16, 256 or truecolor)", value)),
+    }
+}
+
+fn parse_color(value: &str) -> Result<Color, String> {
+    if value == "default" {
+        return Ok(Color::Default);
+    }
+    if let Some(pos) = COLOR_NAMES.iter().position(|n| *n == value) {
+        return Ok(Color::Ansi(pos as u8));
+    }
+    if let Some(name) = value.strip_prefix("bright_") {
+        if let Some(pos) = COLOR_NAMES.iter().position(|n| *n == name) {
+            return Ok(Color::Ansi(pos as u8 + 8));
+        }
+    }
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+            return Ok(Color::Rgb(channel(0), channel(2), channel(4)));
+        }
+    }
+    if let Some(index) = value.strip_prefix("palette:") {
+        if let Ok(n) = index.parse::<u8>() {
+            return Ok(Color::Indexed(n));
+        }
+    }
+    match value.parse::<u8>() {
+        Ok(n) if (30..=37).contains(&n) => Ok(Color::Ansi(n - 30)),
+        Ok(n) if (90..=97).contains(&n) => Ok(Color::Ansi(n - 90 + 8)),
+        _ => Err(format!(
+            "invalid color '{}' (expected a name, #rrggbb, palette:0-255 or an ANSI code 30-37/90-97)",
+            value)),
+    }
+}
+
+fn color_escape(color: Color, depth: ColorDepth, background: bool) -> String {
+    let base: u8 = if background { 40 } else { 30 };
+    let extended: u8 = if background { 48 } else { 38 };
+    let color = match (color, depth) {
+        (Color::Rgb(r, g, b), ColorDepth::Ansi256) => Color::Indexed(rgb_to_256(r, g, b)),
+        (Color::Rgb(r, g, b), ColorDepth::Ansi16) => Color::Ansi(rgb_to_16(r, g, b)),
+        (Color::Indexed(n), ColorDepth::Ansi16) if n < 16 => Color::Ansi(n),
+        (Color::Indexed(n), ColorDepth::Ansi16) => {
+            let (r, g, b) = index_to_rgb(n);
+            Color::Ansi(rgb_to_16(r, g, b))
+        },
+        (c, _) => c,
+    };
+    match color {
+        Color::Default => String::new(),
+        Color::Ansi(n) if n < 8 => format!(";{}", base + n),
+        Color::Ansi(n) => format!(";{}", base + 60 + n - 8),
+        Color::Indexed(n) => format!(";{};5;{}", extended, n),
+        Color::Rgb(r, g, b) => format!(";{};2;{};{};{}", extended, r, g, b),
+    }
+}
+
+fn index_to_rgb(n: u8) -> (u8, u8, u8) {
+    if n < 16 {
+        ANSI_RGB[n as usize]
+    }else if n < 232 {
+        let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+        let n = n - 16;
+        (level(n / 36), level((n / 6) % 6), level(n % 6))
+    }else{
+        let v = 8 + (n - 232) * 10;
+        (v, v, v)
+    }
+}
+
+fn rgb_to_256(r: u8, g: u8, b: u8) -> u8 {
+    let cube = |v: u8| if v < 48 { 0 } else if v < 115 { 1 } else { (v - 35) / 40 };
+    let (cr, cg, cb) = (cube(r), cube(g), cube(b));
+    let cube_index = 16 + 36 * cr + 6 * cg + cb;
+    let avg = (r as u16 + g as u16 + b as u16) / 3;
+    let gray_index = if avg > 238 { 255 } else if avg < 8 { 232 } else { 232 + ((avg - 8) / 10) as u8 };
+    if distance(index_to_rgb(gray_index), (r, g, b)) < distance(index_to_rgb(cube_index), (r, g, b)) {
+        gray_index
+    }else{
+        cube_index
+    }
+}
+
+fn rgb_to_16(r: u8, g: u8, b: u8) -> u8 {
+    let mut best = 0;
+    for (idx, rgb) in ANSI_RGB.iter().enumerate() {
+        if distance(*rgb, (r, g, b)) < distance(ANSI_RGB[best], (r, g, b)) {
+            best = idx;
+        }
+    }
+    best as u8
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
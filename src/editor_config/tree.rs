@@ -0,0 +1,99 @@
+use super::{Erow, Scope};
+
+// The parse tree of the structural parsers lives in the rows: each row lists
+// where nodes open and close on it, in order, and its state holds the scopes
+// still open at its end. An edit reparses rows from the edited one until a
+// row ends in the same scopes as before, and the rows below keep their part
+// of the tree.
+#[derive(Clone, PartialEq)]
+pub enum TreeEvent {
+    Open(Scope, usize),
+    Close(usize),
+}
+
+// A node with the row and column it opens at and, when it closes in the rows
+// parsed so far, the ones it closes at.
+pub struct TreeNode {
+    pub scope: Scope,
+    pub start: (usize, usize),
+    pub end: Option<(usize, usize)>,
+}
+
+fn depth_before(rows: &[Erow], row: usize) -> usize {
+    if row == 0 { 0 } else { rows[row - 1].hl_state.scopes.len() }
+}
+
+// The nodes holding column `col` of `row`, outermost first.
+pub fn tree_path(rows: &[Erow], row: usize, col: usize) -> Vec<TreeNode> {
+    let open: &[Scope] = if row == 0 { &[] } else { &rows[row - 1].hl_state.scopes };
+    let mut path: Vec<TreeNode> = open.iter()
+        .map(|scope| TreeNode { scope: scope.clone(), start: (0, 0), end: None })
+        .collect();
+    // The nodes open at the start of the row opened on the nearest rows above
+    // that end with a node of their depth open.
+    let mut missing: Vec<bool> = vec![true; path.len()];
+    let mut left = path.len();
+    let mut r = row;
+    while left > 0 && r > 0 {
+        r -= 1;
+        let mut depth = depth_before(rows, r);
+        let mut opened: Vec<(usize, usize)> = Vec::new();
+        for event in &rows[r].tree {
+            match event {
+                TreeEvent::Open(_, c) => {
+                    opened.push((depth, *c));
+                    depth += 1;
+                },
+                TreeEvent::Close(_) => {
+                    depth = depth.saturating_sub(1);
+                    opened.retain(|(d, _)| *d < depth);
+                },
+            }
+        }
+        for (d, c) in opened {
+            if d < missing.len() && missing[d] {
+                missing[d] = false;
+                left -= 1;
+                path[d].start = (r, c);
+            }
+        }
+    }
+    let events = match rows.get(row) {
+        Some(erow) => &erow.tree[..],
+        None => return path,
+    };
+    let mut rest = events.len();
+    for (idx, event) in events.iter().enumerate() {
+        match event {
+            TreeEvent::Open(scope, c) if *c <= col => {
+                path.push(TreeNode { scope: scope.clone(), start: (row, *c), end: None });
+            },
+            TreeEvent::Close(c) if *c <= col => {
+                path.pop();
+            },
+            _ => {
+                rest = idx;
+                break;
+            },
+        }
+    }
+    // Each node ends where the depth first drops below it.
+    let mut depth = path.len();
+    let later = rows.iter().enumerate().skip(row + 1).flat_map(|(r, erow)| erow.tree.iter().map(move |e| (r, e)));
+    for (r, event) in events[rest..].iter().map(|e| (row, e)).chain(later) {
+        match event {
+            TreeEvent::Open(..) => depth += 1,
+            TreeEvent::Close(c) => {
+                depth = depth.saturating_sub(1);
+                match path.get_mut(depth) {
+                    Some(node) if node.end.is_none() => node.end = Some((r, *c)),
+                    _ => (),
+                }
+                if depth == 0 {
+                    break;
+                }
+            },
+        }
+    }
+    path
+}
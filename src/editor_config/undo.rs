@@ -0,0 +1,214 @@
+use super::Erow;
+
+// Steps kept per buffer, and the bytes of rows they may hold together. The
+// latest step is kept whatever its size.
+const UNDO_STEPS: usize = 1000;
+const UNDO_BYTES: usize = 16 << 20;
+
+// The `rows` an edit replaced with the `count` rows at `at`, and the cursor
+// before it.
+#[derive(Clone)]
+pub struct UndoStep {
+    pub at: usize,
+    pub count: usize,
+    pub rows: Vec<Vec<u8>>,
+    pub cy: u32,
+    pub cx: u16,
+}
+
+impl UndoStep {
+    fn bytes(&self) -> usize {
+        self.rows.iter().map(|row| row.len() + 1).sum()
+    }
+}
+
+// The journal keeps the rows as they were after the last step. An edit is
+// recorded by comparing the buffer with them from the first row changed
+// since, so a step only holds the rows it changed.
+pub struct UndoJournal {
+    undo: Vec<UndoStep>,
+    redo: Vec<UndoStep>,
+    bytes: usize,
+    rows: Vec<Vec<u8>>,
+    changed_from: Option<usize>,
+    cursor: (u32, u16),
+}
+
+fn row_text(row: &Erow) -> &[u8] {
+    &row.chars[..row.size as usize]
+}
+
+impl UndoJournal {
+    pub fn new() -> UndoJournal {
+        UndoJournal { undo: Vec::new(), redo: Vec::new(), bytes: 0, rows: Vec::new(), changed_from: None, cursor: (0, 0) }
+    }
+
+    // Starts over from rows that were loaded rather than edited.
+    pub fn reset(&mut self, rows: &[Erow]) {
+        *self = UndoJournal::new();
+        self.rows = rows.iter().map(|row| row_text(row).to_vec()).collect();
+    }
+
+    // The cursor to go back to when the edits from now on are undone.
+    pub fn begin(&mut self, cy: u32, cx: u16) {
+        self.cursor = (cy, cx);
+    }
+
+    pub fn changed(&mut self, row: usize) {
+        self.changed_from = Some(self.changed_from.map_or(row, |from| from.min(row)));
+    }
+
+    // Records the rows changed since the last step as a new step; a new edit
+    // drops the steps that were undone.
+    pub fn record(&mut self, rows: &[Erow]) {
+        let from = match self.changed_from.take() {
+            Some(from) => from.min(self.rows.len()).min(rows.len()),
+            None => return,
+        };
+        let old = &self.rows[from..];
+        let new = &rows[from..];
+        let prefix = old.iter().zip(new.iter()).take_while(|(a, b)| &a[..] == row_text(b)).count();
+        let suffix = old.iter().rev().zip(new.iter().rev())
+            .take_while(|(a, b)| &a[..] == row_text(b)).count()
+            .min(old.len().min(new.len()) - prefix);
+        let at = from + prefix;
+        let end = self.rows.len() - suffix;
+        let count = rows.len() - suffix - at;
+        if at == end && count == 0 {
+            return;
+        }
+        let replaced = self.rows.splice(at..end, rows[at..at + count].iter().map(|row| row_text(row).to_vec())).collect();
+        let (cy, cx) = self.cursor;
+        self.push(UndoStep { at, count, rows: replaced, cy, cx });
+        self.redo.clear();
+    }
+
+    fn push(&mut self, step: UndoStep) {
+        self.bytes += step.bytes();
+        self.undo.push(step);
+        let mut drop = 0;
+        while self.undo.len() - drop > 1 && (self.undo.len() - drop > UNDO_STEPS || self.bytes > UNDO_BYTES) {
+            self.bytes -= self.undo[drop].bytes();
+            drop += 1;
+        }
+        self.undo.drain(..drop);
+    }
+
+    // The step to apply to go back, keeping its reverse with the cursor at
+    // (`cy`, `cx`) for redo. Edits not recorded yet must be recorded first.
+    pub fn undo(&mut self, cy: u32, cx: u16) -> Option<UndoStep> {
+        let step = self.undo.pop()?;
+        self.bytes -= step.bytes();
+        let reverse = self.apply(&step, cy, cx);
+        self.redo.push(reverse);
+        Some(step)
+    }
+
+    pub fn redo(&mut self, cy: u32, cx: u16) -> Option<UndoStep> {
+        let step = self.redo.pop()?;
+        let reverse = self.apply(&step, cy, cx);
+        self.push(reverse);
+        Some(step)
+    }
+
+    fn apply(&mut self, step: &UndoStep, cy: u32, cx: u16) -> UndoStep {
+        let replaced = self.rows.splice(step.at..step.at + step.count, step.rows.iter().cloned()).collect();
+        UndoStep { at: step.at, count: step.rows.len(), rows: replaced, cy, cx }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::RowState;
+
+    fn rows(text: &[&str]) -> Vec<Erow> {
+        text.iter().map(|row| {
+            let mut chars = row.as_bytes().to_vec();
+            chars.push(b'\0');
+            Erow {
+                size: row.len() as u16,
+                chars,
+                _rsize: 0,
+                render: Vec::new(),
+                hl: Vec::new(),
+                hl_state: RowState::new(),
+                hl_stale: true,
+                tree: Vec::new(),
+                symbol: None,
+            }
+        }).collect()
+    }
+
+    // Applies a step to rows the way the editor does.
+    fn apply(text: &mut Vec<String>, step: &UndoStep) {
+        let rows = step.rows.iter().map(|row| String::from_utf8_lossy(row).into_owned());
+        text.splice(step.at..step.at + step.count, rows);
+    }
+
+    #[test]
+    fn keeps_only_the_changed_rows() {
+        let mut journal = UndoJournal::new();
+        journal.reset(&rows(&["a", "b", "c", "d"]));
+        journal.begin(1, 1);
+        journal.changed(0);
+        journal.record(&rows(&["a", "x", "y", "z", "d"]));
+        let step = journal.undo(3, 0).unwrap();
+        assert_eq!((step.at, step.count, step.cy, step.cx), (1, 3, 1, 1));
+        assert_eq!(step.rows, vec![b"b".to_vec(), b"c".to_vec()]);
+        let mut text: Vec<String> = ["a", "x", "y", "z", "d"].iter().map(|s| s.to_string()).collect();
+        apply(&mut text, &step);
+        assert_eq!(text, vec!["a", "b", "c", "d"]);
+        let step = journal.redo(0, 0).unwrap();
+        assert_eq!((step.at, step.count, step.cy), (1, 2, 3));
+        apply(&mut text, &step);
+        assert_eq!(text, vec!["a", "x", "y", "z", "d"]);
+        assert!(journal.redo(0, 0).is_none());
+    }
+
+    #[test]
+    fn skips_keys_that_change_nothing() {
+        let mut journal = UndoJournal::new();
+        journal.reset(&rows(&["a", "b"]));
+        journal.changed(0);
+        journal.record(&rows(&["a", "b"]));
+        journal.record(&rows(&["a", "c"]));
+        assert!(journal.undo(0, 0).is_none());
+    }
+
+    #[test]
+    fn new_edits_drop_the_undone_ones() {
+        let mut journal = UndoJournal::new();
+        journal.reset(&rows(&["a"]));
+        journal.changed(0);
+        journal.record(&rows(&["ab"]));
+        journal.undo(0, 2).unwrap();
+        journal.changed(0);
+        journal.record(&rows(&["ac"]));
+        assert!(journal.redo(0, 0).is_none());
+        assert_eq!(journal.undo(0, 0).unwrap().rows, vec![b"a".to_vec()]);
+    }
+
+    #[test]
+    fn drops_the_oldest_steps_past_the_limits() {
+        let mut journal = UndoJournal::new();
+        let line = "x".repeat(1000);
+        let big: Vec<&str> = vec![line.as_str(); UNDO_BYTES / 2 / line.len() + 1];
+        journal.reset(&rows(&[]));
+        for _ in 0..3 {
+            journal.changed(0);
+            journal.record(&rows(&big));
+            journal.changed(0);
+            journal.record(&rows(&[]));
+        }
+        assert!(journal.bytes <= UNDO_BYTES);
+        assert_eq!(journal.undo.len(), 2);
+        assert_eq!(journal.undo[1].rows.len(), big.len());
+        journal.reset(&rows(&[""]));
+        for idx in 0..UNDO_STEPS + 10 {
+            journal.changed(0);
+            journal.record(&rows(&[&idx.to_string()]));
+        }
+        assert_eq!(journal.undo.len(), UNDO_STEPS);
+    }
+}
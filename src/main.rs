@@ -11,6 +11,7 @@ use terminal_size::{terminal_size};
 pub enum EditorKey{
     Arrow(Arrow),  //comment test2
     Function(Function),
+    Alt(u8),
     Else(u8),
 }
 
@@ -28,6 +29,7 @@ pub enum Function {
     End,
     Delete,
     Backspace,
+    BackTab,
 }
 
 struct AppendBuffer {
@@ -66,6 +68,15 @@ fn get_window_size() -> Option<(terminal_size::Width, terminal_size::Height)> {
     size
 }
 
+// How long to wait for a key before looking for background work again.
+const RILO_POLL_MS: i32 = 50;
+
+// Whether a key can be read without waiting longer than `timeout` milliseconds.
+fn editor_key_ready(timeout: i32) -> bool {
+    let mut fds = libc::pollfd { fd: stdin().as_raw_fd(), events: libc::POLLIN, revents: 0 };
+    unsafe { libc::poll(&mut fds, 1, timeout) > 0 }
+}
+
 fn editor_read_key() -> EditorKey {
     let mut c = [0u8;1];
     c[0] = b'\0';
@@ -83,6 +94,9 @@ fn editor_read_key() -> EditorKey {
         }else{
             return EditorKey::Else(b'\x1b')
         };
+        if seq[0][0] != b'[' && seq[0][0] != b'O' && seq[0][0] != b'0' {
+            return EditorKey::Alt(seq[0][0])
+        }
         if let Ok(1) =  stdin().read(&mut seq[1]) {
         }else{
             return EditorKey::Else(b'\x1b')
@@ -111,6 +125,7 @@ fn editor_read_key() -> EditorKey {
                     b'D' => return EditorKey::Arrow(Arrow::Left),
                     b'H' => return EditorKey::Function(Function::Home),
                     b'F' => return EditorKey::Function(Function::End),
+                    b'Z' => return EditorKey::Function(Function::BackTab),
                     _ => return EditorKey::Else(b'\x1b'),
                 };
             }
@@ -133,9 +148,10 @@ fn main() {
     let mut ec: EditorConfig = EditorConfig::new();
     let argc: usize = env::args().len();
     let args: Vec<String> = env::args().collect();
+    let mut error = None;
     if argc == 2 {
         let filename = &args[1];
-        ec.editor_open(&filename);
+        error = ec.editor_open(&filename).err();
     }else{
         if argc > 2 {
             disable_raw_mode(ec.termios);
@@ -143,11 +159,22 @@ fn main() {
         }
     }
 
-    ec.editor_set_status_message(
-        String::from("HELP: Ctrl-s = save | Ctrl-q = quit | Ctrl-f = find"));
+    ec.editor_show_startup_message();
+    if let Some(e) = error {
+        ec.editor_set_status_message(e);
+    }
 
     loop {
         ec.editor_refresh_screen();
+        loop {
+            let timeout = if ec.editor_idle_pending() { 0 } else { RILO_POLL_MS };
+            if editor_key_ready(timeout) {
+                break;
+            }
+            if ec.editor_idle() {
+                ec.editor_refresh_screen();
+            }
+        }
         match ec.editor_process_keypress() {
             Ok(0) => (),
             Ok(1) => break,